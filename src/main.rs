@@ -12,12 +12,23 @@ use poise::serenity_prelude as serenity;
 use serenity::small_fixed_array::FixedString;
 
 use tts_core::{
-    analytics, create_db_handler, database,
+    analytics,
+    announcements::AnnouncementLimiter,
+    autojoin, common, create_db_handler, database,
+    coalesce::InFlight,
+    history::History,
+    inactivity::VoiceTimers,
+    limiter::SynthesisLimiter,
+    transcript::Transcripts,
+    usage::UsageCache,
+    watchdog::Watchdog,
+    word_filter::WordFilter,
     structs::{Data, RegexCache, Result},
 };
 use tts_events::EventHandler;
 use tts_tasks::{logging::Layer, Looper as _};
 
+mod shutdown;
 mod startup;
 
 use startup::*;
@@ -45,6 +56,12 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
 
     println!("Initialising Http client");
     let reqwest = reqwest::Client::new();
+    let openai_api_base = config.main.openai_api_base.as_ref();
+    let openai = config
+        .main
+        .openai_api_key
+        .as_deref()
+        .map(|api_key| common::openai_client(api_key, openai_api_base));
     let auth_key = config.main.tts_service_auth_key.as_deref();
 
     let token = config.main.token.clone();
@@ -68,6 +85,8 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
         guild_voice_db,
         nickname_db,
         user_opt_out_db,
+        role_voice_db,
+        channel_voice_db,
         gtts_voices,
         espeak_voices,
         gcloud_voices,
@@ -82,6 +101,8 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
         create_db_handler!(pool.clone(), "guild_voice", "guild_id", "mode"),
         create_db_handler!(pool.clone(), "nicknames", "guild_id", "user_id"),
         create_db_handler!(pool.clone(), "user_opt_out", "user_id", "guild_id"),
+        create_db_handler!(pool.clone(), "role_voice", "guild_id", "role_id"),
+        create_db_handler!(pool.clone(), "channel_voice", "guild_id", "channel_id"),
         fetch_voices_safe_gtts(&reqwest, tts_service(), auth_key),
         fetch_voices_safe_espeak(&reqwest, tts_service(), auth_key),
         fetch_voices_safe_gcloud(&reqwest, tts_service(), auth_key),
@@ -101,7 +122,7 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
     }
 
     println!("Setting up webhook logging");
-    tts_tasks::logging::WebhookLogger::init(
+    let logger = tts_tasks::logging::WebhookLogger::init(
         console_layer,
         http.clone(),
         webhooks.logs.clone(),
@@ -121,13 +142,33 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
         bot_list_tokens: Mutex::new(config.bot_list_tokens),
 
         fully_started: AtomicBool::new(false),
+        shutting_down: AtomicBool::new(false),
         join_vc_tokens: dashmap::DashMap::new(),
+        synthesis_limiter: SynthesisLimiter::new(&analytics, &config.main),
+        in_flight: InFlight::default(),
+        transcripts: Transcripts::default(),
+        history: History::default(),
+        voice_timers: VoiceTimers::default(),
+        announcement_limiter: AnnouncementLimiter::default(),
+        watchdog: Watchdog::default(),
         songbird: songbird::Songbird::serenity(),
         last_to_xsaid_tracker: dashmap::DashMap::new(),
         update_startup_lock: tokio::sync::Mutex::new(()),
         entitlement_cache: mini_moka::sync::Cache::builder()
             .time_to_live(Duration::from_secs(60 * 60))
             .build(),
+        voice_preview_cache: mini_moka::sync::Cache::builder()
+            .time_to_live(Duration::from_secs(60 * 60 * 24))
+            .max_capacity(256)
+            .build(),
+        quota_notices: mini_moka::sync::Cache::builder()
+            .time_to_live(Duration::from_secs(60 * 60))
+            .build(),
+        usage_cache: UsageCache::default(),
+        word_filter: WordFilter::default(),
+        join_on_enter_cooldowns: mini_moka::sync::Cache::builder()
+            .time_to_live(autojoin::JOIN_ON_ENTER_COOLDOWN)
+            .build(),
 
         gtts_voices,
         espeak_voices,
@@ -140,8 +181,10 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
 
         config: config.main,
         premium_config: config.premium,
+        stt_config: config.stt,
         website_info: Mutex::new(config.website_info),
         reqwest,
+        openai,
         premium_avatar_url: FixedString::from_string_trunc(
             premium_user.as_ref().map(|u| u.face()).unwrap_or_else(|_| "".to_string())
         ),
@@ -156,6 +199,8 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
         user_voice_db,
         guild_voice_db,
         user_opt_out_db,
+        role_voice_db,
+        channel_voice_db,
     });
 
     let framework_options = poise::FrameworkOptions {
@@ -180,11 +225,11 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
         ..poise::FrameworkOptions::default()
     };
 
-    let mut client = serenity::ClientBuilder::new_with_http(token, http, tts_events::get_intents())
+    let mut client = serenity::ClientBuilder::new_with_http(token, http.clone(), tts_events::get_intents())
         .voice_manager::<songbird::Songbird>(data.songbird.clone())
         .framework(poise::Framework::new(framework_options))
         .event_handler::<EventHandler>(EventHandler)
-        .data(data as _)
+        .data(data.clone() as _)
         .await?;
 
     let shutdown_trigger = client.shard_manager.get_shutdown_trigger();
@@ -192,7 +237,7 @@ async fn main_(console_layer: impl Layer, start_time: std::time::SystemTime) ->
         wait_until_shutdown().await;
 
         tracing::warn!("Recieved control C and shutting down.");
-        shutdown_trigger();
+        shutdown::run(&data, &logger, shutdown_trigger).await;
     });
 
     client
@@ -0,0 +1,159 @@
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use poise::serenity_prelude as serenity;
+
+use tts_core::{
+    audio::encode::AudioFormat,
+    common::{synthesize, Synthesis, SynthesisRequest},
+    sessions,
+    structs::{Data, OpenAIModel, Result},
+    usage,
+};
+use tts_tasks::{logging::WebhookLogger, Looper as _};
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// How long leaving the calls and flushing buffers may take once the grace period is over.
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const RESTART_NOTICE: &str = "I am restarting, I'll be back soon!";
+
+type CallLock = Arc<tokio::sync::Mutex<songbird::Call>>;
+
+fn active_calls(data: &Data) -> Vec<(serenity::GuildId, CallLock)> {
+    data.songbird
+        .iter()
+        .map(|(guild_id, call)| (serenity::GuildId::new(guild_id.0.get()), call))
+        .collect()
+}
+
+/// Queues a short spoken notice in every call, after the messages already queued, so
+/// members know why the bot is about to leave.
+///
+/// The notice is read in the free mode, so it is the same audio for every call.
+async fn queue_restart_notice(data: &Data, calls: &[(serenity::GuildId, CallLock)]) -> Result<()> {
+    let mode = usage::free_mode(data);
+    let request = SynthesisRequest {
+        content: RESTART_NOTICE,
+        voice: mode.default_voice(),
+        mode,
+        speaking_rate: mode
+            .speaking_rate_info()
+            .map(|info| info.default)
+            .unwrap_or("1.0"),
+        openai_model: OpenAIModel::default(),
+        instruction: None,
+        max_length: "10",
+        translation_lang: None,
+        format: AudioFormat::OggOpus,
+    };
+
+    let Synthesis::Done(Some(audio)) = synthesize(data, request, None, false).await? else {
+        return Ok(());
+    };
+
+    for (_, call) in calls {
+        let track = songbird::tracks::Track::new(audio.bytes.clone().into());
+        call.lock().await.enqueue(track).await;
+    }
+
+    Ok(())
+}
+
+/// Waits for the messages being read out to finish, until `deadline`.
+async fn drain_queues(calls: &[(serenity::GuildId, CallLock)], deadline: Instant) {
+    while Instant::now() < deadline {
+        let mut all_empty = true;
+        for (_, call) in calls {
+            if !call.lock().await.queue().is_empty() {
+                all_empty = false;
+                break;
+            }
+        }
+
+        if all_empty {
+            return;
+        }
+
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    tracing::warn!("Shutdown grace period ended with messages still queued");
+}
+
+/// Saves the voice session in `guild_id`, then leaves the call.
+async fn leave_call(data: &Data, guild_id: serenity::GuildId, call: &CallLock) -> Result<()> {
+    let channel_id = {
+        let mut call = call.lock().await;
+        call.queue().stop();
+        call.current_channel()
+    };
+
+    if let Some(channel_id) = channel_id {
+        let channel_id = serenity::ChannelId::new(channel_id.get());
+        sessions::save(data, guild_id, channel_id, None).await?;
+    }
+
+    data.songbird.remove(guild_id).await?;
+    data.watchdog.forget(guild_id);
+    Ok(())
+}
+
+/// Shuts down gracefully, so restarts are as unnoticeable as possible.
+///
+/// New messages stop being read out, queued messages and a spoken restart notice are
+/// given time to finish, voice sessions are saved to be restored on startup, and
+/// buffered analytics and logs are written out before the shards are told to disconnect.
+///
+/// This takes at most the grace period plus [`CLEANUP_TIMEOUT`].
+pub async fn run(data: &Data, logger: &Arc<WebhookLogger>, shutdown_trigger: impl FnOnce()) {
+    data.shutting_down.store(true, Ordering::SeqCst);
+
+    let grace_period = data
+        .config
+        .shutdown_grace_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_GRACE_PERIOD);
+
+    let calls = active_calls(data);
+    tracing::warn!(
+        "Shutting down, waiting up to {}s for {} calls to finish",
+        grace_period.as_secs(),
+        calls.len()
+    );
+
+    let deadline = Instant::now() + grace_period;
+    let notice = tokio::time::timeout(grace_period, queue_restart_notice(data, &calls));
+    match notice.await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => tracing::error!("Failed to queue restart notice: {err:?}"),
+        Err(_) => tracing::warn!("Timed out generating the restart notice"),
+    }
+
+    drain_queues(&calls, deadline).await;
+
+    let cleanup = async {
+        for (guild_id, call) in &calls {
+            if let Err(err) = leave_call(data, *guild_id, call).await {
+                tracing::error!("Failed to leave call in {guild_id} while shutting down: {err:?}");
+            }
+        }
+
+        if let Err(err) = data.analytics.loop_func().await {
+            tracing::error!("Failed to flush analytics while shutting down: {err:?}");
+        }
+
+        logger.flush().await;
+    };
+
+    if tokio::time::timeout(CLEANUP_TIMEOUT, cleanup)
+        .await
+        .is_err()
+    {
+        tracing::error!("Timed out leaving calls and flushing buffers while shutting down");
+    }
+
+    shutdown_trigger();
+}
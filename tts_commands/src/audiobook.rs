@@ -0,0 +1,423 @@
+use std::{
+    borrow::Cow,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use poise::{serenity_prelude as serenity, CreateReply};
+
+use tts_core::{
+    audio::{
+        encode::{self, AudioFormat, OggOpusStream},
+        DecodedAudio,
+    },
+    audiobook::{refund_quota, reserve_quota, split_chapters, Chapter},
+    common::{synthesize, Synthesis, SynthesisRequest, SynthesizedAudio},
+    structs::{ApplicationContext, CommandResult, Context, Data, Result},
+    traits::PoiseContextExt as _,
+    usage,
+};
+
+use crate::other::{resolve_settings, ResolvedSettings, TTSOverrides};
+
+/// The largest text file which will be downloaded, in bytes.
+const MAX_FILE_SIZE: u32 = 512 * 1024;
+/// The largest chunk of text sent to the TTS backend at once, in characters.
+const MAX_CHUNK_LEN: usize = 1000;
+/// How many chunks are synthesized at the same time.
+const CONCURRENCY: usize = 4;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+const CHUNK_GAP_MS: u32 = 150;
+const CHAPTER_GAP_MS: u32 = 750;
+/// How many times to try generating a chunk before skipping it.
+const MAX_CHUNK_ATTEMPTS: u32 = 3;
+/// How long to wait before retrying a chunk, multiplied by the attempt number.
+const CHUNK_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// The upload limit for servers without boosts.
+const MAX_UPLOAD_SIZE: usize = 10 * 1024 * 1024;
+
+fn is_text_file(attachment: &serenity::Attachment) -> bool {
+    let filename = attachment.filename.to_lowercase();
+    filename.ends_with(".txt") || filename.ends_with(".md")
+}
+
+fn progress_message(done: usize, total: usize) -> String {
+    format!("Generating your audiobook, {done}/{total} parts done...")
+}
+
+/// Synthesizes one chunk, retrying a few times before giving up on it.
+async fn synthesize_chunk(
+    data: &Data,
+    settings: &ResolvedSettings,
+    content: &str,
+) -> Option<SynthesizedAudio> {
+    let max_length = settings.max_seconds.to_string();
+    for attempt in 1..=MAX_CHUNK_ATTEMPTS {
+        if attempt != 1 {
+            tokio::time::sleep(CHUNK_RETRY_DELAY * attempt).await;
+        }
+
+        let request = SynthesisRequest {
+            content,
+            voice: &settings.voice,
+            mode: settings.mode,
+            speaking_rate: &settings.speaking_rate,
+            openai_model: settings.openai_model,
+            instruction: settings.instruction.as_deref(),
+            max_length: &max_length,
+            translation_lang: settings.translation_lang.as_deref(),
+            format: AudioFormat::Mp3,
+        };
+
+        match synthesize(data, request, settings.guild_id, settings.is_premium).await {
+            Ok(Synthesis::Done(Some(audio))) => return Some(audio),
+            Ok(Synthesis::Done(None) | Synthesis::Busy) => {}
+            Err(err) => tracing::warn!("Failed to generate audiobook chunk: {err:?}"),
+        }
+    }
+
+    None
+}
+
+/// Synthesizes every chunk with bounded parallelism, in order.
+///
+/// Chunks which fail to generate even after retrying are left as `None`.
+async fn synthesize_chunks(
+    ctx: Context<'_>,
+    progress: &poise::ReplyHandle<'_>,
+    settings: Arc<ResolvedSettings>,
+    chunks: Vec<String>,
+) -> Result<Vec<Option<SynthesizedAudio>>> {
+    let data = ctx.data();
+    let total = chunks.len();
+
+    type Task = (usize, Option<SynthesizedAudio>);
+    let spawn = |tasks: &mut tokio::task::JoinSet<Task>, (index, content): (usize, String)| {
+        let data: Arc<Data> = data.clone();
+        let settings = settings.clone();
+        tasks.spawn(async move { (index, synthesize_chunk(&data, &settings, &content).await) });
+    };
+
+    let mut pending = chunks.into_iter().enumerate();
+    let mut tasks = tokio::task::JoinSet::new();
+    for chunk in pending.by_ref().take(CONCURRENCY) {
+        spawn(&mut tasks, chunk);
+    }
+
+    let mut results: Vec<Option<SynthesizedAudio>> = (0..total).map(|_| None).collect();
+    let mut last_update = Instant::now();
+    let mut done = 0;
+
+    // Dropping the JoinSet on an early return aborts any chunks still generating.
+    while let Some(result) = tasks.join_next().await {
+        let (index, audio) = result?;
+        results[index] = audio;
+        done += 1;
+
+        if let Some(chunk) = pending.next() {
+            spawn(&mut tasks, chunk);
+        }
+
+        if last_update.elapsed() >= PROGRESS_INTERVAL {
+            let content = progress_message(done, total);
+            progress
+                .edit(ctx, CreateReply::default().content(content))
+                .await?;
+            last_update = Instant::now();
+        }
+    }
+
+    Ok(results)
+}
+
+struct Audiobook {
+    bytes: Vec<u8>,
+    format: AudioFormat,
+    /// How many chunks failed to generate, and how many characters they held.
+    skipped_chunks: usize,
+    skipped_characters: usize,
+}
+
+/// The audio of an audiobook, as it is joined together.
+enum Output {
+    /// Ogg, which is encoded as each chunk is added so the book is never held as PCM.
+    Ogg(OggOpusStream),
+    /// Uncompressed formats, which are limited to the upload size anyway.
+    Pcm(DecodedAudio, AudioFormat),
+}
+
+impl Output {
+    fn new(first_chunk: &DecodedAudio, format: AudioFormat) -> Result<Self> {
+        Ok(match format {
+            AudioFormat::Wav | AudioFormat::Flac => Self::Pcm(
+                DecodedAudio {
+                    samples: Vec::new(),
+                    sample_rate: first_chunk.sample_rate,
+                    channels: first_chunk.channels,
+                },
+                format,
+            ),
+            // Chapters can only be marked in Ogg, and MP3 cannot be encoded.
+            AudioFormat::OggOpus | AudioFormat::Mp3 => Self::Ogg(OggOpusStream::new(
+                first_chunk.channels.min(2),
+                first_chunk.sample_rate,
+            )?),
+        })
+    }
+
+    fn push(&mut self, audio: &DecodedAudio) -> Result<()> {
+        match self {
+            Self::Ogg(stream) => stream.push(audio),
+            Self::Pcm(output, _) => {
+                output.append(audio);
+                Ok(())
+            }
+        }
+    }
+
+    fn push_silence(&mut self, ms: u32) -> Result<()> {
+        match self {
+            Self::Ogg(stream) => stream.push_silence(ms),
+            Self::Pcm(output, _) => {
+                output.append_silence(ms);
+                Ok(())
+            }
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        match self {
+            Self::Ogg(stream) => stream.duration(),
+            Self::Pcm(output, _) => output.duration(),
+        }
+    }
+
+    /// Roughly how large the output file will be, in bytes.
+    fn len(&self) -> usize {
+        match self {
+            Self::Ogg(stream) => stream.encoded_len(),
+            Self::Pcm(output, _) => output.samples.len() * 2,
+        }
+    }
+
+    fn finish(self, markers: &[(String, Duration)]) -> Result<(Vec<u8>, AudioFormat)> {
+        match self {
+            Self::Ogg(stream) => {
+                let comments = encode::chapter_comments(markers);
+                Ok((stream.finish(&comments)?, AudioFormat::OggOpus))
+            }
+            Self::Pcm(output, format) => Ok((encode::encode(&output, format)?, format)),
+        }
+    }
+}
+
+/// Joins the chunks into one file, marking the start of each titled chapter.
+///
+/// Chunks are decoded one at a time, and `None` is returned as soon as the file is
+/// too large to upload.
+fn concatenate(
+    chapters: &[Chapter],
+    audio: Vec<Option<SynthesizedAudio>>,
+    format: AudioFormat,
+) -> Result<Option<(Vec<u8>, AudioFormat)>> {
+    let mut output: Option<Output> = None;
+    let mut markers = Vec::new();
+
+    let mut audio = audio.into_iter();
+    for chapter in chapters {
+        if let Some(output) = &mut output {
+            output.push_silence(CHAPTER_GAP_MS)?;
+        }
+
+        let start = output.as_ref().map_or(Duration::ZERO, Output::duration);
+        if let Some(title) = &chapter.title {
+            markers.push((title.clone(), start));
+        }
+
+        let chunks = audio.by_ref().take(chapter.chunks.len()).flatten();
+        for (i, chunk) in chunks.enumerate() {
+            let decoded = DecodedAudio::decode(&chunk.bytes, chunk.hint.as_ref())?;
+            let output = match &mut output {
+                Some(output) => output,
+                slot @ None => slot.insert(Output::new(&decoded, format)?),
+            };
+
+            if i != 0 {
+                output.push_silence(CHUNK_GAP_MS)?;
+            }
+
+            output.push(&decoded)?;
+            if output.len() > MAX_UPLOAD_SIZE {
+                return Ok(None);
+            }
+        }
+    }
+
+    let Some(output) = output else {
+        anyhow::bail!("No audio was generated");
+    };
+
+    output.finish(&markers).map(Some)
+}
+
+/// Reads out a text file and sends the result as a single audio file.
+pub async fn audiobook_(
+    ctx: Context<'_>,
+    author: &serenity::User,
+    file: &serenity::Attachment,
+    overrides: TTSOverrides,
+) -> CommandResult {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.send_error("Audiobooks can only be generated in a server")
+            .await?;
+        return Ok(());
+    };
+
+    if !is_text_file(file) {
+        ctx.send_error("Audiobooks can only be generated from `.txt` or `.md` files")
+            .await?;
+        return Ok(());
+    }
+
+    if file.size > MAX_FILE_SIZE {
+        let max_kb = MAX_FILE_SIZE / 1024;
+        ctx.send_error(format!("Text files cannot be larger than {max_kb}KB"))
+            .await?;
+        return Ok(());
+    }
+
+    let Some(settings) = resolve_settings(ctx, author, &overrides).await? else {
+        return Ok(());
+    };
+
+    ctx.defer_or_broadcast().await?;
+
+    let data = ctx.data();
+    let response = data.reqwest.get(file.url.as_str()).send().await?;
+    let Ok(text) = String::from_utf8(response.error_for_status()?.bytes().await?.to_vec()) else {
+        ctx.send_error("That file isn't valid text").await?;
+        return Ok(());
+    };
+
+    let chapters = split_chapters(&text, MAX_CHUNK_LEN);
+    let chunks: Vec<String> = chapters.iter().flat_map(|c| c.chunks.iter().cloned()).collect();
+    if chunks.is_empty() {
+        ctx.send_error("That file has no text to read out").await?;
+        return Ok(());
+    }
+
+    let characters =
+        i32::try_from(chunks.iter().map(|c| c.chars().count()).sum::<usize>()).unwrap_or(i32::MAX);
+    let quota = data.guilds_db.get(guild_id.into()).await?.audiobook_quota;
+    let guild_key = guild_id.get() as i64;
+    if !reserve_quota(&data.pool, guild_key, characters, quota as i32).await? {
+        let msg = format!(
+            "This would go over the server's daily audiobook quota of {quota} characters, try a shorter file or try again tomorrow!"
+        );
+        ctx.send_error(msg).await?;
+        return Ok(());
+    }
+
+    let mode = settings.mode;
+    let chunk_lens: Vec<usize> = chunks.iter().map(|c| c.chars().count()).collect();
+    let generated = async {
+        let progress = ctx.say(progress_message(0, chunks.len())).await?;
+        let audio = synthesize_chunks(ctx, &progress, Arc::new(settings), chunks).await?;
+        if audio.iter().all(Option::is_none) {
+            return Ok(Err("Failed to generate the audiobook, try again later"));
+        }
+
+        let (skipped_chunks, skipped_characters) = audio
+            .iter()
+            .zip(&chunk_lens)
+            .filter(|(audio, _)| audio.is_none())
+            .fold((0, 0), |(chunks, characters), (_, len)| {
+                (chunks + 1, characters + len)
+            });
+
+        let format = overrides.format.unwrap_or(AudioFormat::OggOpus);
+        let concatenated =
+            tokio::task::spawn_blocking(move || concatenate(&chapters, audio, format)).await??;
+
+        let Some((bytes, format)) = concatenated else {
+            return Ok(Err(
+                "The audiobook was too large to upload, try a shorter file",
+            ));
+        };
+
+        progress
+            .edit(ctx, CreateReply::default().content("Finished generating your audiobook!"))
+            .await?;
+
+        Ok::<_, anyhow::Error>(Ok(Audiobook {
+            bytes,
+            format,
+            skipped_chunks,
+            skipped_characters,
+        }))
+    };
+
+    let audiobook = match generated.await {
+        Ok(Ok(audiobook)) => audiobook,
+        Ok(Err(msg)) => {
+            refund_quota(&data.pool, guild_key, characters).await?;
+            ctx.send_error(msg).await?;
+            return Ok(());
+        }
+        Err(err) => {
+            refund_quota(&data.pool, guild_key, characters).await?;
+            return Err(err);
+        }
+    };
+
+    // Skipped parts were never generated, so shouldn't count towards the quota.
+    let skipped_characters = i32::try_from(audiobook.skipped_characters).unwrap_or(i32::MAX);
+    if skipped_characters != 0 {
+        refund_quota(&data.pool, guild_key, skipped_characters).await?;
+    }
+
+    let generated_characters = (characters - skipped_characters) as usize;
+    usage::record(&data, guild_id, author.id, mode, generated_characters).await?;
+
+    let name = file.filename.rsplit_once('.').map_or(file.filename.as_str(), |(name, _)| name);
+    let file_name = format!("{name}.{}", audiobook.format.extension());
+
+    let content = match audiobook.skipped_chunks {
+        0 => Cow::Borrowed("Here is your audiobook!"),
+        skipped => Cow::Owned(format!(
+            "Here is your audiobook! {skipped} parts could not be generated, so were skipped."
+        )),
+    };
+
+    let attachment = serenity::CreateAttachment::bytes(audiobook.bytes, file_name);
+    ctx.send(
+        CreateReply::default()
+            .content(content)
+            .attachment(attachment),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Reads out the text file attached to a message as an audiobook
+#[poise::command(
+    category = "Extra Commands",
+    hide_in_help,
+    guild_only,
+    context_menu_command = "Read file as audiobook",
+    required_bot_permissions = "SEND_MESSAGES | ATTACH_FILES"
+)]
+pub async fn tts_audiobook(
+    ctx: ApplicationContext<'_>,
+    message: serenity::Message,
+) -> CommandResult {
+    let ctx = Context::from(ctx);
+    let Some(file) = message.attachments.iter().find(|a| is_text_file(a)) else {
+        ctx.send_error("That message has no `.txt` or `.md` file attached")
+            .await?;
+        return Ok(());
+    };
+
+    audiobook_(ctx, ctx.author(), file, TTSOverrides::default()).await
+}
@@ -12,6 +12,7 @@ use tts_core::{
     traits::PoiseContextExt,
 };
 
+mod audiobook;
 mod help;
 mod main_;
 mod other;
@@ -22,10 +23,6 @@ mod settings;
 const REQUIRED_SETUP_PERMISSIONS: serenity::Permissions =
     serenity::Permissions::VIEW_CHANNEL.union(serenity::Permissions::SEND_MESSAGES);
 
-const REQUIRED_VC_PERMISSIONS: serenity::Permissions = serenity::Permissions::VIEW_CHANNEL
-    .union(serenity::Permissions::CONNECT)
-    .union(serenity::Permissions::SPEAK);
-
 #[must_use]
 pub fn commands() -> Vec<Command> {
     main_::commands()
@@ -1,4 +1,7 @@
-use std::sync::{atomic::Ordering, Arc};
+use std::{
+    fmt::Write as _,
+    sync::{atomic::Ordering, Arc},
+};
 
 use aformat::{aformat, ArrayString};
 
@@ -6,17 +9,16 @@ use poise::serenity_prelude::{self as serenity, builder::*, colours::branding::Y
 use songbird::error::JoinError;
 
 use tts_core::{
-    common::{push_permission_names, random_footer},
-    constants::RED,
+    common::{push_permission_names, random_footer, safe_truncate},
+    constants::{RED, REQUIRED_VC_PERMISSIONS},
     database_models::GuildRow,
+    history,
     opt_ext::OptionTryUnwrap as _,
-    require_guild,
+    require_guild, sessions,
     structs::{Command, CommandResult, Context, JoinVCToken, Result},
     traits::{PoiseContextExt, SongbirdManagerExt},
 };
 
-use crate::REQUIRED_VC_PERMISSIONS;
-
 /// Returns Some(GuildRow) on correct channel, otherwise None.
 async fn channel_check(
     ctx: &Context<'_>,
@@ -171,6 +173,7 @@ pub async fn join(ctx: Context<'_>) -> CommandResult {
             tracing::warn!("Channel {bot_channel_id} didn't exist in {guild_id} in `/join`");
             data.last_to_xsaid_tracker.remove(&guild_id);
             data.songbird.remove(guild_id).await?;
+            data.watchdog.forget(guild_id);
         }
     }
 
@@ -190,6 +193,13 @@ pub async fn join(ctx: Context<'_>) -> CommandResult {
             };
         }
 
+        let summoner = sessions::Summoner {
+            user_id: ctx.author().id,
+            text_channel_id: ctx.channel_id(),
+        };
+
+        sessions::save(&data, guild_id, author_vc, Some(summoner)).await?;
+
         match ctx {
             Context::Application(poise::ApplicationContext { interaction, .. }) => {
                 interaction.member.as_deref().try_unwrap()?.display_name()
@@ -269,6 +279,7 @@ pub async fn leave(ctx: Context<'_>) -> CommandResult {
         } else {
             data.last_to_xsaid_tracker.remove(&guild_id);
             data.songbird.remove(guild_id).await?;
+            data.watchdog.forget(guild_id);
 
             ctx.say("Left voice channel!").await?;
         }
@@ -312,6 +323,87 @@ pub async fn clear(ctx: Context<'_>) -> CommandResult {
     Ok(())
 }
 
-pub fn commands() -> [Command; 3] {
-    [join(), leave(), clear()]
+/// Shows the messages I have recently read out, with buttons to replay them
+#[poise::command(
+    aliases("recent"),
+    category = "Main Commands",
+    guild_only,
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS"
+)]
+pub async fn history(ctx: Context<'_>) -> CommandResult {
+    let guild_id = ctx.guild_id().unwrap();
+    let items = ctx.data().history.recent(guild_id);
+    if items.is_empty() {
+        ctx.say("I haven't read out any messages recently!").await?;
+        return Ok(());
+    }
+
+    let mut description = String::new();
+    let mut buttons = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let mut text = item.text.clone();
+        safe_truncate(&mut text, 150);
+
+        let position = i + 1;
+        let author = item.author;
+        let spoken_at = item.spoken_at.unix_timestamp();
+        writeln!(
+            description,
+            "`{position}.` <@{author}> <t:{spoken_at}:R>: {text}"
+        )?;
+
+        buttons.push(
+            CreateButton::new(item.replay_custom_id())
+                .label(format!("Replay {position}"))
+                .style(serenity::ButtonStyle::Secondary)
+                .disabled(item.audio.get().is_none()),
+        );
+    }
+
+    let components: Vec<_> = buttons.chunks(5).map(CreateActionRow::buttons).collect();
+
+    let embed = CreateEmbed::default()
+        .title("Recently read out messages")
+        .description(description)
+        .colour(ctx.neutral_colour().await);
+
+    let reply = poise::CreateReply::default()
+        .embed(embed)
+        .components(components)
+        .allowed_mentions(serenity::CreateAllowedMentions::new());
+
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Replays the last message I read out
+#[poise::command(
+    aliases("repeat", "again"),
+    category = "Main Commands",
+    guild_only,
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES"
+)]
+pub async fn replay(ctx: Context<'_>) -> CommandResult {
+    let guild_id = ctx.guild_id().unwrap();
+    let Some(item) = ctx.data().history.latest(guild_id) else {
+        ctx.say("I haven't read out any messages recently!").await?;
+        return Ok(());
+    };
+
+    let result = history::replay(ctx.serenity_context(), guild_id, ctx.author().id, &item).await?;
+    if let Err(failure) = result {
+        ctx.send_error(failure.message()).await?;
+    } else {
+        ctx.say("Replaying the last message!").await?;
+    }
+
+    Ok(())
+}
+
+pub fn commands() -> [Command; 5] {
+    [join(), leave(), clear(), history(), replay()]
 }
@@ -1,4 +1,6 @@
-use aformat::{aformat, astr};
+use std::borrow::Cow;
+
+use aformat::aformat;
 use anyhow::Error;
 use num_format::{Locale, ToFormattedString};
 
@@ -11,12 +13,24 @@ use poise::{
 
 use aformat::ToArrayString;
 use tts_core::{
-    common::{fetch_audio, fetch_openai_audio, prepare_url},
-    constants::OPTION_SEPERATORS,
+    audio::encode::{self, AudioFormat},
+    common::{
+        member_roles_by_position, synthesize, Synthesis, SynthesisRequest, SynthesizedAudio,
+    },
+    constants::{DEFAULT_TTS_FILE_LENGTH, OPTION_SEPERATORS},
     opt_ext::OptionTryUnwrap,
     require_guild,
-    structs::{ApplicationContext, Command, CommandResult, Context, IsPremium, TTSMode},
+    structs::{
+        ApplicationContext, Command, CommandResult, Context, IsPremium, OpenAIModel, Result,
+        SpeakingRateInfo, TTSMode, TTSModeChoice, VoiceScope,
+    },
     traits::PoiseContextExt as _,
+    usage,
+};
+
+use crate::{
+    audiobook,
+    settings::{can_change_mode, check_valid_voice, voice_autocomplete},
 };
 
 /// Shows how long TTS Bot has been online
@@ -38,6 +52,16 @@ pub async fn uptime(ctx: Context<'_>) -> CommandResult {
     Ok(())
 }
 
+/// Overrides for a single `/tts` invocation, falling back to the author's settings.
+#[derive(Default)]
+pub(crate) struct TTSOverrides {
+    pub voice: Option<FixedString<u8>>,
+    pub mode: Option<TTSMode>,
+    pub speaking_rate: Option<f32>,
+    pub instruction: Option<String>,
+    pub format: Option<AudioFormat>,
+}
+
 /// Generates TTS and sends it in the current text channel!
 #[poise::command(
     category = "Extra Commands",
@@ -45,14 +69,26 @@ pub async fn uptime(ctx: Context<'_>) -> CommandResult {
     slash_command,
     required_bot_permissions = "SEND_MESSAGES | ATTACH_FILES"
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn tts(
     ctx: Context<'_>,
     #[description = "The text to TTS"]
     #[rest]
-    message: FixedString<u16>,
+    message: Option<FixedString<u16>>,
+    #[description = "A .txt or .md file to read out as an audiobook, instead of text"]
+    file: Option<serenity::Attachment>,
+    #[description = "The voice to use, instead of your voice"]
+    #[autocomplete = "voice_autocomplete"]
+    voice: Option<FixedString<u8>>,
+    #[description = "The voice mode to use, instead of your mode"] mode: Option<TTSModeChoice>,
+    #[description = "The speaking rate to use, instead of your speaking rate"]
+    speaking_rate: Option<f32>,
+    #[description = "How the voice should speak, only for OpenAI (max 500 chars)"]
+    instruction: Option<String>,
+    #[description = "The format of the generated audio file"] format: Option<AudioFormat>,
 ) -> CommandResult {
     let is_unnecessary_command_invoke = async {
-        if !matches!(ctx, poise::Context::Prefix(_)) {
+        if !matches!(ctx, poise::Context::Prefix(_)) || file.is_some() {
             return Ok(false);
         }
 
@@ -89,97 +125,259 @@ pub async fn tts(
             .await?;
         Ok(())
     } else {
-        tts_(ctx, ctx.author(), &message).await
+        let overrides = TTSOverrides {
+            voice,
+            mode: mode.map(TTSMode::from),
+            speaking_rate,
+            instruction,
+            format,
+        };
+
+        match (message, file) {
+            (_, Some(file)) => audiobook::audiobook_(ctx, ctx.author(), &file, overrides).await,
+            (Some(message), None) => tts_(ctx, ctx.author(), &message, overrides).await,
+            (None, None) => {
+                ctx.send_error("You need to give some text or a file to read out")
+                    .await?;
+                Ok(())
+            }
+        }
     }
 }
 
-async fn tts_(ctx: Context<'_>, author: &serenity::User, message: &str) -> CommandResult {
-    let attachment = {
-        let data = ctx.data();
-        let http = ctx.http();
-        let guild_info = if let Some(guild_id) = ctx.guild_id() {
-            Some((guild_id, data.is_premium_simple(http, guild_id).await?))
-        } else {
-            None
-        };
+/// The settings to generate audio with, after applying any [`TTSOverrides`].
+pub(crate) struct ResolvedSettings {
+    pub voice: Cow<'static, str>,
+    pub mode: TTSMode,
+    pub speaking_rate: Cow<'static, str>,
+    pub instruction: Option<String>,
+    pub openai_model: OpenAIModel,
+    pub translation_lang: Option<String>,
+    /// The max length of generated audio in seconds.
+    pub max_seconds: u16,
+    pub guild_id: Option<serenity::GuildId>,
+    pub is_premium: bool,
+}
+
+/// Resolves the author's settings with `overrides` applied.
+///
+/// Returns `None` if an override was invalid, after telling the user why.
+pub(crate) async fn resolve_settings(
+    ctx: Context<'_>,
+    author: &serenity::User,
+    overrides: &TTSOverrides,
+) -> Result<Option<ResolvedSettings>> {
+    let data = ctx.data();
+    let http = ctx.http();
+    let guild_info = if let Some(guild_id) = ctx.guild_id() {
+        Some((guild_id, data.is_premium_simple(http, guild_id).await?))
+    } else {
+        None
+    };
+
+    let member_roles = match ctx.guild() {
+        Some(guild) => match guild.members.get(&author.id) {
+            Some(member) => member_roles_by_position(&guild, &member.roles),
+            None => Vec::new(),
+        },
+        None => Vec::new(),
+    };
+
+    let scope = VoiceScope {
+        roles: &member_roles,
+        channel_id: Some(ctx.channel_id()),
+    };
+
+    let mut settings = data
+        .parse_user_or_guild_with_premium(author.id, guild_info, scope)
+        .await?;
+
+    let is_premium = guild_info.is_some_and(|(_, is_premium)| is_premium);
+    if !can_change_mode(&ctx, overrides.mode, is_premium).await? {
+        return Ok(None);
+    }
 
-        let (voice, mode, openai_model, _) = data
-            .parse_user_or_guild_with_premium(author.id, guild_info)
+    if let Some((guild_id, is_premium)) = guild_info
+        && usage::is_metered(overrides.mode.unwrap_or(settings.mode))
+        && usage::quota_exceeded(&data, guild_id, author.id, is_premium).await?
+    {
+        if overrides.mode.is_some() {
+            ctx.send_error(
+                "This server has used up its character quota for the paid voice modes, check `/usage` for more information",
+            )
             .await?;
+            return Ok(None);
+        }
 
-        let guild_row;
-        let translation_lang = if let Some((guild_id, is_premium)) = guild_info {
-            guild_row = data.guilds_db.get(guild_id.into()).await?;
-            guild_row.target_lang(IsPremium::from(is_premium))
-        } else {
-            None
-        };
+        usage::downgrade(&data, &mut settings);
+    }
 
-        let author_name: String = author
-            .name
-            .chars()
-            .filter(|char| char.is_alphanumeric())
-            .collect();
-        let speaking_rate = data.speaking_rate(author.id, mode).await?;
-
-        let audio = match mode {
-            TTSMode::OpenAI => {
-                // Use OpenAI TTS API
-                let Some(openai_api_key) = &data.config.openai_api_key else {
-                    ctx.say("OpenAI API key not configured for OpenAI TTS mode").await?;
-                    return Ok(());
-                };
-
-                let speaking_rate_f32 = speaking_rate.parse::<f32>().unwrap_or(1.0);
-                match fetch_openai_audio(openai_api_key, message, &voice, speaking_rate_f32, openai_model, None).await? {
-                    Some(bytes) => bytes,
-                    None => {
-                        ctx.say("Failed to generate TTS audio with OpenAI").await?;
-                        return Ok(());
-                    }
-                }
-            }
-            _ => {
-                // Use traditional TTS service for other modes
-                let url = prepare_url(
-                    data.config.tts_service.clone(),
-                    message,
-                    &voice,
-                    mode,
-                    &speaking_rate,
-                    &u64::MAX.to_arraystring(),
-                    translation_lang,
-                );
-
-                let auth_key = data.config.tts_service_auth_key.as_deref();
-                fetch_audio(&data.reqwest, url, auth_key)
-                    .await?
-                    .try_unwrap()?
-                    .bytes()
-                    .await?
-                    .to_vec()
+    let mode = overrides.mode.unwrap_or(settings.mode);
+    let voice = match &overrides.voice {
+        Some(voice) if check_valid_voice(&data, voice, mode) => Cow::Owned(voice.to_string()),
+        Some(_) => {
+            let msg = format!(
+                "Invalid voice for the {mode} mode, do `/voices` to see the available voices"
+            );
+            ctx.send_error(msg).await?;
+            return Ok(None);
+        }
+        None if mode == settings.mode => settings.voice,
+        None => Cow::Borrowed(mode.default_voice()),
+    };
+
+    let speaking_rate = match overrides.speaking_rate {
+        Some(speaking_rate) => {
+            let Some(SpeakingRateInfo { min, max, .. }) = mode.speaking_rate_info() else {
+                let msg = format!("Cannot set the speaking rate for the {mode} mode");
+                ctx.send_error(msg).await?;
+                return Ok(None);
+            };
+
+            if !(min..=max).contains(&speaking_rate) {
+                let msg = format!("The speaking rate must be between {min} and {max}");
+                ctx.send_error(msg).await?;
+                return Ok(None);
             }
+
+            Cow::Owned(speaking_rate.to_string())
+        }
+        None if mode == settings.mode => settings.speaking_rate,
+        None => Cow::Borrowed(mode.speaking_rate_info().map_or("1.0", |info| info.default)),
+    };
+
+    let instruction = match overrides.instruction.clone() {
+        Some(_) if mode != TTSMode::OpenAI => {
+            ctx.send_error("Instructions can only be used with the OpenAI mode")
+                .await?;
+            return Ok(None);
+        }
+        Some(_) if !settings.openai_model.info().supports_instructions => {
+            let model = settings.openai_model.as_str();
+            let msg = format!("The {model} model doesn't support instructions");
+            ctx.send_error(msg).await?;
+            return Ok(None);
+        }
+        Some(instruction) if instruction.chars().count() > 500 => {
+            ctx.send_error("Instructions cannot be longer than 500 characters")
+                .await?;
+            return Ok(None);
+        }
+        Some(instruction) => Some(instruction),
+        None if mode == settings.mode => settings.instruction,
+        None => None,
+    };
+
+    let (translation_lang, max_seconds) = if let Some((guild_id, is_premium)) = guild_info {
+        let guild_row = data.guilds_db.get(guild_id.into()).await?;
+        (
+            guild_row.target_lang(IsPremium::from(is_premium)).map(String::from),
+            guild_row.tts_file_length,
+        )
+    } else {
+        (None, DEFAULT_TTS_FILE_LENGTH)
+    };
+
+    Ok(Some(ResolvedSettings {
+        voice,
+        mode,
+        speaking_rate,
+        instruction,
+        openai_model: settings.openai_model,
+        translation_lang,
+        max_seconds,
+        guild_id: ctx.guild_id(),
+        is_premium,
+    }))
+}
+
+async fn tts_(
+    ctx: Context<'_>,
+    author: &serenity::User,
+    message: &str,
+    overrides: TTSOverrides,
+) -> CommandResult {
+    let data = ctx.data();
+    let Some(ResolvedSettings {
+        voice,
+        mode,
+        speaking_rate,
+        instruction,
+        openai_model,
+        translation_lang,
+        max_seconds,
+        guild_id,
+        is_premium,
+    }) = resolve_settings(ctx, author, &overrides).await?
+    else {
+        return Ok(());
+    };
+
+    let requested_format = overrides.format.unwrap_or(AudioFormat::Mp3);
+    let request = SynthesisRequest {
+        content: message,
+        voice: &voice,
+        mode,
+        speaking_rate: &speaking_rate,
+        openai_model,
+        instruction: instruction.as_deref(),
+        max_length: &max_seconds.to_arraystring(),
+        translation_lang: translation_lang.as_deref(),
+        format: requested_format,
+    };
+
+    ctx.defer_or_broadcast().await?;
+    let SynthesizedAudio {
+        bytes,
+        hint,
+        format,
+    } = match synthesize(&data, request, guild_id, is_premium).await? {
+        Synthesis::Done(Some(audio)) => audio,
+        Synthesis::Done(None) => {
+            ctx.say("Failed to generate TTS audio").await?;
+            return Ok(());
+        }
+        Synthesis::Busy => {
+            let msg =
+                format!("The {mode} voice mode is very busy right now, please try again later");
+            ctx.send_error(msg).await?;
+            return Ok(());
+        }
+    };
+
+    if let Some(guild_id) = guild_id {
+        usage::record(&data, guild_id, author.id, mode, message.chars().count()).await?;
+    }
+
+    // OpenAI doesn't take a max length, so that has to be enforced after generation.
+    let target_format = overrides.format.unwrap_or(format);
+    let (bytes, format) = if mode == TTSMode::OpenAI || target_format != format {
+        let transcode = move || {
+            encode::transcode(bytes, hint.as_ref(), format, target_format, max_seconds.into())
         };
 
-        let mut file_name = author_name;
-        file_name.push_str(&aformat!(
-            "-{}.{}",
-            ctx.id(),
-            match mode {
-                TTSMode::gTTS | TTSMode::gCloud | TTSMode::Polly | TTSMode::OpenAI => astr!("mp3"),
-                TTSMode::eSpeak => astr!("wav"),
-            }
-        ));
+        tokio::task::spawn_blocking(transcode).await??
+    } else {
+        (bytes, format)
+    };
 
-        serenity::CreateAttachment::bytes(audio.to_vec(), file_name)
+    let content = if target_format != format {
+        "Generated some TTS! MP3 isn't available for this audio, so here is a WAV file instead."
+    } else {
+        "Generated some TTS!"
     };
 
-    ctx.send(
-        CreateReply::default()
-            .content("Generated some TTS!")
-            .attachment(attachment),
-    )
-    .await?;
+    let author_name: String = author
+        .name
+        .chars()
+        .filter(|char| char.is_alphanumeric())
+        .collect();
+
+    let file_name = format!("{author_name}-{}.{}", ctx.id(), format.extension());
+
+    let attachment = serenity::CreateAttachment::bytes(bytes, file_name);
+    ctx.send(CreateReply::default().content(content).attachment(attachment))
+        .await?;
 
     Ok(())
 }
@@ -193,7 +391,7 @@ pub async fn tts_speak_as(
     ctx: ApplicationContext<'_>,
     message: serenity::Message,
 ) -> CommandResult {
-    tts_(ctx.into(), &message.author, &message.content).await
+    tts_(ctx.into(), &message.author, &message.content, TTSOverrides::default()).await
 }
 
 #[poise::command(
@@ -202,7 +400,13 @@ pub async fn tts_speak_as(
     context_menu_command = "Speak with your voice!"
 )]
 pub async fn tts_speak(ctx: ApplicationContext<'_>, message: serenity::Message) -> CommandResult {
-    tts_(ctx.into(), &ctx.interaction.user, &message.content).await
+    tts_(
+        ctx.into(),
+        &ctx.interaction.user,
+        &message.content,
+        TTSOverrides::default(),
+    )
+    .await
 }
 
 /// Shows various different stats
@@ -289,6 +493,86 @@ and can be used by {total_members} people!",
     Ok(())
 }
 
+/// Shows how many characters this server has used with the paid voice modes
+#[poise::command(
+    category = "Extra Commands",
+    guild_only,
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS",
+    aliases("quota", "quotas")
+)]
+pub async fn usage(ctx: Context<'_>) -> CommandResult {
+    use std::fmt::Write as _;
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let is_premium = data.is_premium_simple(ctx.http(), guild_id).await?;
+    let (guild_row, used, by_mode) = tokio::try_join!(
+        data.guilds_db.get(guild_id.into()),
+        usage::fetch_usage(&data, guild_id, ctx.author().id),
+        usage::fetch_monthly_by_mode(&data, guild_id),
+    )?;
+
+    let quotas = usage::Quotas::for_guild(&guild_row, is_premium);
+    let [sep1, sep2, ..] = OPTION_SEPERATORS;
+
+    let mut monthly_costs = String::new();
+    let mut total_cost = 0.0;
+    for (mode, characters) in by_mode {
+        let cost = characters as f64 / 1_000_000.0 * usage::cost_per_million(mode);
+        total_cost += cost;
+
+        let characters = characters.to_formatted_string(&Locale::en);
+        writeln!(monthly_costs, "{sep2} {mode}: `{characters}` characters (~${cost:.2})")?;
+    }
+
+    if monthly_costs.is_empty() {
+        monthly_costs.push_str("Nothing has been generated yet this month\n");
+    }
+
+    let status = if used.exceeds(quotas) {
+        let free_mode = usage::free_mode(&data);
+        format!("**The quota has been used up, so messages are being read with the {free_mode} mode.**")
+    } else {
+        String::from("Messages use the paid voice modes until the quota is used up.")
+    };
+
+    let format = |n: i64| n.to_formatted_string(&Locale::en);
+
+    let embed = CreateEmbed::default()
+        .title("Character Usage")
+        .colour(ctx.neutral_colour().await)
+        .description(status)
+        .field(
+            "**Today**",
+            format!(
+                "{sep1} Server: `{}/{}` characters\n{sep1} You: `{}/{}` characters",
+                format(used.today),
+                format(quotas.daily.into()),
+                format(used.user_today),
+                format(quotas.user_daily.into()),
+            ),
+            false,
+        )
+        .field(
+            "**This Month**",
+            format!(
+                "{sep2} Server: `{}/{}` characters\n{monthly_costs}Estimated cost: ~${total_cost:.2}",
+                format(used.month),
+                format(quotas.monthly.into()),
+            ),
+            false,
+        )
+        .footer(CreateEmbedFooter::new(
+            "Only the OpenAI, gCloud, and Polly modes count towards the quotas, costs are rough estimates.\nChange the quotas with `/set char_quota`!",
+        ));
+
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
 /// Shows the current setup channel!
 #[poise::command(
     category = "Extra Commands",
@@ -369,15 +653,17 @@ pub async fn invite(ctx: Context<'_>) -> CommandResult {
     Ok(())
 }
 
-pub fn commands() -> [Command; 8] {
+pub fn commands() -> [Command; 10] {
     [
         tts(),
         uptime(),
         botstats(),
+        usage(),
         channel(),
         ping(),
         invite(),
         tts_speak(),
         tts_speak_as(),
+        audiobook::tts_audiobook(),
     ]
 }
@@ -5,7 +5,7 @@ use futures_channel::mpsc::UnboundedSender;
 use num_format::{Locale, ToFormattedString};
 use typesize::TypeSize;
 
-use crate::{REQUIRED_SETUP_PERMISSIONS, REQUIRED_VC_PERMISSIONS};
+use crate::REQUIRED_SETUP_PERMISSIONS;
 
 use self::serenity::{
     builder::*,
@@ -15,10 +15,13 @@ use self::serenity::{
 use poise::{serenity_prelude as serenity, CreateReply};
 
 use tts_core::{
-    common::{dm_generic, safe_truncate},
+    common::{dm_generic, member_roles_by_position, safe_truncate},
+    constants::REQUIRED_VC_PERMISSIONS,
     database,
     database_models::Compact,
-    structs::{Command, CommandResult, Context, PrefixContext, TTSModeChoice},
+    structs::{
+        Command, CommandResult, Context, PrefixContext, TTSModeChoice, VoiceScope, VoiceSettings,
+    },
 };
 
 #[poise::command(prefix_command, owners_only, hide_in_help)]
@@ -189,19 +192,52 @@ pub async fn info_(ctx: Context<'_>) -> CommandResult {
         .get((author_id, user_row.voice_mode.unwrap_or_default()))
         .await?;
 
+    let member_roles = match ctx.author_member().await {
+        Some(member) => match ctx.guild() {
+            Some(guild) => member_roles_by_position(&guild, &member.roles),
+            None => Vec::new(),
+        },
+        None => Vec::new(),
+    };
+
+    let scope = VoiceScope {
+        roles: &member_roles,
+        channel_id: Some(ctx.channel_id()),
+    };
+
+    let is_premium = data.is_premium_simple(ctx.http(), guild_id).await?;
+    let settings = data
+        .parse_user_or_guild_with_premium(ctx.author().id, Some((guild_id, is_premium)), scope)
+        .await?;
+
+    let VoiceSettings {
+        voice,
+        mode,
+        speaking_rate,
+        voice_source,
+        mode_source,
+        ..
+    } = settings;
+
     let voice_client = data.songbird.get(guild_id);
+    let call_health = data.watchdog.health(guild_id);
     let embed = CreateEmbed::default()
         .title("TTS Bot Debug Info")
         .description(format!(
             "
 Shard ID: `{shard_id}`
 Voice Client: `{voice_client:?}`
+Call Health: `{call_health:?}`
 
 Server Data: `{guild_row:?}`
 User Data: `{user_row:?}`
 Nickname Data: `{nick_row:?}`
 User Voice Data: `{user_voice_row:?}`
 Guild Voice Data: `{guild_voice_row:?}`
+
+Resolved Mode: `{mode}` (from {mode_source})
+Resolved Voice: `{voice}` at `{speaking_rate}` (from {voice_source})
+Precedence: user → role → channel → server → mode default
 "
         ));
 
@@ -354,6 +390,8 @@ pub async fn cache_info(ctx: Context<'_>, kind: Option<String>) -> CommandResult
             get_db_info("nickname db", &data.nickname_db),
             get_db_info("user voice db", &data.user_voice_db),
             get_db_info("guild voice db", &data.guild_voice_db),
+            get_db_info("role voice db", &data.role_voice_db),
+            get_db_info("channel voice db", &data.channel_voice_db),
         ])
     } else {
         None
@@ -1,6 +1,7 @@
 mod owner;
 mod setup;
 mod voice_paginator;
+mod voice_preview;
 
 use std::{borrow::Cow, collections::HashMap, fmt::Write, sync::atomic::Ordering};
 
@@ -11,18 +12,28 @@ use poise::serenity_prelude as serenity;
 use serenity::{builder::*, small_fixed_array::FixedString, Mentionable};
 
 use tts_core::{
-    common::{confirm_dialog, random_footer},
-    constants::{GTTS_DISABLED_ERROR, OPTION_SEPERATORS, PREMIUM_NEUTRAL_COLOUR},
+    audio::{MAX_PITCH, MAX_VOLUME, MIN_PITCH, MIN_VOLUME},
+    autojoin,
+    common::{cached_member_roles, confirm_dialog, random_footer},
+    constants::{
+        GTTS_DISABLED_ERROR, MAX_GREETING_LENGTH, OPTION_SEPERATORS, PREMIUM_NEUTRAL_COLOUR,
+    },
     database::{self, Compact},
+    opt_ext::OptionTryUnwrap,
     require_guild,
     structs::{
-        ApplicationContext, Command, CommandResult, Context, Data, Error, OpenAIModel, OpenAIModelChoice, Result, SpeakingRateInfo,
-        TTSMode, TTSModeChoice,
+        ApplicationContext, AudioEffect, AudioEffectChoice, Command, CommandResult, Context, Data, Error, FollowMode, FollowModeChoice, LengthPolicy, LengthPolicyChoice, OpenAIModel, OpenAIModelChoice, Result, SpeakingRateInfo,
+        TTSMode, TTSModeChoice, VoiceScope, VoiceSettings,
     },
     traits::PoiseContextExt,
+    usage::{QuotaKind, FREE_QUOTAS, PREMIUM_QUOTAS},
+    word_filter::{self, MAX_BLOCKED_WORDS, MAX_WORD_LENGTH},
 };
 
-use self::voice_paginator::MenuPaginator;
+use self::{
+    voice_paginator::{MenuPaginator, VoicePage},
+    voice_preview::preview,
+};
 
 fn format_voice<'a>(data: &Data, voice: &'a str, mode: TTSMode) -> Cow<'a, str> {
     if mode == TTSMode::gCloud {
@@ -70,6 +81,11 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
         none_str
     };
 
+    let captions_mention = guild_row.captions_channel.map(|c| c.mention().to_arraystring());
+    let transcript_mention = guild_row
+        .transcript_channel
+        .map(|c| c.mention().to_arraystring());
+
     let prefix = &guild_row.prefix;
     let guild_mode = guild_row.voice_mode;
     let nickname = nickname_row.name.as_deref().unwrap_or(none_str);
@@ -154,6 +170,9 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
     let xsaid = guild_row.xsaid();
     let autojoin = guild_row.auto_join();
     let msg_length = guild_row.msg_length;
+    let length_policy = guild_row.length_policy;
+    let tts_file_length = guild_row.tts_file_length;
+    let audiobook_quota = guild_row.audiobook_quota;
     let bot_ignore = guild_row.bot_ignore();
     let skip_emoji = guild_row.skip_emoji();
     let guild_mode: &str = guild_mode.into();
@@ -161,8 +180,15 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
     let require_voice = guild_row.require_voice();
     let text_in_voice = guild_row.text_in_voice();
     let audience_ignore = guild_row.audience_ignore();
+    let master_volume = guild_row.master_volume;
+    let normalize_loudness = guild_row.normalize_loudness();
+    let user_volume = userinfo_row.volume;
+    let user_pitch = userinfo_row.pitch;
+    let user_effect = userinfo_row.audio_effect;
     let voice_mode = user_mode.map(Into::into).unwrap_or(none_str);
     let role_mention = required_role.as_deref().unwrap_or(none_str);
+    let captions_mention = captions_mention.as_deref().unwrap_or(none_str);
+    let transcript_mention = transcript_mention.as_deref().unwrap_or(none_str);
     let required_prefix = guild_row.required_prefix.as_deref().unwrap_or(none_str);
     let repeated_chars = match guild_row.repeated_chars {
         Some(chars) => &chars.to_arraystring(),
@@ -180,7 +206,9 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
 {sep1} Setup Channel: {channel_mention}
 {sep1} Required Role: {role_mention}
 {sep1} Command Prefix: `{prefix}`
-{sep1} Auto Join: `{autojoin}`"), false)
+{sep1} Auto Join: `{autojoin}`
+{sep1} Captions Channel: {captions_mention}
+{sep1} Transcript Channel: {transcript_mention}"), false)
         .field("**TTS Settings**", format!("
 {sep2} <User> said: message: `{xsaid}`
 {sep2} Ignore bot's messages: `{bot_ignore}`
@@ -194,7 +222,12 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
 **{sep2} Default Server Voice: `{default_voice}`**
 
 {sep2} Max Time to Read: `{msg_length} seconds`
+{sep2} Messages over Max Time: `{length_policy}`
+{sep2} Max Length of `/tts` Files: `{tts_file_length} seconds`
+{sep2} Daily Audiobook Quota: `{audiobook_quota} characters`
 {sep2} Max Repeated Characters: `{repeated_chars}`
+{sep2} Master Volume: `{master_volume}%`
+{sep2} Normalize Loudness: `{normalize_loudness}`
         "),        false)
         .field("**Translation Settings (Premium Only)**", format!("
 {sep4} Translation: `{to_translate}`
@@ -208,6 +241,9 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
 {sep3} TTS Instruction: `{openai_instruction}`
 {sep3} Nickname: `{nickname}`
 {sep3} Speaking Rate: `{speaking_rate}{speaking_rate_kind}`
+{sep3} Volume: `{user_volume}%`
+{sep3} Pitch: `{user_pitch} semitones`
+{sep3} Effect: `{user_effect}`
         "),
         false)
     )).await?;
@@ -215,16 +251,28 @@ pub async fn settings(ctx: Context<'_>) -> CommandResult {
     Ok(())
 }
 
-async fn voice_autocomplete<'a>(
+pub(crate) async fn voice_autocomplete<'a>(
     ctx: ApplicationContext<'a>,
     searching: &'a str,
 ) -> serenity::CreateAutocompleteResponse<'a> {
     let data = ctx.data();
-    let Ok((_, mode, _, _)) = data
+    let member_roles = cached_member_roles(
+        ctx.cache(),
+        ctx.interaction.guild_id,
+        ctx.interaction.user.id,
+    );
+
+    let scope = VoiceScope {
+        roles: &member_roles,
+        channel_id: Some(ctx.interaction.channel_id),
+    };
+
+    let Ok(VoiceSettings { mode, .. }) = data
         .parse_user_or_guild(
             ctx.http(),
             ctx.interaction.user.id,
             ctx.interaction.guild_id,
+            scope,
         )
         .await
     else {
@@ -324,7 +372,7 @@ enum Target {
     User,
 }
 
-async fn can_change_mode(
+pub(crate) async fn can_change_mode(
     ctx: &Context<'_>,
     mode: Option<TTSMode>,
     guild_is_premium: bool,
@@ -356,6 +404,20 @@ async fn can_change_mode(
     }
 }
 
+/// Resolves the voice settings of the author, including their role and channel defaults.
+async fn author_voice_settings(ctx: &Context<'_>) -> Result<VoiceSettings> {
+    let author_id = ctx.author().id;
+    let member_roles = cached_member_roles(ctx.cache(), ctx.guild_id(), author_id);
+    let scope = VoiceScope {
+        roles: &member_roles,
+        channel_id: Some(ctx.channel_id()),
+    };
+
+    ctx.data()
+        .parse_user_or_guild(ctx.http(), author_id, ctx.guild_id(), scope)
+        .await
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn change_voice<'a, T, RowT1, RowT2>(
     ctx: &'a Context<'a>,
@@ -375,9 +437,16 @@ where
     (T, TTSMode): database::CacheKeyTrait,
 {
     let data = ctx.data();
-    let (_, mode, _, _) = data
-        .parse_user_or_guild(ctx.http(), author_id, Some(guild_id))
-        .await?;
+    let member_roles = cached_member_roles(ctx.cache(), Some(guild_id), author_id);
+    let scope = VoiceScope {
+        roles: &member_roles,
+        channel_id: Some(ctx.channel_id()),
+    };
+
+    let mode = data
+        .parse_user_or_guild(ctx.http(), author_id, Some(guild_id), scope)
+        .await?
+        .mode;
     Ok(if let Some(voice) = voice {
         if check_valid_voice(&data, &voice, mode) {
             general_db.create_row(key).await?;
@@ -427,7 +496,7 @@ fn get_voice_name<'a>(data: &'a Data, code: &str, mode: TTSMode) -> Option<&'a F
     }
 }
 
-fn check_valid_voice(data: &Data, code: &FixedString<u8>, mode: TTSMode) -> bool {
+pub(crate) fn check_valid_voice(data: &Data, code: &FixedString<u8>, mode: TTSMode) -> bool {
     match mode {
         TTSMode::gTTS | TTSMode::Polly => get_voice_name(data, code, mode).is_some(),
         TTSMode::eSpeak => data.espeak_voices.contains(code),
@@ -533,6 +602,30 @@ create_bool_command!(
     "auto_join",
     aliases("auto_join"),
 );
+create_bool_command!(
+    "Makes the bot join voice channels as soon as someone enters them",
+    join_on_enter,
+    "join_on_enter",
+    aliases("autojoin_on_enter", "join_on_connect"),
+);
+create_bool_command!(
+    "Makes the bot read out when members join or move into its voice channel",
+    announce_joins,
+    "announce_joins",
+    aliases("announce_join", "join_announcements"),
+);
+create_bool_command!(
+    "Makes the bot read out when members leave its voice channel",
+    announce_leaves,
+    "announce_leaves",
+    aliases("announce_leave", "leave_announcements"),
+);
+create_bool_command!(
+    "Makes the bot read out members' greetings when they join the voice channel",
+    greetings,
+    "greetings",
+    aliases("enable_greetings"),
+);
 create_bool_command!(
     "Makes the bot ignore messages sent by bots and webhooks",
     botignore,
@@ -552,7 +645,7 @@ create_bool_command!(
     aliases("audienceignore", "ignore_audience", "ignoreaudience"),
 );
 create_bool_command!(
-    "Makes the bot read messages from text-in-voice channels",
+    "Makes the bot read messages from text-in-voice and stage chat channels",
     text_in_voice,
     "text_in_voice",
     aliases(),
@@ -570,6 +663,18 @@ create_bool_command!(
     aliases("translate", "to_translate", "should_translate"),
     check = "crate::premium_command_check",
 );
+create_bool_command!(
+    "Makes the bot post the transcript of each voice session in a new thread",
+    transcript_threads,
+    "transcript_threads",
+    aliases("transcript_thread"),
+);
+create_bool_command!(
+    "Makes the bot even out the loudness of TTS messages between voices",
+    normalize_loudness,
+    "normalize_loudness",
+    aliases("normalise_loudness", "normalize", "normalise"),
+);
 
 /// Enables the experimental new message formatting
 #[poise::command(
@@ -591,6 +696,26 @@ async fn use_new_formatting(
     Ok(())
 }
 
+/// Allows your voice to be transcribed in servers with captions enabled
+#[poise::command(
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES"
+)]
+async fn captions_opt_in(
+    ctx: Context<'_>,
+    #[description = "Whether your voice can be transcribed into captions"] value: bool,
+) -> CommandResult {
+    let id = ctx.author().id.into();
+    let userinfo = &ctx.data().userinfo_db;
+
+    userinfo.set_one(id, "captions_opted_in", value).await?;
+
+    let resp = "Transcribing your voice into captions is now: {}";
+    ctx.say(replace_bool(resp, value)).await?;
+    Ok(())
+}
+
 /// Changes the required role to use the bot.
 #[poise::command(
     guild_only,
@@ -809,6 +934,217 @@ pub async fn server_voice(
     Ok(())
 }
 
+const ROLE_VOICE_UPSERT: &str = "
+    INSERT INTO role_voice(guild_id, role_id, mode, voice, speaking_rate)
+    VALUES($1, $2, $3, $4, $5)
+
+    ON CONFLICT (guild_id, role_id)
+    DO UPDATE SET
+        mode = EXCLUDED.mode,
+        voice = EXCLUDED.voice,
+        speaking_rate = EXCLUDED.speaking_rate
+";
+
+const CHANNEL_VOICE_UPSERT: &str = "
+    INSERT INTO channel_voice(guild_id, channel_id, mode, voice, speaking_rate)
+    VALUES($1, $2, $3, $4, $5)
+
+    ON CONFLICT (guild_id, channel_id)
+    DO UPDATE SET
+        mode = EXCLUDED.mode,
+        voice = EXCLUDED.voice,
+        speaking_rate = EXCLUDED.speaking_rate
+";
+
+#[allow(clippy::too_many_arguments)]
+async fn change_scoped_voice(
+    ctx: &Context<'_>,
+    db: &database::Handler<[i64; 2], database::ScopedVoiceRowRaw>,
+    upsert: &'static str,
+    guild_id: serenity::GuildId,
+    key: [i64; 2],
+    target: &str,
+    mode: Option<TTSMode>,
+    voice: Option<FixedString<u8>>,
+    speaking_rate: Option<f32>,
+) -> Result<Option<Cow<'static, str>>> {
+    let data = ctx.data();
+    let Some(mode) = mode else {
+        if voice.is_some() || speaking_rate.is_some() {
+            return Ok(Some(Cow::Borrowed(
+                "**Error**: A mode is required to set a voice or speaking rate",
+            )));
+        }
+
+        db.delete(key).await?;
+        return Ok(Some(Cow::Owned(format!(
+            "Reset the voice defaults for {target}"
+        ))));
+    };
+
+    let guild_is_premium = data.is_premium_simple(ctx.http(), guild_id).await?;
+    if !can_change_mode(ctx, Some(mode), guild_is_premium).await? {
+        return Ok(None);
+    }
+
+    if let Some(voice) = &voice
+        && !check_valid_voice(&data, voice, mode)
+    {
+        return Ok(Some(Cow::Borrowed(
+            "Invalid voice, do `/voices` to see the available voices",
+        )));
+    }
+
+    if let Some(speaking_rate) = speaking_rate {
+        let Some(speaking_rate_info) = mode.speaking_rate_info() else {
+            let msg = format!("**Error**: Cannot set speaking rate for the {mode} mode");
+            return Ok(Some(Cow::Owned(msg)));
+        };
+
+        let kind = speaking_rate_info.kind();
+        let SpeakingRateInfo { min, max, .. } = speaking_rate_info;
+        if speaking_rate > max {
+            let msg = format!("**Error**: Cannot set the speaking rate multiplier above {max}{kind}");
+            return Ok(Some(Cow::Owned(msg)));
+        } else if speaking_rate < min {
+            let msg = format!("**Error**: Cannot set the speaking rate multiplier below {min}{kind}");
+            return Ok(Some(Cow::Owned(msg)));
+        }
+    }
+
+    let voice = voice.map(|voice| {
+        if mode == TTSMode::OpenAI {
+            voice.to_lowercase()
+        } else {
+            voice.to_string()
+        }
+    });
+
+    // Written in one query, as the mode cannot be null even for a moment.
+    data.guilds_db.create_row(guild_id.into()).await?;
+    sqlx::query(upsert)
+        .bind(key[0])
+        .bind(key[1])
+        .bind(mode)
+        .bind(voice.as_deref())
+        .bind(speaking_rate)
+        .execute(&data.pool)
+        .await?;
+
+    db.invalidate_cache(&key);
+
+    let mut response = format!("Set the {mode} mode as the default for {target}");
+    if let Some(voice) = &voice {
+        let name = get_voice_name(&data, voice, mode).map_or(voice.as_str(), |n| n.as_str());
+        write!(response, ", speaking in {name}")?;
+    }
+    if let Some(speaking_rate) = speaking_rate {
+        write!(response, ", at {speaking_rate}x speed")?;
+    }
+
+    Ok(Some(Cow::Owned(response)))
+}
+
+/// Sets the default voice for members with a role, leave mode blank to reset
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS",
+    aliases("rolevoice", "role_default_voice")
+)]
+pub async fn role_voice(
+    ctx: Context<'_>,
+    #[description = "The role to change the default voice of"] role: serenity::Role,
+    #[description = "The TTS Mode for this role"] mode: Option<TTSModeChoice>,
+    #[description = "The voice for this role"]
+    #[autocomplete = "voice_autocomplete"]
+    voice: Option<FixedString<u8>>,
+    #[description = "The speaking rate for this role"]
+    #[min = 0]
+    #[max = 400.0]
+    speaking_rate: Option<f32>,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let target = format!("the {} role", role.name);
+    let key = [guild_id.into(), role.id.get() as i64];
+    let mode = mode.map(TTSMode::from);
+
+    let response = change_scoped_voice(
+        &ctx,
+        &data.role_voice_db,
+        ROLE_VOICE_UPSERT,
+        guild_id,
+        key,
+        &target,
+        mode,
+        voice,
+        speaking_rate,
+    )
+    .await?;
+
+    if let Some(response) = response {
+        ctx.say(response).await?;
+    }
+
+    Ok(())
+}
+
+/// Sets the default voice for messages sent in a channel, leave mode blank to reset
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS",
+    aliases("channelvoice", "channel_default_voice")
+)]
+pub async fn channel_voice(
+    ctx: Context<'_>,
+    #[description = "The channel to change the default voice of"]
+    #[channel_types("Text", "Voice")]
+    channel: serenity::GuildChannel,
+    #[description = "The TTS Mode for this channel"] mode: Option<TTSModeChoice>,
+    #[description = "The voice for this channel"]
+    #[autocomplete = "voice_autocomplete"]
+    voice: Option<FixedString<u8>>,
+    #[description = "The speaking rate for this channel"]
+    #[min = 0]
+    #[max = 400.0]
+    speaking_rate: Option<f32>,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let target = channel.mention().to_string();
+    let key = [guild_id.into(), channel.id.get() as i64];
+    let mode = mode.map(TTSMode::from);
+
+    let response = change_scoped_voice(
+        &ctx,
+        &data.channel_voice_db,
+        CHANNEL_VOICE_UPSERT,
+        guild_id,
+        key,
+        &target,
+        mode,
+        voice,
+        speaking_rate,
+    )
+    .await?;
+
+    if let Some(response) = response {
+        ctx.say(response).await?;
+    }
+
+    Ok(())
+}
+
 /// Changes the target language for translation
 #[poise::command(
     guild_only,
@@ -951,150 +1287,914 @@ pub async fn msg_length(
     Ok(())
 }
 
-/// Changes the multiplier for how fast to speak
+/// Changes what happens to messages which take longer than the max time to read
 #[poise::command(
+    guild_only,
     category = "Settings",
     prefix_command,
     slash_command,
+    required_permissions = "ADMINISTRATOR",
     required_bot_permissions = "SEND_MESSAGES",
-    aliases(
-        "speed",
-        "speed_multiplier",
-        "speaking_rate_multiplier",
-        "speaking_speed",
-        "tts_speed"
-    )
+    aliases("length_limit_policy", "long_messages")
 )]
-pub async fn speaking_rate(
+pub async fn length_policy(
     ctx: Context<'_>,
-    #[description = "The speed to speak at"]
-    #[min = 0]
-    #[max = 400.0]
-    speaking_rate: f32,
+    #[description = "Whether to cut off or skip messages over the max length"]
+    policy: LengthPolicyChoice,
 ) -> CommandResult {
-    let data = ctx.data();
-    let author = ctx.author();
-
-    let (_, mode, _, _) = data
-        .parse_user_or_guild(ctx.http(), author.id, ctx.guild_id())
+    let policy = LengthPolicy::from(policy);
+    ctx.data()
+        .guilds_db
+        .set_one(ctx.guild_id().unwrap().into(), "length_policy", policy)
         .await?;
-    let Some(speaking_rate_info) = mode.speaking_rate_info() else {
-        let msg = aformat!("**Error**: Cannot set speaking rate for the {mode} mode");
-        ctx.say(&*msg).await?;
-        return Ok(());
-    };
-
-    let kind = speaking_rate_info.kind();
-    let SpeakingRateInfo { min, max, .. } = speaking_rate_info;
-    let to_send: &str = if speaking_rate > max {
-        &aformat!("**Error**: Cannot set the speaking rate multiplier above {max}{kind}")
-    } else if speaking_rate < min {
-        &aformat!("**Error**: Cannot set the speaking rate multiplier below {min}{kind}")
-    } else {
-        data.userinfo_db.create_row(author.id.get() as i64).await?;
-        data.user_voice_db
-            .set_one(
-                (author.id.get() as i64, mode),
-                "speaking_rate",
-                &speaking_rate,
-            )
-            .await?;
 
-        &aformat!("Your speaking rate is now: {speaking_rate}{kind}")
-    };
+    ctx.say(match policy {
+        LengthPolicy::Truncate => "Messages over the max length will now be cut off",
+        LengthPolicy::Reject => "Messages over the max length will now be skipped",
+    })
+    .await?;
 
-    ctx.say(to_send).await?;
     Ok(())
 }
 
-/// Replaces your username in "<user> said" with a given name
+/// Changes which voice channels the bot joins when someone enters them
 #[poise::command(
     guild_only,
     category = "Settings",
     prefix_command,
     slash_command,
+    required_permissions = "ADMINISTRATOR",
     required_bot_permissions = "SEND_MESSAGES",
-    aliases("nick_name", "nickname", "name")
+    aliases("join_on_enter_channels", "autojoin_channel")
 )]
-pub async fn nick(
+pub async fn join_on_enter_channel(
     ctx: Context<'_>,
-    #[description = "The user to set the nick for, defaults to you"] user: Option<serenity::User>,
-    #[description = "The nickname to set, leave blank to reset"]
-    #[rest]
-    nickname: Option<String>,
+    #[description = "The voice channel to allow or deny"]
+    #[channel_types("Voice", "Stage")]
+    channel: serenity::GuildChannel,
+    #[description = "Which list to put the channel on"] list: autojoin::ChannelList,
 ) -> CommandResult {
-    let author = ctx.author();
     let guild_id = ctx.guild_id().unwrap();
-    let user = user.as_ref().unwrap_or(author);
-
-    if author.id != user.id && !ctx.author_permissions()?.manage_nicknames() {
-        ctx.say("**Error**: You need permission to set other people's nicknames!")
-            .await?;
-        return Ok(());
-    }
-
-    let data = ctx.data();
-
-    let to_send = if let Some(nick) = nickname {
-        if nick.contains('<') && nick.contains('>') {
-            "**Error**: You can't have mentions/emotes in your nickname!"
-        } else {
-            tokio::try_join!(
-                data.guilds_db.create_row(guild_id.into()),
-                data.userinfo_db.create_row(user.id.into())
-            )?;
-
-            data.nickname_db
-                .set_one([guild_id.into(), user.id.into()], "name", &nick)
-                .await?;
+    autojoin::set_channel(ctx.data(), guild_id, channel.id, list).await?;
 
-            &format!("Changed {}'s nickname to {nick}", user.name)
+    let mention = channel.mention();
+    let to_send = match list {
+        autojoin::ChannelList::Allow => {
+            format!("I will now only join {mention} and other allowed channels when someone enters them")
+        }
+        autojoin::ChannelList::Deny => {
+            format!("I will no longer join {mention} when someone enters it")
+        }
+        autojoin::ChannelList::Remove => {
+            format!("{mention} has been removed from the allowed and denied channels")
         }
-    } else {
-        data.nickname_db
-            .delete([guild_id.into(), user.id.into()])
-            .await?;
-
-        &aformat!("Reset {}'s nickname", &user.name)
     };
 
     ctx.say(to_send).await?;
+
     Ok(())
 }
 
-/// Changes the voice mode that messages are read in for you
+/// Makes the bot move with a user when they change voice channel
 #[poise::command(
     guild_only,
     category = "Settings",
     prefix_command,
     slash_command,
-    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS",
-    aliases("voice_mode", "tts_mode", "ttsmode")
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("follow_mode", "follow_user")
 )]
-pub async fn mode(
+pub async fn follow(
     ctx: Context<'_>,
-    #[description = "The TTS Mode to change to, leave blank for server default"] mode: Option<
-        TTSModeChoice,
-    >,
+    #[description = "Who the bot should move with when they change voice channel"]
+    mode: FollowModeChoice,
 ) -> CommandResult {
-    let data = ctx.data();
-    let guild_id = ctx.guild_id().unwrap();
-
-    let mode = mode.map(TTSMode::from);
-    let guild_is_premium = data.is_premium_simple(ctx.http(), guild_id).await?;
-    if !can_change_mode(&ctx, mode, guild_is_premium).await? {
-        return Ok(());
-    }
+    let mode = FollowMode::from(mode);
+    ctx.data()
+        .guilds_db
+        .set_one(ctx.guild_id().unwrap().into(), "follow_mode", mode)
+        .await?;
 
-    let key = if guild_is_premium {
-        "premium_voice_mode"
-    } else {
-        "voice_mode"
-    };
+    ctx.say(match mode {
+        FollowMode::Off => "I will now stay in the voice channel I was summoned to",
+        FollowMode::Summoner => "I will now move with whoever summoned me",
+        FollowMode::LastSpeaker => "I will now move with whoever's message I read out last",
+    })
+    .await?;
 
-    data.userinfo_db
-        .set_one(ctx.author().id.into(), key, mode)
-        .await?;
+    Ok(())
+}
+
+/// Changes the max length of audio generated by `/tts` in seconds
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("file_length", "tts_length")
+)]
+pub async fn tts_file_length(
+    ctx: Context<'_>,
+    #[description = "Max length of /tts audio in seconds"] seconds: u16,
+) -> CommandResult {
+    let to_send = if seconds > 300 {
+        "**Error**: Cannot set the max length of `/tts` files above 300 seconds"
+    } else if seconds < 10 {
+        "**Error**: Cannot set the max length of `/tts` files below 10 seconds"
+    } else {
+        ctx.data()
+            .guilds_db
+            .set_one(
+                ctx.guild_id().unwrap().into(),
+                "tts_file_length",
+                &(seconds as i16),
+            )
+            .await?;
+
+        &aformat!("Max `/tts` file length is now: {seconds} seconds")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes how many characters of audiobooks can be generated in this server each day
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("audiobook_limit")
+)]
+pub async fn audiobook_quota(
+    ctx: Context<'_>,
+    #[description = "Max characters of audiobooks per day, 0 to disable audiobooks"]
+    #[max = 500000]
+    characters: u32,
+) -> CommandResult {
+    let to_send = if characters > 500_000 {
+        "**Error**: Cannot set the daily audiobook quota above 500000 characters"
+    } else {
+        ctx.data()
+            .guilds_db
+            .set_one(
+                ctx.guild_id().unwrap().into(),
+                "audiobook_quota",
+                &(characters as i32),
+            )
+            .await?;
+
+        &aformat!("The daily audiobook quota is now: {characters} characters")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Transcribes speech in the voice channel into a text channel, leave blank to disable
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("captions_channel", "transcription")
+)]
+pub async fn captions(
+    ctx: Context<'_>,
+    #[description = "The channel to post captions to"]
+    #[channel_types("Text")]
+    channel: Option<serenity::GuildChannel>,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    if data.stt_config.is_none() {
+        ctx.send_error("Speech-to-text has not been set up for this bot, so captions are unavailable.").await?;
+        return Ok(());
+    }
+
+    let to_send = if let Some(channel) = channel {
+        let bot_id = ctx.cache().current_user().id;
+        let can_send = {
+            let guild = require_guild!(ctx);
+            let bot_member = guild.members.get(&bot_id).try_unwrap()?;
+            guild.user_permissions_in(&channel, bot_member).send_messages()
+        };
+
+        if !can_send {
+            ctx.send_error("I do not have permission to send messages in that channel!").await?;
+            return Ok(());
+        }
+
+        data.guilds_db
+            .set_one(guild_id.into(), "captions_channel", &(channel.id.get() as i64))
+            .await?;
+
+        format!(
+            "Speech from members who have run `/set captions_opt_in True` will be transcribed into {} from the next time I join a voice channel.",
+            channel.mention()
+        )
+    } else {
+        data.guilds_db
+            .set_one(guild_id.into(), "captions_channel", None::<i64>)
+            .await?;
+
+        String::from("Captions are now disabled, this takes effect from the next time I join a voice channel.")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Logs everything read out by the bot to a channel, leave blank to disable
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("transcript", "tts_log")
+)]
+pub async fn transcript_channel(
+    ctx: Context<'_>,
+    #[description = "The channel to post the transcript to"]
+    #[channel_types("Text")]
+    channel: Option<serenity::GuildChannel>,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let to_send = if let Some(channel) = channel {
+        let bot_id = ctx.cache().current_user().id;
+        let can_send = {
+            let guild = require_guild!(ctx);
+            let bot_member = guild.members.get(&bot_id).try_unwrap()?;
+            guild.user_permissions_in(&channel, bot_member).send_messages()
+        };
+
+        if !can_send {
+            ctx.send_error("I do not have permission to send messages in that channel!").await?;
+            return Ok(());
+        }
+
+        data.guilds_db
+            .set_one(guild_id.into(), "transcript_channel", &(channel.id.get() as i64))
+            .await?;
+
+        format!("Messages read out will now be logged in {}", channel.mention())
+    } else {
+        data.guilds_db
+            .set_one(guild_id.into(), "transcript_channel", None::<i64>)
+            .await?;
+
+        String::from("Messages read out will no longer be logged")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes how many days transcript messages are kept for, leave blank to keep forever
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("transcript_retention_days", "transcript_expiry")
+)]
+pub async fn transcript_retention(
+    ctx: Context<'_>,
+    #[description = "Days to keep transcript messages for"]
+    #[min = 1]
+    #[max = 365]
+    days: Option<u16>,
+) -> CommandResult {
+    let guilds_db = &ctx.data().guilds_db;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let to_send = match days {
+        Some(days) if !(1..=365).contains(&days) => {
+            Cow::Borrowed("**Error**: Transcript messages can only be kept for 1 to 365 days")
+        }
+        Some(days) => {
+            guilds_db
+                .set_one(guild_id.into(), "transcript_retention", &(days as i16))
+                .await?;
+
+            Cow::Owned(format!("Transcript messages will now be deleted after {days} days"))
+        }
+        None => {
+            guilds_db
+                .set_one(guild_id.into(), "transcript_retention", None::<i16>)
+                .await?;
+
+            Cow::Borrowed("Transcript messages will now be kept forever")
+        }
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Makes the bot leave voice channels after nothing has been read out for a while
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("idle_leave", "inactivity_timeout")
+)]
+pub async fn idle_timeout(
+    ctx: Context<'_>,
+    #[description = "Minutes without TTS before leaving, leave blank to never leave"]
+    #[min = 1]
+    #[max = 1440]
+    minutes: Option<u16>,
+) -> CommandResult {
+    let guilds_db = &ctx.data().guilds_db;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let to_send = match minutes {
+        Some(minutes) if !(1..=1440).contains(&minutes) => {
+            Cow::Borrowed("**Error**: The idle timeout must be between 1 and 1440 minutes")
+        }
+        Some(minutes) => {
+            guilds_db
+                .set_one(guild_id.into(), "idle_timeout", &(minutes as i16))
+                .await?;
+
+            Cow::Owned(format!(
+                "I will now leave voice channels after {minutes} minutes without TTS"
+            ))
+        }
+        None => {
+            guilds_db
+                .set_one(guild_id.into(), "idle_timeout", None::<i16>)
+                .await?;
+
+            Cow::Borrowed("I will no longer leave voice channels for being idle")
+        }
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes how long the bot waits before leaving once everyone else has left
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("lonely_delay", "leave_delay")
+)]
+pub async fn lonely_leave_delay(
+    ctx: Context<'_>,
+    #[description = "Seconds to wait before leaving an empty channel, leave blank to leave instantly"]
+    #[min = 1]
+    #[max = 600]
+    seconds: Option<u16>,
+) -> CommandResult {
+    let guilds_db = &ctx.data().guilds_db;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let to_send = match seconds {
+        Some(seconds) if !(1..=600).contains(&seconds) => {
+            Cow::Borrowed("**Error**: The lonely leave delay must be between 1 and 600 seconds")
+        }
+        Some(seconds) => {
+            guilds_db
+                .set_one(guild_id.into(), "lonely_leave_delay", &(seconds as i16))
+                .await?;
+
+            Cow::Owned(format!(
+                "I will now wait {seconds} seconds before leaving an empty voice channel"
+            ))
+        }
+        None => {
+            guilds_db
+                .set_one(guild_id.into(), "lonely_leave_delay", None::<i16>)
+                .await?;
+
+            Cow::Borrowed("I will now leave empty voice channels instantly")
+        }
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes how many characters can be read out with the paid voice modes
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("quota", "character_quota")
+)]
+pub async fn char_quota(
+    ctx: Context<'_>,
+    #[description = "Which quota to change"] kind: QuotaKind,
+    #[description = "Max characters, leave blank to reset to the highest allowed"]
+    characters: Option<u32>,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let max = if data.is_premium_simple(ctx.http(), guild_id).await? {
+        PREMIUM_QUOTAS
+    } else {
+        FREE_QUOTAS
+    };
+
+    let max = kind.get(max);
+    let name = kind.label();
+    let to_send = match characters {
+        Some(characters) if characters > max => {
+            format!("**Error**: Cannot set the {name} quota above {max} characters")
+        }
+        Some(characters) => {
+            data.guilds_db
+                .set_one(guild_id.into(), kind.column(), &(characters as i32))
+                .await?;
+
+            format!("The {name} quota is now: {characters} characters")
+        }
+        None => {
+            data.guilds_db
+                .set_one(guild_id.into(), kind.column(), None::<i32>)
+                .await?;
+
+            format!("The {name} quota has been reset to {max} characters")
+        }
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes the multiplier for how fast to speak
+#[poise::command(
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases(
+        "speed",
+        "speed_multiplier",
+        "speaking_rate_multiplier",
+        "speaking_speed",
+        "tts_speed"
+    )
+)]
+pub async fn speaking_rate(
+    ctx: Context<'_>,
+    #[description = "The speed to speak at"]
+    #[min = 0]
+    #[max = 400.0]
+    speaking_rate: f32,
+) -> CommandResult {
+    let data = ctx.data();
+    let author = ctx.author();
+
+    let mode = author_voice_settings(&ctx).await?.mode;
+    let Some(speaking_rate_info) = mode.speaking_rate_info() else {
+        let msg = aformat!("**Error**: Cannot set speaking rate for the {mode} mode");
+        ctx.say(&*msg).await?;
+        return Ok(());
+    };
+
+    let kind = speaking_rate_info.kind();
+    let SpeakingRateInfo { min, max, .. } = speaking_rate_info;
+    let to_send: &str = if speaking_rate > max {
+        &aformat!("**Error**: Cannot set the speaking rate multiplier above {max}{kind}")
+    } else if speaking_rate < min {
+        &aformat!("**Error**: Cannot set the speaking rate multiplier below {min}{kind}")
+    } else {
+        data.userinfo_db.create_row(author.id.get() as i64).await?;
+        data.user_voice_db
+            .set_one(
+                (author.id.get() as i64, mode),
+                "speaking_rate",
+                &speaking_rate,
+            )
+            .await?;
+
+        &aformat!("Your speaking rate is now: {speaking_rate}{kind}")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes how loud your TTS messages are, as a percentage
+#[poise::command(
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("user_volume", "tts_volume")
+)]
+pub async fn volume(
+    ctx: Context<'_>,
+    #[description = "The volume to speak at, as a percentage"]
+    #[min = 0]
+    #[max = 200]
+    volume: i16,
+) -> CommandResult {
+    let to_send = if volume > MAX_VOLUME {
+        &aformat!("**Error**: Cannot set the volume above {MAX_VOLUME}%")
+    } else if volume < MIN_VOLUME {
+        &aformat!("**Error**: Cannot set the volume below {MIN_VOLUME}%")
+    } else {
+        let author_id = ctx.author().id.into();
+        let userinfo_db = &ctx.data().userinfo_db;
+
+        userinfo_db.create_row(author_id).await?;
+        userinfo_db.set_one(author_id, "volume", &volume).await?;
+
+        &aformat!("Your volume is now: {volume}%")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes how high or low your TTS voice is, in semitones
+#[poise::command(
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("voice_pitch", "tts_pitch")
+)]
+pub async fn pitch(
+    ctx: Context<'_>,
+    #[description = "The number of semitones to shift your voice by"]
+    #[min = -12]
+    #[max = 12]
+    semitones: i16,
+) -> CommandResult {
+    let max_lowered = -MIN_PITCH;
+    let to_send = if semitones > MAX_PITCH {
+        &aformat!("**Error**: Cannot raise the pitch by more than {MAX_PITCH} semitones")
+    } else if semitones < MIN_PITCH {
+        &aformat!("**Error**: Cannot lower the pitch by more than {max_lowered} semitones")
+    } else {
+        let author_id = ctx.author().id.into();
+        let userinfo_db = &ctx.data().userinfo_db;
+
+        userinfo_db.create_row(author_id).await?;
+        userinfo_db.set_one(author_id, "pitch", &semitones).await?;
+
+        &aformat!("Your pitch is now shifted by: {semitones} semitones")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Changes the effect applied to your TTS voice
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS",
+    aliases("audio_effect", "voice_effect")
+)]
+pub async fn effect(
+    ctx: Context<'_>,
+    #[description = "The effect to apply, leave blank to remove"] effect: Option<AudioEffectChoice>,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let effect = effect.map_or(AudioEffect::None, AudioEffect::from);
+
+    if effect.is_premium() && !data.is_premium_simple(ctx.http(), guild_id).await? {
+        ctx.send(poise::CreateReply::default().embed(CreateEmbed::default()
+            .title("TTS Bot Premium")
+            .colour(PREMIUM_NEUTRAL_COLOUR)
+            .thumbnail(data.premium_avatar_url.as_str())
+            .description(aformat!("
+                The `{effect}` effect is only for TTS Bot Premium subscribers, please check out the `/premium` command!
+            ").as_str())
+        )).await?;
+
+        return Ok(());
+    }
+
+    let author_id = ctx.author().id.into();
+    data.userinfo_db.create_row(author_id).await?;
+    data.userinfo_db
+        .set_one(author_id, "audio_effect", effect)
+        .await?;
+
+    let response = if effect == AudioEffect::None {
+        "Removed the effect from your voice"
+    } else {
+        &aformat!("Your voice now has the {effect} effect")
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Changes the volume of all TTS messages in this server, as a percentage
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("server_volume", "mastervolume")
+)]
+pub async fn master_volume(
+    ctx: Context<'_>,
+    #[description = "The volume for all TTS messages, as a percentage"]
+    #[min = 0]
+    #[max = 200]
+    volume: i16,
+) -> CommandResult {
+    let to_send = if volume > MAX_VOLUME {
+        &aformat!("**Error**: Cannot set the master volume above {MAX_VOLUME}%")
+    } else if volume < MIN_VOLUME {
+        &aformat!("**Error**: Cannot set the master volume below {MIN_VOLUME}%")
+    } else {
+        ctx.data()
+            .guilds_db
+            .set_one(ctx.guild_id().unwrap().into(), "master_volume", &volume)
+            .await?;
+
+        &aformat!("The server's master volume is now: {volume}%")
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Replaces your username in "<user> said" with a given name
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("nick_name", "nickname", "name")
+)]
+pub async fn nick(
+    ctx: Context<'_>,
+    #[description = "The user to set the nick for, defaults to you"] user: Option<serenity::User>,
+    #[description = "The nickname to set, leave blank to reset"]
+    #[rest]
+    nickname: Option<String>,
+) -> CommandResult {
+    let author = ctx.author();
+    let guild_id = ctx.guild_id().unwrap();
+    let user = user.as_ref().unwrap_or(author);
+
+    if author.id != user.id && !ctx.author_permissions()?.manage_nicknames() {
+        ctx.say("**Error**: You need permission to set other people's nicknames!")
+            .await?;
+        return Ok(());
+    }
+
+    let data = ctx.data();
+
+    let to_send = if let Some(nick) = nickname {
+        if nick.contains('<') && nick.contains('>') {
+            "**Error**: You can't have mentions/emotes in your nickname!"
+        } else {
+            tokio::try_join!(
+                data.guilds_db.create_row(guild_id.into()),
+                data.userinfo_db.create_row(user.id.into())
+            )?;
+
+            data.nickname_db
+                .set_one([guild_id.into(), user.id.into()], "name", &nick)
+                .await?;
+
+            &format!("Changed {}'s nickname to {nick}", user.name)
+        }
+    } else {
+        let key = [guild_id.into(), user.id.into()];
+        if data.nickname_db.get(key).await?.greeting.is_some() {
+            data.nickname_db.set_one(key, "name", None::<&str>).await?;
+        } else {
+            data.nickname_db.delete(key).await?;
+        }
+
+        &aformat!("Reset {}'s nickname", &user.name)
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Sets a greeting to be read out when you join the voice channel
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("greet", "join_message")
+)]
+pub async fn greeting(
+    ctx: Context<'_>,
+    #[description = "The user to set the greeting for, defaults to you"] user: Option<
+        serenity::User,
+    >,
+    #[description = "The greeting to read out, leave blank to reset"]
+    #[rest]
+    greeting: Option<String>,
+) -> CommandResult {
+    let author = ctx.author();
+    let guild_id = ctx.guild_id().unwrap();
+    let user = user.as_ref().unwrap_or(author);
+
+    if author.id != user.id && !ctx.author_permissions()?.manage_nicknames() {
+        ctx.say("**Error**: You need permission to set other people's greetings!")
+            .await?;
+        return Ok(());
+    }
+
+    let data = ctx.data();
+    let to_send = if let Some(greeting) = greeting {
+        if greeting.chars().count() > MAX_GREETING_LENGTH {
+            Cow::Owned(format!(
+                "**Error**: Greetings cannot be longer than {MAX_GREETING_LENGTH} characters!"
+            ))
+        } else if greeting.contains('<') && greeting.contains('>') {
+            Cow::Borrowed("**Error**: You can't have mentions/emotes in your greeting!")
+        } else if let Some(blocked_words) = data.word_filter.get(&data.pool, guild_id).await?
+            && blocked_words.is_match(&greeting)
+        {
+            Cow::Borrowed(
+                "**Error**: Your greeting contains a word which is blocked in this server!",
+            )
+        } else {
+            tokio::try_join!(
+                data.guilds_db.create_row(guild_id.into()),
+                data.userinfo_db.create_row(user.id.into())
+            )?;
+
+            data.nickname_db
+                .set_one([guild_id.into(), user.id.into()], "greeting", &greeting)
+                .await?;
+
+            let guild_row = data.guilds_db.get(guild_id.into()).await?;
+            let mut msg = format!("Changed {}'s greeting to: {greeting}", user.name);
+            if !guild_row.greetings() {
+                msg.push_str("\nGreetings are currently disabled in this server, an administrator can enable them with `/set greetings`.");
+            }
+
+            Cow::Owned(msg)
+        }
+    } else {
+        let key = [guild_id.into(), user.id.into()];
+        if data.nickname_db.get(key).await?.name.is_some() {
+            data.nickname_db.set_one(key, "greeting", None::<&str>).await?;
+        } else {
+            data.nickname_db.delete(key).await?;
+        }
+
+        Cow::Owned(format!("Reset {}'s greeting", user.name))
+    };
+
+    ctx.say(to_send).await?;
+    Ok(())
+}
+
+/// Blocks a word from being read out, or lists the blocked words
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("blocked_words", "ban_word")
+)]
+pub async fn block_word(
+    ctx: Context<'_>,
+    #[description = "The word to block, leave blank to list the blocked words"] word: Option<
+        String,
+    >,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let blocked_words = word_filter::list(&data.pool, guild_id).await?;
+
+    let Some(word) = word else {
+        let msg = if blocked_words.is_empty() {
+            Cow::Borrowed("No words are blocked in this server.")
+        } else {
+            Cow::Owned(format!("Blocked words: ||{}||", blocked_words.join(", ")))
+        };
+
+        ctx.say(msg).await?;
+        return Ok(());
+    };
+
+    let msg = if !word_filter::is_valid_word(&word) {
+        Cow::Owned(format!(
+            "**Error**: Blocked words must be a single word of up to {MAX_WORD_LENGTH} letters or numbers!"
+        ))
+    } else if blocked_words.len() >= MAX_BLOCKED_WORDS {
+        Cow::Owned(format!(
+            "**Error**: Servers cannot block more than {MAX_BLOCKED_WORDS} words!"
+        ))
+    } else {
+        data.guilds_db.create_row(guild_id.into()).await?;
+        if data.word_filter.add(&data.pool, guild_id, &word).await? {
+            Cow::Borrowed("Blocked that word, it will no longer be read out.")
+        } else {
+            Cow::Borrowed("That word is already blocked.")
+        }
+    };
+
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+/// Unblocks a word, so it is read out again
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    required_bot_permissions = "SEND_MESSAGES",
+    aliases("unban_word")
+)]
+pub async fn unblock_word(
+    ctx: Context<'_>,
+    #[description = "The word to unblock"] word: String,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let msg = if data.word_filter.remove(&data.pool, guild_id, &word).await? {
+        "Unblocked that word, it will be read out again."
+    } else {
+        "That word isn't blocked."
+    };
+
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+/// Changes the voice mode that messages are read in for you
+#[poise::command(
+    guild_only,
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES | EMBED_LINKS",
+    aliases("voice_mode", "tts_mode", "ttsmode")
+)]
+pub async fn mode(
+    ctx: Context<'_>,
+    #[description = "The TTS Mode to change to, leave blank for server default"] mode: Option<
+        TTSModeChoice,
+    >,
+) -> CommandResult {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let mode = mode.map(TTSMode::from);
+    let guild_is_premium = data.is_premium_simple(ctx.http(), guild_id).await?;
+    if !can_change_mode(&ctx, mode, guild_is_premium).await? {
+        return Ok(());
+    }
+
+    let key = if guild_is_premium {
+        "premium_voice_mode"
+    } else {
+        "voice_mode"
+    };
+
+    data.userinfo_db
+        .set_one(ctx.author().id.into(), key, mode)
+        .await?;
 
     let response = if let Some(mode) = mode {
         &aformat!("Set your voice mode to: {mode}")
@@ -1123,11 +2223,9 @@ pub async fn openai_model(
     let guild_id = ctx.guild_id().unwrap();
 
     // Get current mode to check if user is using OpenAI
-    let (_, current_mode, _, _) = data
-        .parse_user_or_guild_with_premium(ctx.author().id, Some((guild_id, data.is_premium_simple(ctx.http(), guild_id).await?)))
-        .await?;
+    let current_settings = author_voice_settings(&ctx).await?;
 
-    if current_mode != TTSMode::OpenAI {
+    if current_settings.mode != TTSMode::OpenAI {
         ctx.say("You need to set your TTS mode to OpenAI first using `/set mode OpenAI TTS (high quality)` before changing OpenAI models.").await?;
         return Ok(());
     }
@@ -1156,11 +2254,24 @@ pub async fn openai_model(
         .set_one(user_voice_key, "openai_model", model)
         .await?;
 
-    if let Some(model) = model {
-        ctx.say(&format!("Set your OpenAI TTS model to: {}", model.as_str())).await?;
+    let mut response = if let Some(model) = model {
+        format!("Set your OpenAI TTS model to: {}", model.as_str())
     } else {
-        ctx.say("Reset your OpenAI TTS model to default (tts-1-hd)").await?;
+        String::from("Reset your OpenAI TTS model to default (tts-1-hd)")
     };
+
+    let info = model.unwrap_or_default().info();
+    if !info.supports_voice(&current_settings.voice) {
+        let default_voice = TTSMode::OpenAI.default_voice();
+        let voice = &current_settings.voice;
+        write!(response, "\n`{voice}` isn't available with this model, so `{default_voice}` will be used instead.")?;
+    }
+
+    if current_settings.instruction.is_some() && !info.supports_instructions {
+        response.push_str("\nThis model doesn't support instructions, so your TTS instruction will be ignored.");
+    }
+
+    ctx.say(response).await?;
     Ok(())
 }
 
@@ -1178,18 +2289,21 @@ pub async fn instruction(
     #[description = "The instruction for TTS speech style (max 500 chars), leave blank to clear"] instruction: Option<String>,
 ) -> CommandResult {
     let data = ctx.data();
-    let guild_id = ctx.guild_id().unwrap();
 
     // Get current mode to check if user is using OpenAI
-    let (_, current_mode, _, _) = data
-        .parse_user_or_guild_with_premium(ctx.author().id, Some((guild_id, data.is_premium_simple(ctx.http(), guild_id).await?)))
-        .await?;
+    let current_settings = author_voice_settings(&ctx).await?;
 
-    if current_mode != TTSMode::OpenAI {
+    if current_settings.mode != TTSMode::OpenAI {
         ctx.say("You need to set your TTS mode to OpenAI first using `/set mode OpenAI TTS (high quality)` before setting TTS instructions.").await?;
         return Ok(());
     }
 
+    if instruction.is_some() && !current_settings.openai_model.info().supports_instructions {
+        let model = current_settings.openai_model.as_str();
+        ctx.say(format!("The {model} model doesn't support instructions, change your model with `/set openai_model gpt-4o-mini-tts` first.")).await?;
+        return Ok(());
+    }
+
     // Validate instruction length
     if let Some(ref instruction) = instruction {
         if instruction.len() > 500 {
@@ -1318,14 +2432,12 @@ pub async fn voices(
     >,
 ) -> CommandResult {
     let data = ctx.data();
-    let http = ctx.http();
     let cache = ctx.cache();
     let author = ctx.author();
-    let guild_id = ctx.guild_id();
 
     let mode = match mode {
         Some(mode) => TTSMode::from(mode),
-        None => data.parse_user_or_guild(http, author.id, guild_id).await?.1,
+        None => author_voice_settings(&ctx).await?.mode,
     };
 
     let voices = {
@@ -1383,12 +2495,14 @@ pub async fn voices(
     Ok(())
 }
 
-async fn list_polly_voices(ctx: &Context<'_>) -> Result<(String, Vec<String>)> {
+async fn list_polly_voices(ctx: &Context<'_>) -> Result<(String, Vec<VoicePage>)> {
     let data = ctx.data();
 
-    let (voice_id, mode, _, _) = data
-        .parse_user_or_guild(ctx.http(), ctx.author().id, ctx.guild_id())
-        .await?;
+    let VoiceSettings {
+        voice: voice_id,
+        mode,
+        ..
+    } = author_voice_settings(ctx).await?;
     let voice = match mode {
         TTSMode::Polly => {
             let voice_id: &str = &voice_id;
@@ -1408,16 +2522,20 @@ async fn list_polly_voices(ctx: &Context<'_>) -> Result<(String, Vec<String>)> {
     let pages = lang_to_voices
         .into_values()
         .map(|voices| {
-            let mut buf = String::with_capacity(voices.len() * 12);
-            for voice in voices {
+            let mut description = String::with_capacity(voices.len() * 12);
+            for voice in &voices {
                 writeln!(
-                    buf,
+                    description,
                     "{} - {} ({})",
                     voice.id, voice.language_name, voice.gender
                 )?;
             }
 
-            anyhow::Ok(buf)
+            let voices = voices.into_iter().map(|voice| voice.id.clone()).collect();
+            anyhow::Ok(VoicePage {
+                description,
+                voices,
+            })
         })
         .collect::<Result<_>>()?;
 
@@ -1427,12 +2545,14 @@ async fn list_polly_voices(ctx: &Context<'_>) -> Result<(String, Vec<String>)> {
     ))
 }
 
-async fn list_gcloud_voices(ctx: &Context<'_>) -> Result<(String, Vec<String>)> {
+async fn list_gcloud_voices(ctx: &Context<'_>) -> Result<(String, Vec<VoicePage>)> {
     let data = ctx.data();
 
-    let (lang_variant, mode, _, _) = data
-        .parse_user_or_guild(ctx.http(), ctx.author().id, ctx.guild_id())
-        .await?;
+    let VoiceSettings {
+        voice: lang_variant,
+        mode,
+        ..
+    } = author_voice_settings(ctx).await?;
     let (lang, variant) = match mode {
         TTSMode::gCloud => &lang_variant,
         _ => TTSMode::gCloud.default_voice(),
@@ -1444,12 +2564,17 @@ async fn list_gcloud_voices(ctx: &Context<'_>) -> Result<(String, Vec<String>)>
         .gcloud_voices
         .iter()
         .map(|(language, variants)| {
-            let mut buf = String::with_capacity(variants.len() * 12);
+            let mut description = String::with_capacity(variants.len() * 12);
+            let mut voices = Vec::with_capacity(variants.len());
             for (variant, gender) in variants {
-                writeln!(buf, "{language} {variant} ({gender})")?;
+                writeln!(description, "{language} {variant} ({gender})")?;
+                voices.push(FixedString::from_string_trunc(format!("{language} {variant}")));
             }
 
-            anyhow::Ok(buf)
+            anyhow::Ok(VoicePage {
+                description,
+                voices,
+            })
         })
         .collect::<Result<_>>()?;
 
@@ -1488,23 +2613,24 @@ pub async fn opt_out(ctx: Context<'_>, opted_out: bool) -> CommandResult {
         data.user_opt_out_db
             .set_one([user_id.into(), guild_id.into()], "opted_out", &true)
             .await?;
-        ctx.say("✅ You have opted out of TTS processing in this server. Your messages will no longer be read aloud.").await?;
+        ctx.say("✅ You have opted out of TTS processing in this server. Your messages will no longer be read aloud, and your voice will not be transcribed.").await?;
     } else {
         // Remove the opt-out entry (opting back in)
         data.user_opt_out_db
             .delete([user_id.into(), guild_id.into()])
             .await?;
-        ctx.say("✅ You have opted back into TTS processing in this server. Your messages will be read aloud again.").await?;
+        ctx.say("✅ You have opted back into TTS processing in this server. Your messages will be read aloud again, and your voice can be transcribed if you have opted in to captions.").await?;
     }
 
     Ok(())
 }
 
-pub fn commands() -> [Command; 6] {
+pub fn commands() -> [Command; 7] {
     [
         settings(),
         setup::setup(),
         voices(),
+        preview(),
         translation_languages(),
         opt_out(),
         poise::Command {
@@ -1518,15 +2644,42 @@ pub fn commands() -> [Command; 6] {
                 required_role(),
                 voice(),
                 server_voice(),
+                role_voice(),
+                channel_voice(),
                 mode(),
                 server_mode(),
                 openai_model(),
                 instruction(),
                 msg_length(),
+                length_policy(),
+                tts_file_length(),
+                audiobook_quota(),
+                char_quota(),
+                captions(),
+                captions_opt_in(),
+                transcript_channel(),
+                transcript_threads(),
+                transcript_retention(),
+                idle_timeout(),
+                lonely_leave_delay(),
+                follow(),
+                join_on_enter(),
+                join_on_enter_channel(),
+                announce_joins(),
+                announce_leaves(),
+                greetings(),
+                greeting(),
+                block_word(),
+                unblock_word(),
                 botignore(),
                 translation(),
                 translation_lang(),
                 speaking_rate(),
+                volume(),
+                pitch(),
+                effect(),
+                master_volume(),
+                normalize_loudness(),
                 nick(),
                 repeated_characters(),
                 audience_ignore(),
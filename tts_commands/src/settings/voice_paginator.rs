@@ -1,15 +1,34 @@
 use std::borrow::Cow;
 
+use aformat::aformat;
+
 use poise::serenity_prelude as serenity;
-use serenity::{builder::*, small_fixed_array::FixedString, CollectComponentInteractions};
+use serenity::{
+    builder::*,
+    small_fixed_array::{FixedArray, FixedString},
+    CollectComponentInteractions, ComponentInteractionDataKind,
+};
+
+use tts_core::{
+    structs::{Context, Data, Result, TTSMode},
+    traits::PoiseContextExt as _,
+};
+
+use super::voice_preview::{get_preview_audio, play_preview, preview_file_name};
 
-use tts_core::structs::{Context, TTSMode};
+const PREVIEW_BUTTON: &str = "preview";
+
+pub struct VoicePage {
+    pub description: String,
+    /// The voice IDs listed on this page, used to offer previews.
+    pub voices: Vec<FixedString<u8>>,
+}
 
 pub struct MenuPaginator<'a> {
     index: usize,
     mode: TTSMode,
     ctx: Context<'a>,
-    pages: Vec<String>,
+    pages: Vec<VoicePage>,
     footer: Cow<'a, str>,
     current_voice: String,
 }
@@ -17,7 +36,7 @@ pub struct MenuPaginator<'a> {
 impl<'a> MenuPaginator<'a> {
     pub fn new(
         ctx: Context<'a>,
-        pages: Vec<String>,
+        pages: Vec<VoicePage>,
         current_voice: String,
         mode: TTSMode,
         footer: Cow<'a, str>,
@@ -44,6 +63,15 @@ impl<'a> MenuPaginator<'a> {
             .footer(CreateEmbedFooter::new(self.footer.as_ref()))
     }
 
+    fn create_preview_row(&self, disabled: bool) -> serenity::CreateActionRow<'_> {
+        let button = CreateButton::new(PREVIEW_BUTTON)
+            .label("Preview")
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(disabled || self.pages[self.index].voices.is_empty());
+
+        serenity::CreateActionRow::Buttons(vec![button].into())
+    }
+
     fn create_action_row(&self, disabled: bool) -> serenity::CreateActionRow<'_> {
         let buttons = ["⏮️", "◀", "⏹️", "▶️", "⏭️"]
             .into_iter()
@@ -66,9 +94,9 @@ impl<'a> MenuPaginator<'a> {
     }
 
     async fn create_message(&self) -> serenity::Result<serenity::MessageId> {
-        let components = [self.create_action_row(false)];
+        let components = [self.create_action_row(false), self.create_preview_row(false)];
         let builder = poise::CreateReply::default()
-            .embed(self.create_page(&self.pages[self.index]))
+            .embed(self.create_page(&self.pages[self.index].description))
             .components(&components);
 
         self.ctx.send(builder).await?.message().await.map(|m| m.id)
@@ -82,14 +110,29 @@ impl<'a> MenuPaginator<'a> {
         let http = self.ctx.http();
         let channel_id = self.ctx.channel_id();
 
-        let components = [self.create_action_row(disable)];
+        let components = [
+            self.create_action_row(disable),
+            self.create_preview_row(disable),
+        ];
         let builder = EditMessage::default()
-            .embed(self.create_page(&self.pages[self.index]))
+            .embed(self.create_page(&self.pages[self.index].description))
             .components(&components);
 
         Ok(channel_id.edit_message(http, message, builder).await?.id)
     }
 
+    fn create_preview(&self) -> Preview {
+        Preview {
+            ctx: self.ctx.serenity_context().clone(),
+            author_id: self.ctx.author().id,
+            channel_id: self.ctx.channel_id(),
+            guild_id: self.ctx.guild_id(),
+            author_vc: self.ctx.author_vc(),
+            mode: self.mode,
+            voices: self.pages[self.index].voices.clone(),
+        }
+    }
+
     pub async fn start(mut self) -> serenity::Result<()> {
         let mut message_id = self.create_message().await?;
         let serenity_context = self.ctx.serenity_context();
@@ -125,9 +168,102 @@ impl<'a> MenuPaginator<'a> {
                     self.index = self.pages.len() - 1;
                     self.edit_message(message_id, false).await?
                 }
+                PREVIEW_BUTTON => {
+                    // Synthesis can take a while, so is run separately from the page buttons.
+                    let preview = self.create_preview();
+                    tokio::spawn(async move {
+                        if let Err(err) = preview.run(&interaction).await {
+                            tracing::warn!("Failed to preview voice: {err:?}");
+                        }
+                    });
+
+                    continue;
+                }
                 _ => unreachable!(),
             };
             interaction.defer(&serenity_context.http).await?;
         }
     }
 }
+
+/// Everything needed to preview a voice from the current page, without borrowing the paginator.
+struct Preview {
+    ctx: serenity::Context,
+    author_id: serenity::UserId,
+    channel_id: serenity::GenericChannelId,
+    guild_id: Option<serenity::GuildId>,
+    author_vc: Option<serenity::ChannelId>,
+    mode: TTSMode,
+    voices: Vec<FixedString<u8>>,
+}
+
+impl Preview {
+    /// Responds with an ephemeral select menu of the page's voices, then previews the chosen one.
+    async fn run(self, interaction: &serenity::ComponentInteraction) -> Result<()> {
+        let http = &self.ctx.http;
+
+        let interaction_id = interaction.id;
+        let custom_id = aformat!("preview::{interaction_id}");
+        let custom_ids = FixedArray::from_vec_trunc(vec![FixedString::from_str_trunc(&custom_id)]);
+
+        let options = self
+            .voices
+            .iter()
+            .take(25)
+            .map(|voice| CreateSelectMenuOption::new(&**voice, &**voice))
+            .collect();
+
+        let select = CreateSelectMenu::new(&*custom_id, CreateSelectMenuKind::String { options })
+            .placeholder("Select a voice to preview");
+
+        let components = [serenity::CreateActionRow::SelectMenu(select)];
+        let response = CreateInteractionResponseMessage::new()
+            .ephemeral(true)
+            .components(&components);
+
+        interaction
+            .create_response(http, CreateInteractionResponse::Message(response))
+            .await?;
+
+        let select_interaction = self
+            .channel_id
+            .collect_component_interactions(&self.ctx)
+            .timeout(std::time::Duration::from_secs(60))
+            .author_id(self.author_id)
+            .custom_ids(custom_ids)
+            .await;
+
+        let Some(select_interaction) = select_interaction else {
+            return Ok(());
+        };
+
+        let ComponentInteractionDataKind::StringSelect { values } = &select_interaction.data.kind
+        else {
+            anyhow::bail!("Expected a string value");
+        };
+
+        select_interaction.defer_ephemeral(http).await?;
+
+        let data = self.ctx.data_ref::<Data>();
+        let voice = FixedString::from_str_trunc(&values[0]);
+        let followup = CreateInteractionResponseFollowup::new().ephemeral(true);
+
+        let Some(audio) = get_preview_audio(data, self.guild_id, self.mode, &voice, None).await?
+        else {
+            let followup = followup.content("Failed to generate a preview of that voice");
+            select_interaction.create_followup(http, followup).await?;
+            return Ok(());
+        };
+
+        let followup = if play_preview(data, self.guild_id, self.author_vc, &audio).await? {
+            followup.content(format!("Playing a preview of `{voice}` in your voice channel!"))
+        } else {
+            let attachment =
+                CreateAttachment::bytes(audio.to_vec(), preview_file_name(self.mode, &voice));
+            followup.add_file(attachment)
+        };
+
+        select_interaction.create_followup(http, followup).await?;
+        Ok(())
+    }
+}
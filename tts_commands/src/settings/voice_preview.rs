@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use poise::serenity_prelude as serenity;
+use serenity::{builder::*, small_fixed_array::FixedString};
+
+use tts_core::{
+    audio::encode::AudioFormat,
+    common::{synthesize, Synthesis, SynthesisRequest},
+    structs::{CommandResult, Context, Data, OpenAIModel, Result, TTSMode, TTSModeChoice},
+    traits::PoiseContextExt as _,
+};
+
+use super::{can_change_mode, check_valid_voice, voice_autocomplete};
+
+const PREVIEW_SAMPLE: &str = "Hello! This is how I will sound when reading out your messages.";
+const PREVIEW_MAX_LENGTH: &str = "20";
+
+/// Fetches a preview of `voice`, using the cached sample if no custom text is given.
+///
+/// Returns `None` if the audio could not be generated, or the backend is too busy.
+pub async fn get_preview_audio(
+    data: &Data,
+    guild_id: Option<serenity::GuildId>,
+    mode: TTSMode,
+    voice: &FixedString<u8>,
+    text: Option<&str>,
+) -> Result<Option<Arc<[u8]>>> {
+    let voice = if mode == TTSMode::OpenAI {
+        FixedString::from_string_trunc(voice.to_lowercase())
+    } else {
+        voice.clone()
+    };
+
+    let cache_key = (mode, voice);
+    if text.is_none()
+        && let Some(audio) = data.voice_preview_cache.get(&cache_key)
+    {
+        return Ok(Some(audio));
+    }
+
+    let speaking_rate = mode.speaking_rate_info().map_or("1.0", |info| info.default);
+    let request = SynthesisRequest {
+        content: text.unwrap_or(PREVIEW_SAMPLE),
+        voice: &cache_key.1,
+        mode,
+        speaking_rate,
+        openai_model: OpenAIModel::default(),
+        instruction: None,
+        max_length: PREVIEW_MAX_LENGTH,
+        translation_lang: None,
+        format: AudioFormat::Mp3,
+    };
+
+    let Synthesis::Done(Some(audio)) = synthesize(data, request, guild_id, false).await? else {
+        return Ok(None);
+    };
+
+    let audio: Arc<[u8]> = audio.bytes.into();
+    if text.is_none() {
+        data.voice_preview_cache.insert(cache_key, audio.clone());
+    }
+
+    Ok(Some(audio))
+}
+
+/// Plays `audio` if the bot is already in the same voice channel as `author_vc`.
+pub async fn play_preview(
+    data: &Data,
+    guild_id: Option<serenity::GuildId>,
+    author_vc: Option<serenity::ChannelId>,
+    audio: &[u8],
+) -> Result<bool> {
+    let (Some(guild_id), Some(author_vc)) = (guild_id, author_vc) else {
+        return Ok(false);
+    };
+
+    let Some(call_lock) = data.songbird.get(guild_id) else {
+        return Ok(false);
+    };
+
+    let mut call = call_lock.lock().await;
+    if call.current_channel() != Some(author_vc.into()) {
+        return Ok(false);
+    }
+
+    call.enqueue_input(songbird::input::Input::from(audio.to_vec()))
+        .await;
+
+    Ok(true)
+}
+
+pub fn preview_file_name(mode: TTSMode, voice: &str) -> String {
+    let extension = match mode {
+        TTSMode::gTTS | TTSMode::gCloud | TTSMode::Polly | TTSMode::OpenAI => "mp3",
+        TTSMode::eSpeak => "wav",
+    };
+
+    let voice: String = voice.chars().filter(|c| c.is_alphanumeric()).collect();
+    format!("{mode}-{voice}-preview.{extension}")
+}
+
+/// Plays or sends a sample of what a voice sounds like
+#[poise::command(
+    category = "Settings",
+    prefix_command,
+    slash_command,
+    required_bot_permissions = "SEND_MESSAGES | ATTACH_FILES",
+    aliases("sample", "try")
+)]
+pub async fn preview(
+    ctx: Context<'_>,
+    #[description = "The mode of the voice to preview"] mode: TTSModeChoice,
+    #[description = "The voice to preview"]
+    #[autocomplete = "voice_autocomplete"]
+    voice: FixedString<u8>,
+    #[description = "The text to read, leave blank for a sample sentence"]
+    #[rest]
+    text: Option<String>,
+) -> CommandResult {
+    let data = ctx.data();
+    let mode = TTSMode::from(mode);
+
+    let guild_is_premium = match ctx.guild_id() {
+        Some(guild_id) => data.is_premium_simple(ctx.http(), guild_id).await?,
+        None => false,
+    };
+
+    if !can_change_mode(&ctx, Some(mode), guild_is_premium).await? {
+        return Ok(());
+    }
+
+    if !check_valid_voice(&data, &voice, mode) {
+        ctx.send_error("Invalid voice, do `/voices` to see the available voices")
+            .await?;
+        return Ok(());
+    }
+
+    ctx.defer_or_broadcast().await?;
+    let audio = get_preview_audio(&data, ctx.guild_id(), mode, &voice, text.as_deref()).await?;
+    let Some(audio) = audio else {
+        ctx.send_error("Failed to generate a preview of that voice").await?;
+        return Ok(());
+    };
+
+    if play_preview(&data, ctx.guild_id(), ctx.author_vc(), &audio).await? {
+        ctx.say(format!("Playing a preview of `{voice}` in your voice channel!"))
+            .await?;
+        return Ok(());
+    }
+
+    let attachment = CreateAttachment::bytes(audio.to_vec(), preview_file_name(mode, &voice));
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!("Here is a preview of `{voice}`!"))
+            .attachment(attachment),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Compares the per-track cost of MP3 audio, which songbird has to decode, resample and
+//! re-encode to Opus, against Ogg/Opus audio which it can pass straight through to Discord.
+//!
+//! Both paths are measured on real tts-service output for the same message, so the
+//! results reflect what `preferred_format=ogg` saves. Fetch the samples with:
+//!
+//! ```sh
+//! curl "$TTS_SERVICE/tts?text=...&mode=gCloud&preferred_format=mp3" -o sample.mp3
+//! curl "$TTS_SERVICE/tts?text=...&mode=gCloud&preferred_format=ogg" -o sample.ogg
+//! ```
+//!
+//! Then run with `PLAYBACK_BENCH_SAMPLES=<dir> cargo bench -p tts_core` on a nightly toolchain.
+#![feature(test)]
+extern crate test;
+
+use std::{io::Cursor, path::PathBuf};
+
+use songbird::driver::opus::{coder::Encoder, Application, Channels, SampleRate};
+use symphonia::core::{
+    codecs::CODEC_TYPE_OPUS,
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::{MediaSourceStream, MediaSourceStreamOptions},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use test::Bencher;
+
+use tts_core::audio::DecodedAudio;
+
+/// songbird's mixer always runs at 48kHz stereo, in 20ms frames.
+const MIXER_RATE: u32 = 48000;
+const MIXER_FRAME: usize = MIXER_RATE as usize / 50;
+
+fn read_sample(name: &str) -> Vec<u8> {
+    let dir = std::env::var_os("PLAYBACK_BENCH_SAMPLES")
+        .expect("PLAYBACK_BENCH_SAMPLES should point to a directory of tts-service samples");
+
+    let path = PathBuf::from(dir).join(name);
+    std::fs::read(&path).unwrap_or_else(|err| panic!("Could not read {}: {err}", path.display()))
+}
+
+fn probe(bytes: &[u8], extension: &str) -> Box<dyn FormatReader> {
+    let source = Box::new(Cursor::new(bytes.to_vec()));
+    let stream = MediaSourceStream::new(source, MediaSourceStreamOptions::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+
+    songbird::input::codecs::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .unwrap()
+        .format
+}
+
+/// The path for MP3 audio: decode, resample to the mixer's layout, then encode to Opus.
+#[bench]
+fn mp3_transcode(b: &mut Bencher) {
+    let mp3 = read_sample("sample.mp3");
+
+    let mut hint = Hint::new();
+    hint.with_extension("mp3");
+
+    b.iter(|| {
+        let decoded = DecodedAudio::decode(&mp3, Some(&hint)).unwrap();
+
+        let mut mixed = DecodedAudio {
+            samples: Vec::new(),
+            sample_rate: MIXER_RATE,
+            channels: 2,
+        };
+        mixed.append(&decoded);
+
+        let mut encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).unwrap();
+
+        let mut packet = [0; 4000];
+        let mut encoded = 0;
+        for frame in mixed.samples.chunks_exact(MIXER_FRAME * 2) {
+            encoded += encoder.encode_float(frame, &mut packet).unwrap();
+        }
+
+        encoded
+    });
+}
+
+/// The path for Ogg/Opus audio: packets are read out of the container and sent as-is.
+#[bench]
+fn opus_passthrough(b: &mut Bencher) {
+    let ogg = read_sample("sample.ogg");
+
+    let format = probe(&ogg, "ogg");
+    let codec = format.default_track().unwrap().codec_params.codec;
+    assert_eq!(
+        codec, CODEC_TYPE_OPUS,
+        "sample.ogg should contain Opus audio"
+    );
+
+    b.iter(|| {
+        let mut format = probe(&ogg, "ogg");
+        let mut packets = 0;
+        loop {
+            match format.next_packet() {
+                Ok(_) => packets += 1,
+                Err(SymphoniaError::IoError(_)) => break packets,
+                Err(err) => panic!("{err}"),
+            }
+        }
+    });
+}
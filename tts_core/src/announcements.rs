@@ -0,0 +1,175 @@
+//! Reading out when members join or leave the bot's voice channel, for members who
+//! cannot see the member list such as screen reader users, along with the personal
+//! greetings members can set.
+use std::time::{Duration, Instant};
+
+use aformat::ToArrayString as _;
+use dashmap::DashMap;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId};
+
+use crate::{
+    audio::encode::AudioFormat,
+    common::{synthesize, Synthesis, SynthesisRequest, SynthesizedAudio},
+    structs::{Data, IsPremium, Result, VoiceScope},
+    usage,
+};
+
+/// How many announcements can be made in a guild within [`WINDOW`].
+const MAX_PER_WINDOW: u8 = 3;
+/// The window announcements are limited within, so a whole channel moving at once
+/// doesn't read out a long list of names.
+const WINDOW: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Announcement {
+    Joined,
+    MovedIn,
+    Left,
+}
+
+impl Announcement {
+    const fn verb(self) -> &'static str {
+        match self {
+            Self::Joined => "joined",
+            Self::MovedIn => "moved in",
+            Self::Left => "left",
+        }
+    }
+}
+
+/// Limits how many announcements are made in each guild.
+#[derive(Default)]
+pub struct AnnouncementLimiter {
+    windows: DashMap<GuildId, (Instant, u8)>,
+}
+
+impl AnnouncementLimiter {
+    /// Returns if another announcement can be made in `guild_id`, counting it if so.
+    fn try_acquire(&self, guild_id: GuildId) -> bool {
+        let mut window = self
+            .windows
+            .entry(guild_id)
+            .or_insert_with(|| (Instant::now(), 0));
+
+        let (started_at, count) = &mut *window;
+        if started_at.elapsed() >= WINDOW {
+            *started_at = Instant::now();
+            *count = 0;
+        }
+
+        if *count >= MAX_PER_WINDOW {
+            return false;
+        }
+
+        *count += 1;
+        true
+    }
+}
+
+/// Reads out that `member` has joined or left `voice_channel`, if enabled in the guild.
+///
+/// Members joining with a greeting set have it read out instead, if greetings are enabled.
+pub async fn announce(
+    ctx: &serenity::Context,
+    member: &serenity::Member,
+    voice_channel: ChannelId,
+    announcement: Announcement,
+) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    let guild_id = member.guild_id;
+    let Some(call_lock) = data.songbird.get(guild_id) else {
+        return Ok(());
+    };
+
+    let (guild_row, opt_out_row, nickname_row) = tokio::try_join!(
+        data.guilds_db.get(guild_id.into()),
+        data.user_opt_out_db
+            .get([member.user.id.into(), guild_id.into()]),
+        data.nickname_db
+            .get([guild_id.into(), member.user.id.into()]),
+    )?;
+
+    let (enabled, greeting) = match announcement {
+        Announcement::Joined | Announcement::MovedIn => (
+            guild_row.announce_joins(),
+            nickname_row
+                .greeting
+                .as_deref()
+                .filter(|_| guild_row.greetings()),
+        ),
+        Announcement::Left => (guild_row.announce_leaves(), None),
+    };
+
+    if (!enabled && greeting.is_none()) || opt_out_row.opted_out {
+        return Ok(());
+    }
+
+    if !data.announcement_limiter.try_acquire(guild_id) {
+        return Ok(());
+    }
+
+    let mut content = if let Some(greeting) = greeting {
+        greeting.to_owned()
+    } else {
+        let name = nickname_row
+            .name
+            .as_deref()
+            .or(member.nick.as_deref())
+            .or(member.user.global_name.as_deref())
+            .unwrap_or(&member.user.name);
+
+        format!("{name} {}", announcement.verb())
+    };
+
+    // Words may have been blocked since the greeting or nickname was set.
+    if let Some(blocked_words) = data.word_filter.get(&data.pool, guild_id).await? {
+        content = blocked_words.replace_all(&content, "").into_owned();
+        if content.trim().is_empty() {
+            return Ok(());
+        }
+    }
+
+    // The bot has no voice settings of its own, so this is the voice set for the
+    // guild or the voice channel.
+    let bot_id = ctx.cache.current_user().id;
+    let is_premium = data.is_premium_simple(&ctx.http, guild_id).await?;
+    let scope = VoiceScope {
+        roles: &[],
+        channel_id: Some(voice_channel.widen()),
+    };
+
+    let mut settings = data
+        .parse_user_or_guild_with_premium(bot_id, Some((guild_id, is_premium)), scope)
+        .await?;
+
+    // Announcements are counted towards the quota of the member being announced.
+    let user_id = member.user.id;
+    usage::enforce_quota(data, guild_id, user_id, is_premium, &mut settings).await?;
+
+    let request = SynthesisRequest {
+        content: &content,
+        voice: &settings.voice,
+        mode: settings.mode,
+        speaking_rate: &settings.speaking_rate,
+        openai_model: settings.openai_model,
+        instruction: settings.instruction.as_deref(),
+        max_length: &guild_row.msg_length.to_arraystring(),
+        translation_lang: guild_row.target_lang(IsPremium::from(is_premium)),
+        format: AudioFormat::OggOpus,
+    };
+
+    let synthesis = synthesize(data, request, Some(guild_id), is_premium).await?;
+    let Synthesis::Done(Some(SynthesizedAudio { bytes, .. })) = synthesis else {
+        return Ok(());
+    };
+
+    let track = songbird::tracks::Track::new(songbird::input::Input::from(bytes))
+        .volume(f32::from(guild_row.master_volume) / 100.0);
+
+    call_lock.lock().await.enqueue(track).await;
+
+    usage::record(data, guild_id, user_id, settings.mode, content.chars().count()).await?;
+    data.analytics.log("voice_announcement".into(), false);
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use anyhow::{bail, ensure};
+
+use super::DecodedAudio;
+use crate::structs::{AudioEffect, Result};
+
+/// Applies `effect` in place, leaving the audio untouched if the effect fails.
+pub fn apply(audio: &mut DecodedAudio, effect: AudioEffect) {
+    let result = match effect {
+        AudioEffect::None => return,
+        AudioEffect::Echo => echo(audio),
+        AudioEffect::Robot => robot(audio),
+        AudioEffect::Telephone => telephone(audio),
+        AudioEffect::Reverb => reverb(audio),
+    };
+
+    match result.and_then(|samples| check_output(samples, audio.channels)) {
+        Ok(samples) => audio.samples = samples,
+        Err(err) => tracing::warn!("Bypassing {effect} effect: {err:?}"),
+    }
+}
+
+fn check_output(samples: Vec<f32>, channels: u16) -> Result<Vec<f32>> {
+    ensure!(
+        samples.len() % channels as usize == 0,
+        "effect output is not aligned to frames"
+    );
+
+    if let Some(sample) = samples.iter().find(|s| !s.is_finite()) {
+        bail!("effect output contained {sample}");
+    }
+
+    Ok(samples)
+}
+
+fn ms_to_frames(sample_rate: u32, ms: u32) -> usize {
+    (sample_rate as usize * ms as usize) / 1000
+}
+
+/// A single feedback delay, mixed over the dry signal.
+fn echo(audio: &DecodedAudio) -> Result<Vec<f32>> {
+    const DELAY_MS: u32 = 250;
+    const FEEDBACK: f32 = 0.35;
+    const REPEATS: usize = 4;
+
+    let channels = audio.channels as usize;
+    let delay = ms_to_frames(audio.sample_rate, DELAY_MS) * channels;
+    ensure!(delay != 0, "sample rate too low for echo");
+
+    let mut output = audio.samples.clone();
+    output.resize(audio.samples.len() + delay * REPEATS, 0.0);
+
+    for i in delay..output.len() {
+        output[i] += output[i - delay] * FEEDBACK;
+    }
+
+    for sample in &mut output {
+        *sample = sample.clamp(-1.0, 1.0);
+    }
+
+    Ok(output)
+}
+
+/// Ring modulation followed by bit and sample rate reduction.
+fn robot(audio: &DecodedAudio) -> Result<Vec<f32>> {
+    const CARRIER_HZ: f32 = 50.0;
+    const BIT_DEPTH: i32 = 6;
+    const HOLD_FRAMES: usize = 4;
+
+    let channels = audio.channels as usize;
+    let levels = 2_f32.powi(BIT_DEPTH - 1);
+    let step = std::f32::consts::TAU * CARRIER_HZ / audio.sample_rate as f32;
+
+    let mut output = Vec::with_capacity(audio.samples.len());
+    for frame in 0..audio.samples.len() / channels {
+        let held = frame - (frame % HOLD_FRAMES);
+        let carrier = (held as f32 * step).sin();
+
+        for channel in 0..channels {
+            let sample = audio.samples[held * channels + channel] * carrier;
+            output.push((sample * levels).round() / levels);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Coefficients for a biquad filter, from the RBJ audio EQ cookbook.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn new(sample_rate: u32, cutoff: f32, high_pass: bool) -> Self {
+        let omega = std::f32::consts::TAU * cutoff / sample_rate as f32;
+        let alpha = omega.sin() / std::f32::consts::SQRT_2;
+        let cos = omega.cos();
+
+        let (b0, b1, b2) = if high_pass {
+            ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0)
+        } else {
+            ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0)
+        };
+
+        let a0 = 1.0 + alpha;
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: (-2.0 * cos) / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    fn process(self, samples: &mut [f32], channels: usize) {
+        for channel in 0..channels {
+            let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+            for sample in samples.iter_mut().skip(channel).step_by(channels) {
+                let x0 = *sample;
+                let y0 = self.b0 * x0 + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+
+                (x2, x1) = (x1, x0);
+                (y2, y1) = (y1, y0);
+                *sample = y0;
+            }
+        }
+    }
+}
+
+/// Band-passes to the range of a phone line, with some light distortion.
+fn telephone(audio: &DecodedAudio) -> Result<Vec<f32>> {
+    const LOW_CUTOFF: f32 = 300.0;
+    const HIGH_CUTOFF: f32 = 3400.0;
+    const DRIVE: f32 = 2.0;
+
+    ensure!(
+        audio.sample_rate as f32 > HIGH_CUTOFF * 2.0,
+        "sample rate too low for telephone"
+    );
+
+    let channels = audio.channels as usize;
+    let mut output = audio.samples.clone();
+    Biquad::new(audio.sample_rate, LOW_CUTOFF, true).process(&mut output, channels);
+    Biquad::new(audio.sample_rate, HIGH_CUTOFF, false).process(&mut output, channels);
+
+    for sample in &mut output {
+        *sample = (*sample * DRIVE).tanh() / DRIVE.tanh();
+    }
+
+    Ok(output)
+}
+
+/// A Schroeder reverb, four parallel comb filters into two series all-pass filters.
+fn reverb(audio: &DecodedAudio) -> Result<Vec<f32>> {
+    const COMB_DELAYS_MS: [u32; 4] = [30, 34, 39, 45];
+    const ALLPASS_DELAYS_MS: [u32; 2] = [5, 2];
+    const COMB_FEEDBACK: f32 = 0.8;
+    const ALLPASS_GAIN: f32 = 0.7;
+    const WET: f32 = 0.3;
+    const TAIL_MS: u32 = 1000;
+
+    let channels = audio.channels as usize;
+    let frames = audio.samples.len() / channels + ms_to_frames(audio.sample_rate, TAIL_MS);
+
+    let mut output = vec![0.0; frames * channels];
+    for channel in 0..channels {
+        let dry: Vec<f32> = (0..frames)
+            .map(|i| audio.samples.get(i * channels + channel).copied().unwrap_or(0.0))
+            .collect();
+
+        let mut wet = vec![0.0; frames];
+        for delay_ms in COMB_DELAYS_MS {
+            let delay = ms_to_frames(audio.sample_rate, delay_ms);
+            ensure!(delay != 0, "sample rate too low for reverb");
+
+            let mut comb = vec![0.0; frames];
+            for i in 0..frames {
+                let feedback = if i >= delay { comb[i - delay] } else { 0.0 };
+                comb[i] = dry[i] + feedback * COMB_FEEDBACK;
+                wet[i] += comb[i] / COMB_DELAYS_MS.len() as f32;
+            }
+        }
+
+        for delay_ms in ALLPASS_DELAYS_MS {
+            let delay = ms_to_frames(audio.sample_rate, delay_ms);
+            ensure!(delay != 0, "sample rate too low for reverb");
+
+            let input = wet.clone();
+            for i in 0..frames {
+                let delayed_in = if i >= delay { input[i - delay] } else { 0.0 };
+                let delayed_out = if i >= delay { wet[i - delay] } else { 0.0 };
+                wet[i] = -ALLPASS_GAIN * input[i] + delayed_in + ALLPASS_GAIN * delayed_out;
+            }
+        }
+
+        for (i, (dry, wet)) in dry.into_iter().zip(wet).enumerate() {
+            output[i * channels + channel] = (dry * (1.0 - WET) + wet * WET).clamp(-1.0, 1.0);
+        }
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,448 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use songbird::driver::opus::{coder::Encoder, Application, Channels, SampleRate};
+use symphonia::core::probe::Hint;
+
+use super::DecodedAudio;
+use crate::structs::{Result, TTSMode};
+
+#[derive(poise::ChoiceParameter, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum AudioFormat {
+    #[name = "MP3"]
+    #[name = "mp3"]
+    Mp3,
+    #[name = "Ogg (Opus)"]
+    #[name = "ogg"]
+    #[name = "opus"]
+    OggOpus,
+    #[name = "WAV"]
+    #[name = "wav"]
+    Wav,
+    #[name = "FLAC"]
+    #[name = "flac"]
+    Flac,
+}
+
+impl AudioFormat {
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::OggOpus => "ogg",
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+        }
+    }
+
+    /// The format of audio served as `content_type`, if it is one which is understood.
+    #[must_use]
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime {
+            "audio/mpeg" | "audio/mp3" => Some(Self::Mp3),
+            "audio/ogg" | "audio/opus" => Some(Self::OggOpus),
+            "audio/wav" | "audio/wave" | "audio/x-wav" => Some(Self::Wav),
+            "audio/flac" | "audio/x-flac" => Some(Self::Flac),
+            _ => None,
+        }
+    }
+}
+
+/// The formats each backend can generate directly, in order of preference.
+#[must_use]
+pub const fn supported_formats(mode: TTSMode) -> &'static [AudioFormat] {
+    match mode {
+        TTSMode::OpenAI => &[
+            AudioFormat::OggOpus,
+            AudioFormat::Mp3,
+            AudioFormat::Flac,
+            AudioFormat::Wav,
+        ],
+        TTSMode::gCloud => &[AudioFormat::OggOpus, AudioFormat::Mp3],
+        TTSMode::gTTS | TTSMode::Polly => &[AudioFormat::Mp3],
+        TTSMode::eSpeak => &[AudioFormat::Wav],
+    }
+}
+
+/// Picks the format to request from the backend for `mode`.
+///
+/// This is `preferred` if the backend supports it, otherwise the backend's default.
+#[must_use]
+pub fn negotiate(mode: TTSMode, preferred: AudioFormat) -> AudioFormat {
+    let supported = supported_formats(mode);
+    if supported.contains(&preferred) {
+        preferred
+    } else {
+        supported[0]
+    }
+}
+
+/// Converts `audio` to `format`, if an encoder is available for it.
+pub fn encode(audio: &DecodedAudio, format: AudioFormat) -> Result<Vec<u8>> {
+    match format {
+        AudioFormat::Wav => Ok(audio.to_wav()),
+        AudioFormat::Flac => Ok(encode_flac(audio)),
+        AudioFormat::OggOpus => encode_ogg_opus(audio, &[]),
+        AudioFormat::Mp3 => bail!("MP3 encoding is not supported"),
+    }
+}
+
+/// Converts `bytes` from `source` to `format`, cutting it down to `max_seconds`.
+///
+/// The original bytes are returned if no conversion or cutting is needed, and as MP3
+/// cannot be encoded, re-encoded audio requested as MP3 is returned as WAV instead.
+pub fn transcode(
+    bytes: Vec<u8>,
+    hint: Option<&Hint>,
+    source: AudioFormat,
+    format: AudioFormat,
+    max_seconds: u32,
+) -> Result<(Vec<u8>, AudioFormat)> {
+    let mut audio = DecodedAudio::decode(&bytes, hint)?;
+
+    let max_length = Duration::from_secs(max_seconds.into());
+    let needs_truncate = audio.duration() > max_length;
+    if source == format && !needs_truncate {
+        return Ok((bytes, format));
+    }
+
+    audio.truncate_with_fade(max_length);
+    let format = if format == AudioFormat::Mp3 { AudioFormat::Wav } else { format };
+    Ok((encode(&audio, format)?, format))
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 == 0 { crc << 1 } else { (crc << 1) ^ 0x07 };
+        }
+
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, &byte| {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 == 0 { crc << 1 } else { (crc << 1) ^ 0x8005 };
+        }
+
+        crc
+    })
+}
+
+/// Encodes `value` in the UTF-8 like scheme FLAC uses for frame numbers.
+fn push_flac_utf8(buf: &mut Vec<u8>, value: u32) {
+    if value < 0x80 {
+        buf.push(value as u8);
+        return;
+    }
+
+    let mut continuation = Vec::new();
+    let mut remaining = value;
+    let mut first_byte_bits = 6;
+    while remaining >= (1 << first_byte_bits) {
+        continuation.push(0x80 | (remaining & 0x3F) as u8);
+        remaining >>= 6;
+        first_byte_bits -= 1;
+    }
+
+    let length = continuation.len() + 1;
+    let prefix = !(0xFF_u8 >> length);
+    buf.push(prefix | remaining as u8);
+    buf.extend(continuation.into_iter().rev());
+}
+
+/// Encodes 16-bit FLAC using verbatim subframes.
+///
+/// This doesn't compress, but is lossless and needs no external encoder.
+#[must_use]
+pub fn encode_flac(audio: &DecodedAudio) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 4096;
+
+    let channels = audio.channels.clamp(1, 8);
+    let channel_count = channels as usize;
+    let total_frames = (audio.samples.len() / audio.channels as usize) as u64;
+
+    let mut flac = Vec::with_capacity(audio.samples.len() * 2 + 64);
+    flac.extend_from_slice(b"fLaC");
+
+    // STREAMINFO, marked as the last metadata block.
+    flac.extend_from_slice(&[0x80, 0, 0, 34]);
+    flac.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    flac.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    flac.extend_from_slice(&[0; 6]); // Unknown min and max frame sizes
+
+    let packed = (u64::from(audio.sample_rate) << 44)
+        | (u64::from(channels - 1) << 41)
+        | (15 << 36)
+        | (total_frames & 0xF_FFFF_FFFF);
+    flac.extend_from_slice(&packed.to_be_bytes());
+    flac.extend_from_slice(&[0; 16]); // Unknown MD5
+
+    let frame_samples = BLOCK_SIZE * audio.channels as usize;
+    for (frame_number, block) in audio.samples.chunks(frame_samples).enumerate() {
+        let block_frames = block.len() / audio.channels as usize;
+        let frame_start = flac.len();
+
+        flac.extend_from_slice(&[0xFF, 0xF8]);
+        // 16 bit block size at end of header, sample rate from STREAMINFO
+        flac.push(0b0111_0000);
+        // Independent channels, 16 bits per sample
+        flac.push(((channels as u8 - 1) << 4) | 0b1000);
+        push_flac_utf8(&mut flac, frame_number as u32);
+        flac.extend_from_slice(&(block_frames as u16 - 1).to_be_bytes());
+        flac.push(crc8(&flac[frame_start..]));
+
+        for channel in 0..channel_count {
+            // Verbatim subframe, no wasted bits
+            flac.push(0b0000_0010);
+            for sample in block.iter().skip(channel).step_by(audio.channels as usize) {
+                flac.extend_from_slice(&to_i16(*sample).to_be_bytes());
+            }
+        }
+
+        let crc = crc16(&flac[frame_start..]);
+        flac.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    flac
+}
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |mut crc, &byte| {
+        crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 == 0 { crc << 1 } else { (crc << 1) ^ 0x04C1_1DB7 };
+        }
+
+        crc
+    })
+}
+
+struct OggWriter {
+    output: Vec<u8>,
+    serial: u32,
+    sequence: u32,
+}
+
+impl OggWriter {
+    /// Writes `packet` as its own page.
+    fn write_page(&mut self, packet: &[u8], granule_position: u64, header_type: u8) {
+        let page_start = self.output.len();
+
+        self.output.extend_from_slice(b"OggS");
+        self.output.push(0); // Version
+        self.output.push(header_type);
+        self.output.extend_from_slice(&granule_position.to_le_bytes());
+        self.output.extend_from_slice(&self.serial.to_le_bytes());
+        self.output.extend_from_slice(&self.sequence.to_le_bytes());
+        self.output.extend_from_slice(&[0; 4]); // CRC, filled in below
+
+        let segments = packet.len() / 255 + 1;
+        self.output.push(segments as u8);
+        self.output.extend(std::iter::repeat_n(255, segments - 1));
+        self.output.push((packet.len() % 255) as u8);
+        self.output.extend_from_slice(packet);
+
+        let crc = ogg_crc(&self.output[page_start..]);
+        self.output[page_start + 22..page_start + 26].copy_from_slice(&crc.to_le_bytes());
+        self.sequence += 1;
+    }
+}
+
+/// Formats chapter markers as Vorbis comments, as understood by most Ogg players.
+#[must_use]
+pub fn chapter_comments(chapters: &[(String, std::time::Duration)]) -> Vec<String> {
+    let mut comments = Vec::with_capacity(chapters.len() * 2);
+    for (i, (title, start)) in chapters.iter().enumerate() {
+        let number = i + 1;
+        let millis = start.as_millis();
+        let (hours, minutes) = (millis / 3_600_000, (millis / 60_000) % 60);
+        let (seconds, millis) = ((millis / 1000) % 60, millis % 1000);
+
+        comments.push(format!(
+            "CHAPTER{number:03}={hours:02}:{minutes:02}:{seconds:02}.{millis:03}"
+        ));
+        comments.push(format!("CHAPTER{number:03}NAME={title}"));
+    }
+
+    comments
+}
+
+/// Encodes to Opus in an Ogg container, resampling to 48kHz.
+///
+/// `comments` are written to the stream's tags, in `KEY=value` form.
+pub fn encode_ogg_opus(audio: &DecodedAudio, comments: &[String]) -> Result<Vec<u8>> {
+    let mut stream = OggOpusStream::new(audio.channels, audio.sample_rate)?;
+    stream.push(audio)?;
+    stream.finish(comments)
+}
+
+/// Encodes Opus in an Ogg container a piece at a time, so long audio never has to be
+/// held in memory as PCM.
+pub struct OggOpusStream {
+    encoder: Encoder,
+    /// The audio pages, which start after the two header pages written by `finish`.
+    writer: OggWriter,
+    channels: u16,
+    input_rate: u32,
+    pre_skip: u16,
+    /// Samples at 48kHz which don't yet fill a whole frame.
+    pending: Vec<f32>,
+    /// How many frames at 48kHz have been pushed, not counting padding.
+    total_frames: u64,
+    encoded_frames: u64,
+    /// The latest packet, held back so the final page can be marked as such.
+    last_packet: Option<Vec<u8>>,
+}
+
+impl OggOpusStream {
+    const OPUS_RATE: u32 = 48000;
+    const FRAME_SIZE: usize = Self::OPUS_RATE as usize / 50; // 20ms
+    const MAX_PACKET: usize = 4000;
+
+    /// Starts a stream of `channels` audio, noting `input_rate` as the original sample rate.
+    pub fn new(channels: u16, input_rate: u32) -> Result<Self> {
+        let opus_channels = match channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            _ => bail!("Opus encoding only supports mono or stereo audio"),
+        };
+
+        let encoder = Encoder::new(SampleRate::Hz48000, opus_channels, Application::Voip)?;
+        let pre_skip = encoder.lookahead()? as u16;
+
+        Ok(Self {
+            encoder,
+            writer: OggWriter {
+                output: Vec::new(),
+                serial: rand::random(),
+                sequence: 2,
+            },
+            channels,
+            input_rate,
+            pre_skip,
+            pending: Vec::new(),
+            total_frames: 0,
+            encoded_frames: 0,
+            last_packet: None,
+        })
+    }
+
+    /// Encodes `audio`, converting it to 48kHz and the stream's channel count.
+    pub fn push(&mut self, audio: &DecodedAudio) -> Result<()> {
+        let mut converted = DecodedAudio {
+            samples: Vec::new(),
+            sample_rate: Self::OPUS_RATE,
+            channels: self.channels,
+        };
+
+        converted.append(audio);
+        self.push_samples(&converted.samples)
+    }
+
+    pub fn push_silence(&mut self, ms: u32) -> Result<()> {
+        let frames = Self::OPUS_RATE as usize * ms as usize / 1000;
+        self.push_samples(&vec![0.0; frames * self.channels as usize])
+    }
+
+    fn push_samples(&mut self, samples: &[f32]) -> Result<()> {
+        let channels = self.channels as usize;
+        self.total_frames += (samples.len() / channels) as u64;
+        self.pending.extend_from_slice(samples);
+
+        let frame_len = Self::FRAME_SIZE * channels;
+        let whole_frames = self.pending.len() / frame_len * frame_len;
+        let pending = std::mem::take(&mut self.pending);
+        for frame in pending[..whole_frames].chunks_exact(frame_len) {
+            self.encode_frame(frame)?;
+        }
+
+        self.pending = pending[whole_frames..].to_vec();
+        Ok(())
+    }
+
+    fn encode_frame(&mut self, frame: &[f32]) -> Result<()> {
+        let mut packet = [0; Self::MAX_PACKET];
+        let packet_len = self.encoder.encode_float(frame, &mut packet)?;
+
+        if let Some(previous) = self.last_packet.replace(packet[..packet_len].to_vec()) {
+            let granule_position = self.encoded_frames + u64::from(self.pre_skip);
+            self.writer.write_page(&previous, granule_position, 0);
+        }
+
+        self.encoded_frames += Self::FRAME_SIZE as u64;
+        Ok(())
+    }
+
+    /// How long the audio pushed so far lasts.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.total_frames as f64 / f64::from(Self::OPUS_RATE))
+    }
+
+    /// Roughly how large the encoded output is so far, in bytes.
+    #[must_use]
+    pub fn encoded_len(&self) -> usize {
+        self.writer.output.len()
+    }
+
+    /// Encodes any remaining audio, then writes the headers with `comments` in front.
+    ///
+    /// `comments` are written to the stream's tags, in `KEY=value` form.
+    pub fn finish(mut self, comments: &[String]) -> Result<Vec<u8>> {
+        if !self.pending.is_empty() {
+            // The final frame is padded with silence.
+            let mut frame = std::mem::take(&mut self.pending);
+            frame.resize(Self::FRAME_SIZE * self.channels as usize, 0.0);
+            self.encode_frame(&frame)?;
+        }
+
+        let Some(last_packet) = self.last_packet.take() else {
+            bail!("Cannot encode empty audio");
+        };
+
+        let granule_position = self.total_frames + u64::from(self.pre_skip);
+        self.writer.write_page(&last_packet, granule_position, 0x04);
+
+        let mut headers = OggWriter {
+            output: Vec::new(),
+            serial: self.writer.serial,
+            sequence: 0,
+        };
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // Version
+        head.push(self.channels as u8);
+        head.extend_from_slice(&self.pre_skip.to_le_bytes());
+        head.extend_from_slice(&self.input_rate.to_le_bytes());
+        head.extend_from_slice(&0_i16.to_le_bytes()); // Output gain
+        head.push(0); // Channel mapping family
+        headers.write_page(&head, 0, 0x02);
+
+        let vendor = env!("CARGO_PKG_NAME").as_bytes();
+        let mut tags = Vec::with_capacity(16 + vendor.len());
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            tags.extend_from_slice(comment.as_bytes());
+        }
+        headers.write_page(&tags, 0, 0);
+
+        let mut output = headers.output;
+        output.append(&mut self.writer.output);
+        Ok(output)
+    }
+}
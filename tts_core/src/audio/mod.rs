@@ -0,0 +1,411 @@
+//! Post-processing for generated TTS audio.
+//!
+//! Audio is decoded with symphonia into interleaved f32 PCM, processed, then re-encoded
+//! as 16-bit WAV for songbird to play back.
+pub mod effects;
+pub mod encode;
+pub mod stream;
+
+use std::{io::Cursor, time::Duration};
+
+use anyhow::bail;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::DecoderOptions,
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSourceStream, MediaSourceStreamOptions},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::structs::{AudioEffect, LengthPolicy, Result};
+
+/// The loudness all audio is normalized to, -20 dBFS.
+const TARGET_RMS: f32 = 0.1;
+/// Blocks quieter than -60 dBFS are ignored when measuring loudness, so pauses don't count.
+const SILENCE_GATE: f32 = 0.001;
+const MIN_GAIN: f32 = 0.1;
+const MAX_GAIN: f32 = 10.0;
+const PEAK_LIMIT: f32 = 0.99;
+
+pub const MIN_PITCH: i16 = -12;
+pub const MAX_PITCH: i16 = 12;
+pub const MIN_VOLUME: i16 = 0;
+pub const MAX_VOLUME: i16 = 200;
+
+/// How long audio fades out for when cut off at the max length.
+const FADE_OUT_MS: u32 = 500;
+
+/// The processing to apply to a TTS message before it is played.
+#[derive(Debug, Clone, Copy)]
+pub struct AudioProcessing {
+    /// Volume as a percentage.
+    pub volume: i16,
+    /// Pitch shift in semitones.
+    pub pitch: i16,
+    pub effect: AudioEffect,
+    pub normalize: bool,
+    /// The max length to enforce, for backends which cannot limit length themselves.
+    pub max_length: Option<Duration>,
+    pub length_policy: LengthPolicy,
+}
+
+impl AudioProcessing {
+    #[must_use]
+    pub fn is_noop(&self) -> bool {
+        self.volume == 100
+            && self.pitch == 0
+            && self.effect == AudioEffect::None
+            && !self.normalize
+            && self.max_length.is_none()
+    }
+}
+
+pub struct DecodedAudio {
+    /// Interleaved samples, in the range `-1.0..=1.0`.
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl DecodedAudio {
+    pub fn decode(bytes: &[u8], hint: Option<&Hint>) -> Result<Self> {
+        let source = Box::new(Cursor::new(bytes.to_vec()));
+        let stream = MediaSourceStream::new(source, MediaSourceStreamOptions::default());
+
+        let probed = songbird::input::codecs::get_probe().format(
+            hint.unwrap_or(&Hint::new()),
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+
+        let mut format = probed.format;
+        let Some(track) = format.default_track() else {
+            bail!("Audio contained no tracks");
+        };
+
+        let track_id = track.id;
+        let codecs = songbird::input::codecs::get_codec_registry();
+        let mut decoder = codecs.make(&track.codec_params, &DecoderOptions::default())?;
+
+        let mut samples = Vec::new();
+        let mut sample_rate = track.codec_params.sample_rate;
+        let mut channels = track.codec_params.channels.map(|c| c.count() as u16);
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err))
+                    if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(err)) => {
+                    tracing::debug!("Skipping undecodable packet: {err}");
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            let spec = *decoded.spec();
+            sample_rate = Some(spec.rate);
+            channels = Some(spec.channels.count() as u16);
+
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+
+        let (Some(sample_rate), Some(channels)) = (sample_rate, channels) else {
+            bail!("Could not determine audio format");
+        };
+
+        Ok(Self {
+            samples,
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// Measures the RMS loudness, ignoring silent blocks.
+    #[must_use]
+    pub fn gated_rms(&self) -> Option<f32> {
+        // 50ms blocks
+        let block_len = (self.sample_rate as usize / 20) * self.channels as usize;
+
+        let mut total = 0.0;
+        let mut counted = 0_usize;
+        for block in self.samples.chunks(block_len.max(1)) {
+            let sum: f32 = block.iter().map(|s| s * s).sum();
+            if (sum / block.len() as f32).sqrt() >= SILENCE_GATE {
+                total += sum;
+                counted += block.len();
+            }
+        }
+
+        (counted != 0).then(|| (total / counted as f32).sqrt())
+    }
+
+    fn peak(&self) -> f32 {
+        self.samples.iter().fold(0.0, |peak, s| s.abs().max(peak))
+    }
+
+    pub fn apply_gain(&mut self, gain: f32) {
+        for sample in &mut self.samples {
+            *sample = (*sample * gain).clamp(-1.0, 1.0);
+        }
+    }
+
+    /// Applies gain to bring the audio to [`TARGET_RMS`], without clipping.
+    pub fn normalize_loudness(&mut self) {
+        let Some(rms) = self.gated_rms() else { return };
+
+        let mut gain = (TARGET_RMS / rms).clamp(MIN_GAIN, MAX_GAIN);
+        let peak = self.peak();
+        if peak * gain > PEAK_LIMIT {
+            gain = PEAK_LIMIT / peak;
+        }
+
+        self.apply_gain(gain);
+    }
+
+    /// Shifts the pitch by `semitones`, keeping the same duration.
+    ///
+    /// This time-stretches with overlap-add then resamples back to the original length.
+    pub fn shift_pitch(&mut self, semitones: i16) {
+        if semitones == 0 || self.samples.is_empty() {
+            return;
+        }
+
+        let factor = 2_f32.powf(f32::from(semitones) / 12.0);
+        let channels = self.channels as usize;
+
+        let mut output = Vec::new();
+        for channel in 0..channels {
+            let input: Vec<f32> = self
+                .samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .copied()
+                .collect();
+            let stretched = time_stretch(&input, factor, self.sample_rate);
+            let resampled = resample(&stretched, input.len());
+
+            if output.is_empty() {
+                output = vec![0.0; resampled.len() * channels];
+            }
+
+            for (i, sample) in resampled.into_iter().enumerate() {
+                output[i * channels + channel] = sample;
+            }
+        }
+
+        self.samples = output;
+    }
+
+    #[must_use]
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    /// Appends `other`, converting it to this audio's sample rate and channel count.
+    pub fn append(&mut self, other: &DecodedAudio) {
+        let channels = self.channels as usize;
+        let other_channels = other.channels as usize;
+        let new_frames =
+            (other.frames() as u64 * u64::from(self.sample_rate) / u64::from(other.sample_rate)) as usize;
+
+        let converted: Vec<Vec<f32>> = (0..channels)
+            .map(|channel| {
+                let source: Vec<f32> = if other_channels == channels {
+                    other.samples.iter().skip(channel).step_by(channels).copied().collect()
+                } else {
+                    // Mismatched layouts are mixed down to mono, then copied to each channel.
+                    other
+                        .samples
+                        .chunks_exact(other_channels)
+                        .map(|frame| frame.iter().sum::<f32>() / other_channels as f32)
+                        .collect()
+                };
+
+                if other.sample_rate == self.sample_rate {
+                    source
+                } else {
+                    resample(&source, new_frames)
+                }
+            })
+            .collect();
+
+        let frames = converted.first().map_or(0, Vec::len);
+        self.samples.reserve(frames * channels);
+        for frame in 0..frames {
+            for channel in &converted {
+                self.samples.push(channel[frame]);
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / f64::from(self.sample_rate))
+    }
+
+    /// Cuts the audio to `max_length`, fading out over the end if it was cut.
+    pub fn truncate_with_fade(&mut self, max_length: Duration) {
+        let channels = self.channels as usize;
+        let max_frames = (max_length.as_secs_f64() * f64::from(self.sample_rate)) as usize;
+        if self.frames() <= max_frames {
+            return;
+        }
+
+        self.samples.truncate(max_frames * channels);
+
+        let fade_frames = (self.sample_rate as usize * FADE_OUT_MS as usize / 1000).min(max_frames);
+        let fade_start = max_frames - fade_frames;
+        for (i, frame) in self.samples[fade_start * channels..].chunks_exact_mut(channels).enumerate() {
+            let gain = 1.0 - (i as f32 / fade_frames as f32);
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+    }
+
+    pub fn append_silence(&mut self, ms: u32) {
+        let frames = self.sample_rate as usize * ms as usize / 1000;
+        self.samples.resize(self.samples.len() + frames * self.channels as usize, 0.0);
+    }
+
+    #[must_use]
+    pub fn to_wav(&self) -> Vec<u8> {
+        const BITS_PER_SAMPLE: u16 = 16;
+
+        let data_len = (self.samples.len() * 2) as u32;
+        let block_align = self.channels * (BITS_PER_SAMPLE / 8);
+        let byte_rate = self.sample_rate * u32::from(block_align);
+
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16_u32.to_le_bytes());
+        wav.extend_from_slice(&1_u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&self.channels.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&byte_rate.to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        wav
+    }
+}
+
+/// Stretches `input` to `factor` times its length, without changing pitch.
+fn time_stretch(input: &[f32], factor: f32, sample_rate: u32) -> Vec<f32> {
+    // 40ms frames with 75% overlap
+    let frame_len = (sample_rate as usize / 25).max(4);
+    let analysis_hop = frame_len / 4;
+    let synthesis_hop = ((analysis_hop as f32) * factor).round().max(1.0) as usize;
+
+    let window: Vec<f32> = (0..frame_len)
+        .map(|i| {
+            let phase = std::f32::consts::TAU * i as f32 / frame_len as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect();
+
+    let frames = input.len().div_ceil(analysis_hop);
+    let output_len = frames * synthesis_hop + frame_len;
+    let mut output = vec![0.0; output_len];
+    let mut weights = vec![0.0; output_len];
+
+    for frame in 0..frames {
+        let in_start = frame * analysis_hop;
+        let out_start = frame * synthesis_hop;
+        for (i, weight) in window.iter().enumerate() {
+            let Some(sample) = input.get(in_start + i) else { break };
+            output[out_start + i] += sample * weight;
+            weights[out_start + i] += weight;
+        }
+    }
+
+    for (sample, weight) in output.iter_mut().zip(weights) {
+        if weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+
+    output.truncate((input.len() as f32 * factor) as usize);
+    output
+}
+
+/// Linearly resamples `input` to `new_len` samples.
+pub(crate) fn resample(input: &[f32], new_len: usize) -> Vec<f32> {
+    if input.is_empty() || new_len == 0 {
+        return Vec::new();
+    }
+
+    let step = input.len() as f32 / new_len as f32;
+    (0..new_len)
+        .map(|i| {
+            let position = i as f32 * step;
+            let index = position as usize;
+            let fraction = position - index as f32;
+
+            let current = input[index.min(input.len() - 1)];
+            let next = input[(index + 1).min(input.len() - 1)];
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+/// Applies the requested processing to `bytes`, returning a WAV file.
+///
+/// Returns `None` if the audio is too long and the length policy is to reject it.
+pub fn process(
+    bytes: &[u8],
+    hint: Option<&Hint>,
+    processing: AudioProcessing,
+) -> Result<Option<Vec<u8>>> {
+    let mut audio = DecodedAudio::decode(bytes, hint)?;
+
+    if let Some(max_length) = processing.max_length
+        && audio.duration() > max_length
+    {
+        match processing.length_policy {
+            LengthPolicy::Truncate => audio.truncate_with_fade(max_length),
+            LengthPolicy::Reject => return Ok(None),
+        }
+    }
+
+    audio.shift_pitch(processing.pitch);
+    effects::apply(&mut audio, processing.effect);
+    if processing.normalize {
+        audio.normalize_loudness();
+    }
+
+    if processing.volume != 100 {
+        audio.apply_gain(f32::from(processing.volume) / 100.0);
+    }
+
+    Ok(Some(audio.to_wav()))
+}
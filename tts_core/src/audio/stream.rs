@@ -0,0 +1,131 @@
+//! Playing HTTP responses as they download, instead of waiting for the full file.
+use std::{
+    io::{Error as IoError, Result as IoResult, SeekFrom},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::TryStreamExt as _;
+use poise::serenity_prelude as serenity;
+use songbird::input::{
+    AsyncAdapterStream, AsyncMediaSource, AudioStream, AudioStreamError, Input, LiveInput,
+};
+use symphonia::core::{io::MediaSource, probe::Hint};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio_util::io::StreamReader;
+
+use crate::coalesce::Tee;
+
+/// How much of the response is buffered ahead of playback.
+const BUFFER_LEN: usize = 64 * 1024;
+
+/// The body of an in-flight HTTP response, which can only be read forwards.
+struct ResponseStream {
+    reader: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    len: Option<u64>,
+    /// Collects the body for identical requests waiting on this one.
+    tee: Option<Tee>,
+}
+
+impl AsyncRead for ResponseStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.reader).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            let read = &buf.filled()[filled_before..];
+            if read.is_empty() && buf.remaining() != 0 {
+                // Nothing was read with space left, so the response has finished.
+                if let Some(tee) = self.tee.take() {
+                    tee.finish();
+                }
+            } else if let Some(tee) = &mut self.tee {
+                tee.push(read);
+            }
+        }
+
+        result
+    }
+}
+
+impl AsyncSeek for ResponseStream {
+    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> IoResult<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<u64>> {
+        Poll::Ready(Err(std::io::ErrorKind::Unsupported.into()))
+    }
+}
+
+#[serenity::async_trait]
+impl AsyncMediaSource for ResponseStream {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    async fn byte_len(&self) -> Option<u64> {
+        self.len
+    }
+
+    async fn try_resume(
+        &mut self,
+        _offset: u64,
+    ) -> Result<Box<dyn AsyncMediaSource>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+}
+
+/// Wraps `response` into an input which songbird can start playing before the body is
+/// fully downloaded, using `hint` to pick the format without reading ahead.
+///
+/// If given, `tee` is sent the full body once it has been read.
+///
+/// This must be called from within the tokio runtime.
+#[must_use]
+pub fn response_input(
+    response: reqwest::Response,
+    hint: Option<Hint>,
+    tee: Option<Tee>,
+) -> Input {
+    let len = response.content_length();
+    let reader = StreamReader::new(response.bytes_stream().map_err(IoError::other));
+    let stream = ResponseStream {
+        reader: Box::new(reader),
+        len,
+        tee,
+    };
+
+    let adapter = AsyncAdapterStream::new(Box::new(stream), BUFFER_LEN);
+    let input: Box<dyn MediaSource> = Box::new(adapter);
+    Input::Live(LiveInput::Raw(AudioStream { input, hint }), None)
+}
+
+struct StopTrack;
+
+#[serenity::async_trait]
+impl songbird::EventHandler for StopTrack {
+    async fn act(&self, ctx: &songbird::EventContext<'_>) -> Option<songbird::Event> {
+        if let songbird::EventContext::Track([(_, handle)]) = ctx
+            && let Err(err) = handle.stop()
+        {
+            tracing::debug!("Failed to stop over-length track: {err}");
+        }
+
+        Some(songbird::Event::Cancel)
+    }
+}
+
+/// Stops `track` once it has played for `max_length`, for streamed audio which cannot
+/// be measured before playback starts.
+pub fn stop_after(
+    track: &songbird::tracks::TrackHandle,
+    max_length: Duration,
+) -> Result<(), songbird::error::ControlError> {
+    track.add_event(songbird::Event::Delayed(max_length), StopTrack)
+}
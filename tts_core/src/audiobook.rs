@@ -0,0 +1,193 @@
+//! Splitting long text files into chunks small enough to synthesize, and tracking
+//! how much each guild has generated.
+use crate::structs::Result;
+
+/// The most chapters to mark, any further headings are read as normal text.
+pub const MAX_CHAPTERS: usize = 99;
+
+pub struct Chapter {
+    /// The heading this chapter started with, if any.
+    pub title: Option<String>,
+    pub chunks: Vec<String>,
+}
+
+/// Parses a markdown heading, such as `## Chapter 2`.
+fn parse_heading(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+
+    let title = trimmed[level..].strip_prefix(' ')?.trim().trim_end_matches('#').trim();
+    (!title.is_empty()).then_some(title)
+}
+
+/// Splits `text` into sentences, keeping the punctuation with the sentence.
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    let mut remaining = text.trim();
+    std::iter::from_fn(move || {
+        if remaining.is_empty() {
+            return None;
+        }
+
+        let mut chars = remaining.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if matches!(c, '.' | '!' | '?' | '\n')
+                && chars.peek().is_none_or(|(_, next)| next.is_whitespace())
+            {
+                let end = i + c.len_utf8();
+                let sentence = &remaining[..end];
+                remaining = remaining[end..].trim_start();
+                return Some(sentence);
+            }
+        }
+
+        Some(std::mem::take(&mut remaining))
+    })
+}
+
+/// Splits a sentence which is too long on its own, preferring to split on whitespace.
+fn split_long(sentence: &str, max_len: usize, chunks: &mut Vec<String>) {
+    let mut remaining = sentence;
+    while let Some((mut split_at, _)) = remaining.char_indices().nth(max_len) {
+        if let Some(space) = remaining[..split_at].rfind(char::is_whitespace) {
+            if space != 0 {
+                split_at = space;
+            }
+        }
+
+        chunks.push(remaining[..split_at].trim().to_owned());
+        remaining = remaining[split_at..].trim_start();
+    }
+
+    if !remaining.is_empty() {
+        chunks.push(remaining.to_owned());
+    }
+}
+
+/// Packs the sentences of `text` into chunks of at most `max_len` characters.
+fn chunk_text(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for sentence in sentences(text) {
+        let sentence = sentence.trim();
+        if sentence.is_empty() {
+            continue;
+        }
+
+        let sentence_len = sentence.chars().count();
+        if !current.is_empty() && current_len + 1 + sentence_len > max_len {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+
+        if sentence_len > max_len {
+            split_long(sentence, max_len, &mut chunks);
+        } else {
+            if !current.is_empty() {
+                current.push(' ');
+                current_len += 1;
+            }
+            current.push_str(sentence);
+            current_len += sentence_len;
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Splits `text` into chapters on markdown headings, then into chunks at sentence boundaries.
+///
+/// Each heading is read out as the first chunk of its chapter.
+#[must_use]
+pub fn split_chapters(text: &str, max_chunk_len: usize) -> Vec<Chapter> {
+    let mut chapters = Vec::new();
+    let mut title = None;
+    let mut body = String::new();
+
+    for line in text.lines() {
+        if chapters.len() < MAX_CHAPTERS
+            && let Some(heading) = parse_heading(line)
+        {
+            if title.is_some() || !body.trim().is_empty() {
+                let mut chunks = chunk_text(&body, max_chunk_len);
+                if let Some(title) = &title {
+                    chunks.insert(0, format!("{title}."));
+                }
+
+                chapters.push(Chapter { title, chunks });
+            }
+
+            title = Some(heading.to_owned());
+            body.clear();
+            continue;
+        }
+
+        body.push_str(line);
+        body.push('\n');
+    }
+
+    let mut chunks = chunk_text(&body, max_chunk_len);
+    if let Some(title) = &title {
+        chunks.insert(0, format!("{title}."));
+    }
+
+    if !chunks.is_empty() {
+        chapters.push(Chapter { title, chunks });
+    }
+
+    chapters
+}
+
+/// Reserves `characters` of the guild's daily audiobook quota.
+///
+/// Returns `false` without reserving anything if the quota would be exceeded.
+pub async fn reserve_quota(
+    pool: &sqlx::PgPool,
+    guild_id: i64,
+    characters: i32,
+    quota: i32,
+) -> Result<bool> {
+    if characters > quota {
+        return Ok(false);
+    }
+
+    let reserved: Option<(i32,)> = sqlx::query_as(
+        "INSERT INTO audiobook_usage(guild_id, day, characters)
+        VALUES($1, CURRENT_DATE, $2)
+
+        ON CONFLICT (guild_id, day)
+        DO UPDATE SET characters = audiobook_usage.characters + EXCLUDED.characters
+        WHERE audiobook_usage.characters + EXCLUDED.characters <= $3
+        RETURNING characters",
+    )
+    .bind(guild_id)
+    .bind(characters)
+    .bind(quota)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(reserved.is_some())
+}
+
+/// Gives back quota reserved by [`reserve_quota`], if generation failed.
+pub async fn refund_quota(pool: &sqlx::PgPool, guild_id: i64, characters: i32) -> Result<()> {
+    sqlx::query(
+        "UPDATE audiobook_usage
+        SET characters = GREATEST(characters - $2, 0)
+        WHERE guild_id = $1 AND day = CURRENT_DATE",
+    )
+    .bind(guild_id)
+    .bind(characters)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
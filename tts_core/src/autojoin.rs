@@ -0,0 +1,87 @@
+//! Joining voice channels as soon as someone enters them, limited by per-guild lists
+//! of which channels can be joined.
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId};
+
+use crate::structs::{Data, Result};
+
+/// How long after joining on enter before the bot will do so again in the same guild,
+/// so members hopping between channels don't make the bot rejoin over and over.
+pub const JOIN_ON_ENTER_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum ChannelList {
+    #[name = "Allow joining this channel"]
+    #[name = "allow"]
+    Allow,
+    #[name = "Never join this channel"]
+    #[name = "deny"]
+    Deny,
+    #[name = "Remove this channel from the lists"]
+    #[name = "remove"]
+    Remove,
+}
+
+/// Puts `channel_id` on the allow or deny list of `guild_id`, or removes it from both.
+pub async fn set_channel(
+    data: &Data,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    list: ChannelList,
+) -> Result<()> {
+    let allowed = match list {
+        ChannelList::Allow => true,
+        ChannelList::Deny => false,
+        ChannelList::Remove => {
+            sqlx::query(
+                "DELETE FROM join_on_enter_channels WHERE guild_id = $1 AND channel_id = $2",
+            )
+            .bind(guild_id.get() as i64)
+            .bind(channel_id.get() as i64)
+            .execute(&data.pool)
+            .await?;
+
+            return Ok(());
+        }
+    };
+
+    // Makes sure the guild row exists, for the foreign key.
+    data.guilds_db.create_row(guild_id.into()).await?;
+    sqlx::query(
+        "INSERT INTO join_on_enter_channels(guild_id, channel_id, allowed)
+        VALUES($1, $2, $3)
+
+        ON CONFLICT (guild_id, channel_id)
+        DO UPDATE SET allowed = EXCLUDED.allowed",
+    )
+    .bind(guild_id.get() as i64)
+    .bind(channel_id.get() as i64)
+    .bind(allowed)
+    .execute(&data.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Checks the allow and deny lists of `guild_id` for `channel_id`.
+///
+/// Denied channels are never joined, and if any channels are allowed, only those are joined.
+pub async fn can_join(data: &Data, guild_id: GuildId, channel_id: ChannelId) -> Result<bool> {
+    let lists: Vec<(i64, bool)> = sqlx::query_as(
+        "SELECT channel_id, allowed FROM join_on_enter_channels WHERE guild_id = $1",
+    )
+    .bind(guild_id.get() as i64)
+    .fetch_all(&data.pool)
+    .await?;
+
+    let channel_id = channel_id.get() as i64;
+    let listed = lists.iter().find(|(id, _)| *id == channel_id);
+    let any_allowed = lists.iter().any(|(_, allowed)| *allowed);
+
+    Ok(match listed {
+        Some((_, allowed)) => *allowed,
+        None => !any_allowed,
+    })
+}
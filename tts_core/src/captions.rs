@@ -0,0 +1,328 @@
+//! Transcribing what is said in voice channels into text captions, for members who
+//! cannot listen to the voice channel.
+//!
+//! Audio is received per speaker from songbird, split into segments at pauses, sent
+//! to the configured speech-to-text backend, then posted to the guild's captions channel.
+use std::{collections::HashMap, sync::Arc};
+
+use parking_lot::Mutex;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, CreateAllowedMentions, CreateMessage, GuildId, Mentionable, UserId};
+use songbird::{
+    driver::{Channels, DecodeConfig, DecodeMode, SampleRate},
+    CoreEvent, EventContext,
+};
+
+use crate::{
+    audio::DecodedAudio,
+    errors,
+    structs::{Data, Result, SttConfig, SttKind},
+};
+
+const SAMPLE_RATE: u32 = 16_000;
+/// How many silent 20ms ticks end a speaker's segment.
+const SILENCE_TICKS: u32 = 40;
+/// Segments shorter than this are usually coughs or clicks, so aren't transcribed.
+const MIN_SEGMENT_SAMPLES: usize = SAMPLE_RATE as usize / 2;
+/// Segments are cut off at this length, so long monologues are captioned as they go.
+const MAX_SEGMENT_SAMPLES: usize = SAMPLE_RATE as usize * 30;
+
+#[derive(serde::Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+/// Sends a segment of speech to the speech-to-text backend, returning what was said.
+async fn transcribe(data: &Data, config: &SttConfig, audio: &DecodedAudio) -> Result<String> {
+    let file = reqwest::multipart::Part::bytes(audio.to_wav())
+        .file_name("speech.wav")
+        .mime_str("audio/wav")?;
+
+    let mut form = reqwest::multipart::Form::new()
+        .part("file", file)
+        .text("response_format", "json");
+
+    let mut request = data.reqwest.post(config.url.clone());
+    match config.kind {
+        SttKind::OpenAI => {
+            let model = config.model.as_deref().unwrap_or("whisper-1");
+            form = form.text("model", model.to_owned());
+
+            let api_key = config
+                .api_key
+                .as_ref()
+                .or(data.config.openai_api_key.as_ref());
+            if let Some(api_key) = api_key {
+                request = request.bearer_auth(api_key);
+            }
+        }
+        SttKind::WhisperServer => {
+            form = form.text("temperature", "0.0");
+            if let Some(api_key) = &config.api_key {
+                request = request.bearer_auth(api_key);
+            }
+        }
+    }
+
+    let response = request.multipart(form).send().await?.error_for_status()?;
+    let response: TranscriptionResponse = response.json().await?;
+    Ok(response.text.trim().to_owned())
+}
+
+/// Enables decoding in `config`, for calls which are being captioned.
+#[must_use]
+pub fn decoding_config(config: songbird::Config) -> songbird::Config {
+    let decode_config = DecodeConfig::new(Channels::Mono, SampleRate::Hz16000);
+    config.decode_mode(DecodeMode::Decode(decode_config))
+}
+
+/// Starts captioning the voice call in `guild_id`, if captions are enabled and the
+/// bot has a call there.
+///
+/// This must only be called once per call, when the bot first connects.
+pub async fn start(
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    voice_channel: ChannelId,
+) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    if data.stt_config.is_none() {
+        return Ok(());
+    }
+
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+    let Some(captions_channel) = guild_row.captions_channel else {
+        return Ok(());
+    };
+
+    let Some(call) = data.songbird.get(guild_id) else {
+        return Ok(());
+    };
+
+    let handler = CaptionHandler {
+        ctx: ctx.clone(),
+        guild_id,
+        speakers: Arc::default(),
+    };
+
+    {
+        let mut call = call.lock().await;
+        let config = decoding_config(call.config().clone());
+        call.set_config(config);
+
+        call.add_global_event(CoreEvent::SpeakingStateUpdate.into(), handler.clone());
+        call.add_global_event(CoreEvent::VoiceTick.into(), handler.clone());
+        call.add_global_event(CoreEvent::ClientDisconnect.into(), handler);
+    }
+
+    // Members should always know when they can be transcribed.
+    let notice = format!(
+        "Speech in this voice channel can be transcribed into {}. Only members who have run `/set captions_opt_in True` are transcribed.",
+        captions_channel.mention()
+    );
+
+    if voice_channel.widen().say(&ctx.http, &notice).await.is_err() {
+        captions_channel.widen().say(&ctx.http, notice).await?;
+    }
+
+    Ok(())
+}
+
+struct Speaker {
+    user_id: UserId,
+    /// If the speaker has opted in to being transcribed, audio is only kept if so.
+    consented: bool,
+    samples: Vec<f32>,
+    silent_ticks: u32,
+}
+
+impl Speaker {
+    /// Takes the speech collected so far, if it is long enough to be worth transcribing.
+    fn take_segment(&mut self) -> Option<DecodedAudio> {
+        let samples = std::mem::take(&mut self.samples);
+        if samples.len() < MIN_SEGMENT_SAMPLES {
+            return None;
+        }
+
+        Some(DecodedAudio {
+            samples,
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct CaptionHandler {
+    ctx: serenity::Context,
+    guild_id: GuildId,
+    /// The speakers in the call, keyed by the SSRC of their audio.
+    speakers: Arc<Mutex<HashMap<u32, Speaker>>>,
+}
+
+impl CaptionHandler {
+    /// Checks if `user_id` has opted in to captions, and hasn't opted out of this guild.
+    async fn has_consented(&self, user_id: UserId) -> Result<bool> {
+        let data = self.ctx.data_ref::<Data>();
+        let (user_row, opt_out_row) = tokio::try_join!(
+            data.userinfo_db.get(user_id.into()),
+            data.user_opt_out_db
+                .get([user_id.into(), self.guild_id.into()]),
+        )?;
+
+        Ok(user_row.captions_opted_in() && !opt_out_row.opted_out)
+    }
+
+    async fn add_speaker(&self, ssrc: u32, user_id: UserId) -> Result<()> {
+        let speaker = Speaker {
+            user_id,
+            consented: self.has_consented(user_id).await?,
+            samples: Vec::new(),
+            silent_ticks: 0,
+        };
+
+        self.speakers.lock().insert(ssrc, speaker);
+        Ok(())
+    }
+
+    /// Adds the audio from a tick to each speaker, returning any finished segments.
+    fn collect_segments(
+        &self,
+        tick: &songbird::events::context_data::VoiceTick,
+    ) -> Vec<(UserId, DecodedAudio)> {
+        let mut segments = Vec::new();
+        let mut speakers = self.speakers.lock();
+
+        for (ssrc, voice_data) in &tick.speaking {
+            let Some(speaker) = speakers.get_mut(ssrc) else {
+                continue;
+            };
+            let Some(decoded) = &voice_data.decoded_voice else {
+                continue;
+            };
+            if !speaker.consented {
+                continue;
+            }
+
+            speaker.silent_ticks = 0;
+            speaker.samples.extend(
+                decoded
+                    .iter()
+                    .map(|&sample| f32::from(sample) / f32::from(i16::MAX)),
+            );
+
+            if speaker.samples.len() >= MAX_SEGMENT_SAMPLES
+                && let Some(segment) = speaker.take_segment()
+            {
+                segments.push((speaker.user_id, segment));
+            }
+        }
+
+        for ssrc in &tick.silent {
+            let Some(speaker) = speakers.get_mut(ssrc) else {
+                continue;
+            };
+            if speaker.samples.is_empty() {
+                continue;
+            }
+
+            speaker.silent_ticks += 1;
+            if speaker.silent_ticks >= SILENCE_TICKS {
+                speaker.silent_ticks = 0;
+                if let Some(segment) = speaker.take_segment() {
+                    segments.push((speaker.user_id, segment));
+                }
+            }
+        }
+
+        segments
+    }
+
+    fn remove_speaker(&self, user_id: UserId) -> Option<(UserId, DecodedAudio)> {
+        let mut speakers = self.speakers.lock();
+        let ssrc = *speakers.iter().find(|(_, s)| s.user_id == user_id)?.0;
+
+        let mut speaker = speakers.remove(&ssrc)?;
+        speaker.take_segment().map(|segment| (user_id, segment))
+    }
+
+    async fn caption(self, user_id: UserId, segment: DecodedAudio) -> Result<()> {
+        let data = self.ctx.data_ref::<Data>();
+        let Some(stt_config) = &data.stt_config else {
+            return Ok(());
+        };
+
+        // The settings may have changed since the call started.
+        let (guild_row, consented) = tokio::try_join!(
+            data.guilds_db.get(self.guild_id.into()),
+            self.has_consented(user_id),
+        )?;
+
+        let Some(captions_channel) = guild_row.captions_channel else {
+            return Ok(());
+        };
+
+        if !consented {
+            return Ok(());
+        }
+
+        let text = transcribe(data, stt_config, &segment).await?;
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let builder = CreateMessage::default()
+            .content(format!("<@{user_id}>: {text}"))
+            .allowed_mentions(CreateAllowedMentions::new());
+
+        captions_channel
+            .widen()
+            .send_message(&self.ctx.http, builder)
+            .await?;
+        data.analytics.log("speech_captioned".into(), false);
+        Ok(())
+    }
+
+    fn spawn_caption(&self, user_id: UserId, segment: DecodedAudio) {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let ctx = handler.ctx.clone();
+            if let Err(err) = handler.caption(user_id, segment).await {
+                let result = errors::handle_unexpected_default(&ctx, "Captions", err).await;
+                if let Err(err_err) = result {
+                    tracing::error!("Error in captions handler: {err_err:?}");
+                }
+            }
+        });
+    }
+}
+
+#[serenity::async_trait]
+impl songbird::EventHandler for CaptionHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<songbird::Event> {
+        match ctx {
+            EventContext::SpeakingStateUpdate(speaking) => {
+                if let Some(user_id) = speaking.user_id {
+                    let user_id = UserId::new(user_id.0);
+                    if let Err(err) = self.add_speaker(speaking.ssrc, user_id).await {
+                        tracing::error!("Failed to add speaker to captions: {err:?}");
+                    }
+                }
+            }
+            EventContext::VoiceTick(tick) => {
+                for (user_id, segment) in self.collect_segments(tick) {
+                    self.spawn_caption(user_id, segment);
+                }
+            }
+            EventContext::ClientDisconnect(disconnect) => {
+                let user_id = UserId::new(disconnect.user_id.0);
+                if let Some((user_id, segment)) = self.remove_speaker(user_id) {
+                    self.spawn_caption(user_id, segment);
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
@@ -0,0 +1,154 @@
+//! Sharing a single backend request between identical syntheses running at the same time,
+//! such as when the same message is spammed before any of it has been read out.
+use std::sync::Arc;
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use songbird::input::core::probe::Hint;
+use tokio::sync::watch;
+
+use crate::{
+    audio::encode::AudioFormat,
+    common::{SynthesisRequest, SynthesizedAudio},
+    structs::{OpenAIModel, TTSMode},
+};
+
+/// The result of a synthesis, `None` if the backend declined to generate audio.
+pub type Shared = Option<Arc<SynthesizedAudio>>;
+/// A synthesis which is still running, see [`wait`].
+pub type Pending = watch::Receiver<Option<Shared>>;
+
+/// Everything which affects the audio generated for a [`SynthesisRequest`].
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SynthesisKey {
+    content: String,
+    voice: String,
+    mode: TTSMode,
+    speaking_rate: String,
+    openai_model: OpenAIModel,
+    instruction: Option<String>,
+    max_length: String,
+    translation_lang: Option<String>,
+    format: AudioFormat,
+}
+
+impl From<&SynthesisRequest<'_>> for SynthesisKey {
+    fn from(request: &SynthesisRequest<'_>) -> Self {
+        Self {
+            content: request.content.to_owned(),
+            voice: request.voice.to_owned(),
+            mode: request.mode,
+            speaking_rate: request.speaking_rate.to_owned(),
+            openai_model: request.openai_model,
+            instruction: request.instruction.map(String::from),
+            max_length: request.max_length.to_owned(),
+            translation_lang: request.translation_lang.map(String::from),
+            format: request.format,
+        }
+    }
+}
+
+pub enum Role {
+    /// No identical request is running, so this one should be sent and its result published.
+    Leader(Publisher),
+    /// An identical request is running, so its result can be waited for.
+    Follower(Pending),
+}
+
+/// The syntheses currently waiting on a backend.
+#[derive(Default)]
+pub struct InFlight(Arc<DashMap<SynthesisKey, Pending>>);
+
+impl InFlight {
+    #[must_use]
+    pub fn join(&self, request: &SynthesisRequest<'_>) -> Role {
+        match self.0.entry(SynthesisKey::from(request)) {
+            Entry::Occupied(entry) => Role::Follower(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                let key = entry.key().clone();
+                let (sender, receiver) = watch::channel(None);
+                entry.insert(receiver);
+
+                Role::Leader(Publisher {
+                    map: self.0.clone(),
+                    key,
+                    sender,
+                })
+            }
+        }
+    }
+}
+
+/// Waits for the leader of an identical request to publish its result.
+///
+/// Returns `None` if the leader failed, so the request has to be sent separately.
+pub async fn wait(mut receiver: Pending) -> Option<Shared> {
+    let result = receiver.wait_for(Option::is_some).await.ok()?;
+    result.clone()
+}
+
+/// Sends the result of a request to any identical requests waiting on it.
+///
+/// If dropped without publishing, the waiting requests are sent separately.
+pub struct Publisher {
+    map: Arc<DashMap<SynthesisKey, Pending>>,
+    key: SynthesisKey,
+    sender: watch::Sender<Option<Shared>>,
+}
+
+impl Publisher {
+    /// Waits for this request's own result, such as to save it once it has been streamed.
+    #[must_use]
+    pub fn subscribe(&self) -> Pending {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(self, audio: Shared) {
+        self.sender.send_replace(Some(audio));
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        // The entry can only be replaced once removed, so this is always our own entry.
+        self.map.remove(&self.key);
+    }
+}
+
+/// Collects streamed audio as it is played, to publish once the stream has finished.
+///
+/// If the stream is dropped before finishing, the waiting requests are sent separately.
+pub struct Tee {
+    publisher: Publisher,
+    hint: Option<Hint>,
+    format: AudioFormat,
+    bytes: Vec<u8>,
+}
+
+impl Tee {
+    #[must_use]
+    pub fn new(publisher: Publisher, hint: Option<Hint>, format: AudioFormat) -> (Self, Pending) {
+        let receiver = publisher.subscribe();
+        let tee = Self {
+            publisher,
+            hint,
+            format,
+            bytes: Vec::new(),
+        };
+
+        (tee, receiver)
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) {
+        let audio = SynthesizedAudio {
+            bytes: self.bytes,
+            hint: self.hint,
+            format: self.format,
+        };
+
+        self.publisher.publish(Some(Arc::new(audio)));
+    }
+}
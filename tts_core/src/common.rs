@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::num::NonZeroU8;
+use std::sync::Arc;
 
 use itertools::Itertools;
 use rand::Rng as _;
@@ -7,8 +8,14 @@ use rand::Rng as _;
 use serenity::all as serenity;
 use serenity::{CollectComponentInteractions, CreateActionRow, CreateButton};
 
+use crate::audio::{
+    self,
+    encode::{self, AudioFormat},
+};
+use crate::coalesce::{self, Role, Tee};
 use crate::structs::{
-    Context, Data, LastToXsaidTracker, LastXsaidInfo, RegexCache, Result, TTSMode, TTSServiceError,
+    Context, Data, LastToXsaidTracker, LastXsaidInfo, OpenAIModel, RegexCache, Result, TTSMode,
+    TTSServiceError,
 };
 
 pub(crate) fn timestamp_in_future(ts: serenity::Timestamp) -> bool {
@@ -25,6 +32,37 @@ pub fn push_permission_names(buffer: &mut String, permissions: serenity::Permiss
     }
 }
 
+/// Sorts the given roles by their position in the guild, highest first.
+pub fn member_roles_by_position(
+    guild: &serenity::Guild,
+    roles: &[serenity::RoleId],
+) -> Vec<serenity::RoleId> {
+    let mut roles = roles.to_vec();
+    roles.sort_by_key(|role_id| {
+        let position = guild.roles.get(role_id).map(|r| r.position);
+        std::cmp::Reverse(position)
+    });
+
+    roles
+}
+
+/// The cached roles of `user_id` in `guild_id`, sorted by their position in the guild.
+#[must_use]
+pub fn cached_member_roles(
+    cache: &serenity::Cache,
+    guild_id: Option<serenity::GuildId>,
+    user_id: serenity::UserId,
+) -> Vec<serenity::RoleId> {
+    let Some(guild) = guild_id.and_then(|guild_id| cache.guild(guild_id)) else {
+        return Vec::new();
+    };
+
+    match guild.members.get(&user_id) {
+        Some(member) => member_roles_by_position(&guild, &member.roles),
+        None => Vec::new(),
+    }
+}
+
 pub async fn remove_premium(data: &Data, guild_id: serenity::GuildId) -> Result<()> {
     tokio::try_join!(
         data.guilds_db
@@ -91,17 +129,94 @@ pub async fn fetch_audio(
     }
 }
 
-pub async fn fetch_openai_audio(
-    openai_api_key: &str,
+/// The ways an OpenAI request can fail, which are each handled differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAIErrorKind {
+    RateLimited,
+    Auth,
+    InvalidInput,
+    Other,
+}
+
+impl OpenAIErrorKind {
+    #[must_use]
+    pub fn from_status(status: reqwest::StatusCode) -> Self {
+        match status.as_u16() {
+            429 => Self::RateLimited,
+            401 | 403 => Self::Auth,
+            400 | 404 | 422 => Self::InvalidInput,
+            _ => Self::Other,
+        }
+    }
+
+    #[must_use]
+    pub fn from_error(error: &async_openai::error::OpenAIError) -> Self {
+        use async_openai::error::OpenAIError;
+
+        match error {
+            OpenAIError::ApiError(error) => match (error.r#type.as_deref(), error.code.as_deref()) {
+                (_, Some("rate_limit_exceeded" | "insufficient_quota"))
+                | (Some("rate_limit_error" | "tokens" | "requests"), _) => Self::RateLimited,
+                (_, Some("invalid_api_key"))
+                | (Some("authentication_error" | "permission_error"), _) => Self::Auth,
+                (Some("invalid_request_error"), _) => Self::InvalidInput,
+                _ => Self::Other,
+            },
+            OpenAIError::Reqwest(error) => error.status().map(Self::from_status).unwrap_or(Self::Other),
+            OpenAIError::InvalidArgument(_) => Self::InvalidInput,
+            _ => Self::Other,
+        }
+    }
+
+    /// Handles a failed request, skipping the message if only this request was at fault.
+    fn handle<T>(self, error: &dyn std::fmt::Debug) -> Result<Option<T>> {
+        match self {
+            Self::RateLimited => {
+                tracing::warn!("OpenAI TTS rate limit hit, skipping message: {error:?}");
+                Ok(None)
+            }
+            Self::InvalidInput => {
+                tracing::debug!("OpenAI TTS rejected the input: {error:?}");
+                Ok(None)
+            }
+            Self::Auth => Err(anyhow::anyhow!(
+                "OpenAI TTS authentication failed, check `openai_api_key`: {error:?}"
+            )),
+            Self::Other => Err(anyhow::anyhow!("OpenAI TTS error: {error:?}")),
+        }
+    }
+}
+
+#[must_use]
+pub fn openai_client(
+    api_key: &str,
+    api_base: Option<&reqwest::Url>,
+) -> async_openai::Client<async_openai::config::OpenAIConfig> {
+    let mut config = async_openai::config::OpenAIConfig::new().with_api_key(api_key);
+    if let Some(api_base) = api_base {
+        config = config.with_api_base(api_base.as_str().trim_end_matches('/'));
+    }
+
+    async_openai::Client::with_config(config)
+}
+
+fn build_openai_request(
     content: &str,
     voice: &str,
     speaking_rate: f32,
-    model: crate::structs::OpenAIModel,
+    model: OpenAIModel,
     instruction: Option<&str>,
-) -> Result<Option<Vec<u8>>> {
-    use async_openai::{
-        types::{CreateSpeechRequestArgs, SpeechModel, Voice as OpenAIVoice},
-        Client,
+    format: AudioFormat,
+) -> Result<async_openai::types::CreateSpeechRequest> {
+    use async_openai::types::{
+        CreateSpeechRequestArgs, SpeechModel, SpeechResponseFormat, Voice as OpenAIVoice,
+    };
+
+    let info = model.info();
+    let voice = if info.supports_voice(voice) {
+        voice
+    } else {
+        TTSMode::OpenAI.default_voice()
     };
 
     // Parse voice from string to OpenAI Voice enum
@@ -122,55 +237,326 @@ pub async fn fetch_openai_audio(
         _ => OpenAIVoice::Alloy, // fallback to default
     };
 
-    let config = async_openai::config::OpenAIConfig::new().with_api_key(openai_api_key);
-    let client = Client::with_config(config);
-
     // Convert our enum to OpenAI's SpeechModel
     let speech_model = match model {
-        crate::structs::OpenAIModel::Tts1 => SpeechModel::Tts1,
-        crate::structs::OpenAIModel::Tts1Hd => SpeechModel::Tts1Hd,
-        crate::structs::OpenAIModel::Gpt4oMiniTts => SpeechModel::Other("gpt-4o-mini-tts".to_string()),
+        OpenAIModel::Tts1 => SpeechModel::Tts1,
+        OpenAIModel::Tts1Hd => SpeechModel::Tts1Hd,
+        OpenAIModel::Gpt4oMiniTts => SpeechModel::Other("gpt-4o-mini-tts".to_string()),
     };
 
-    // Build the request with or without instructions based on model and availability
-    let request = if matches!(model, crate::structs::OpenAIModel::Gpt4oMiniTts) {
-        if let Some(instruction) = instruction {
-            tracing::info!("Adding OpenAI instruction: {}", instruction);
-            CreateSpeechRequestArgs::default()
-                .input(content)
-                .voice(openai_voice)
-                .model(speech_model)
-                .speed(speaking_rate)
-                .instructions(instruction)
-                .build()?
-        } else {
-            CreateSpeechRequestArgs::default()
-                .input(content)
-                .voice(openai_voice)
-                .model(speech_model)
-                .speed(speaking_rate)
-                .build()?
-        }
-    } else {
-        CreateSpeechRequestArgs::default()
-            .input(content)
-            .voice(openai_voice)
-            .model(speech_model)
-            .speed(speaking_rate)
-            .build()?
+    let response_format = match format {
+        AudioFormat::Mp3 => SpeechResponseFormat::Mp3,
+        AudioFormat::OggOpus => SpeechResponseFormat::Opus,
+        AudioFormat::Wav => SpeechResponseFormat::Wav,
+        AudioFormat::Flac => SpeechResponseFormat::Flac,
+    };
+
+    // Longer input is rejected outright, so read out as much as possible instead.
+    let content = match content.char_indices().nth(info.max_input_len) {
+        Some((end, _)) => &content[..end],
+        None => content,
     };
 
+    let mut request = CreateSpeechRequestArgs::default();
+    request
+        .input(content)
+        .voice(openai_voice)
+        .model(speech_model)
+        .response_format(response_format);
+
+    if let Some((min, max)) = info.speed_range {
+        request.speed(speaking_rate.clamp(min, max));
+    }
+
+    if info.supports_instructions
+        && let Some(instruction) = instruction
+    {
+        tracing::info!("Adding OpenAI instruction: {}", instruction);
+        request.instructions(instruction);
+    }
+
+    Ok(request.build()?)
+}
+
+pub async fn fetch_openai_audio(
+    client: &async_openai::Client<async_openai::config::OpenAIConfig>,
+    content: &str,
+    voice: &str,
+    speaking_rate: f32,
+    model: OpenAIModel,
+    instruction: Option<&str>,
+    format: AudioFormat,
+) -> Result<Option<Vec<u8>>> {
+    let request =
+        build_openai_request(content, voice, speaking_rate, model, instruction, format)?;
+
     match client.audio().speech(request).await {
-        Ok(response) => {
-            let bytes = response.bytes;
-            Ok(Some(bytes.to_vec()))
+        Ok(response) => Ok(Some(response.bytes.to_vec())),
+        Err(err) => OpenAIErrorKind::from_error(&err).handle(&err),
+    }
+}
+
+/// The parameters for a single TTS request, to any backend.
+#[derive(Clone, Copy)]
+pub struct SynthesisRequest<'a> {
+    pub content: &'a str,
+    pub voice: &'a str,
+    pub mode: TTSMode,
+    pub speaking_rate: &'a str,
+    pub openai_model: OpenAIModel,
+    pub instruction: Option<&'a str>,
+    pub max_length: &'a str,
+    pub translation_lang: Option<&'a str>,
+    /// The format to request, backends which cannot generate it fall back to their default.
+    pub format: AudioFormat,
+}
+
+#[derive(Clone)]
+pub struct SynthesizedAudio {
+    pub bytes: Vec<u8>,
+    pub hint: Option<songbird::input::core::probe::Hint>,
+    pub format: AudioFormat,
+}
+
+pub enum Synthesis<T> {
+    /// The request was sent, `None` if the backend declined to generate audio, such as
+    /// the message being too long.
+    Done(Option<T>),
+    /// The backend was too busy to take the request, so it was skipped.
+    Busy,
+}
+
+/// Generates audio for `request` from the backend for its mode, sharing the result with
+/// any identical requests made while it is generating.
+///
+/// Only requests which are sent to the backend wait for a turn from the synthesis limiter,
+/// on behalf of `guild_id`.
+pub async fn synthesize(
+    data: &Data,
+    request: SynthesisRequest<'_>,
+    guild_id: Option<serenity::GuildId>,
+    is_premium: bool,
+) -> Result<Synthesis<SynthesizedAudio>> {
+    let publisher = match data.in_flight.join(&request) {
+        Role::Leader(publisher) => Some(publisher),
+        Role::Follower(receiver) => match coalesce::wait(receiver).await {
+            Some(audio) => return Ok(Synthesis::Done(audio.map(Arc::unwrap_or_clone))),
+            // The identical request failed, so this may succeed if sent separately.
+            None => None,
+        },
+    };
+
+    let limiter = &data.synthesis_limiter;
+    let Some(_permit) = limiter.acquire(request.mode, guild_id, is_premium).await else {
+        return Ok(Synthesis::Busy);
+    };
+
+    let audio = fetch_synthesis(data, request).await?.map(Arc::new);
+    if let Some(publisher) = publisher {
+        publisher.publish(audio.clone());
+    }
+
+    Ok(Synthesis::Done(audio.map(Arc::unwrap_or_clone)))
+}
+
+async fn fetch_synthesis(
+    data: &Data,
+    request: SynthesisRequest<'_>,
+) -> Result<Option<SynthesizedAudio>> {
+    use songbird::input::core::probe::Hint;
+
+    let format = encode::negotiate(request.mode, request.format);
+    if request.mode == TTSMode::OpenAI {
+        let Some(openai) = &data.openai else {
+            tracing::error!("OpenAI API key not configured for OpenAI TTS mode");
+            return Ok(None);
+        };
+
+        let speaking_rate = request.speaking_rate.parse::<f32>().unwrap_or(1.0);
+        let audio = fetch_openai_audio(
+            openai,
+            request.content,
+            request.voice,
+            speaking_rate,
+            request.openai_model,
+            request.instruction,
+            format,
+        )
+        .await?;
+
+        return Ok(audio.map(|bytes| {
+            let mut hint = Hint::new();
+            hint.with_extension(format.extension());
+            SynthesizedAudio {
+                bytes,
+                hint: Some(hint),
+                format,
+            }
+        }));
+    }
+
+    let url = prepare_url(
+        data.config.tts_service.clone(),
+        request.content,
+        request.voice,
+        request.mode,
+        request.speaking_rate,
+        request.max_length,
+        request.translation_lang,
+        format,
+    );
+
+    let auth_key = data.config.tts_service_auth_key.as_deref();
+    let Some(audio) = fetch_audio(&data.reqwest, url, auth_key).await? else {
+        return Ok(None);
+    };
+
+    let hint = content_type_hint(&audio)?;
+    let format = response_format(&audio, request.mode);
+
+    let bytes = audio.bytes().await?.to_vec();
+    Ok(Some(SynthesizedAudio {
+        bytes,
+        hint,
+        format,
+    }))
+}
+
+fn content_type_hint(
+    response: &reqwest::Response,
+) -> Result<Option<songbird::input::core::probe::Hint>> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .map(|ct| {
+            let mut hint = songbird::input::core::probe::Hint::new();
+            hint.mime_type(ct.to_str()?);
+            Ok::<_, anyhow::Error>(hint)
+        })
+        .transpose()
+}
+
+/// The format the tts-service responded with, which may not be the one requested if
+/// it doesn't support it.
+fn response_format(response: &reqwest::Response, mode: TTSMode) -> AudioFormat {
+    let content_type = response.headers().get(reqwest::header::CONTENT_TYPE);
+    let format = content_type
+        .and_then(|ct| ct.to_str().ok())
+        .and_then(AudioFormat::from_content_type);
+
+    // Without a content type, assume the format the tts-service has always sent.
+    format.unwrap_or(match mode {
+        TTSMode::eSpeak => AudioFormat::Wav,
+        TTSMode::gTTS | TTSMode::gCloud | TTSMode::Polly | TTSMode::OpenAI => AudioFormat::Mp3,
+    })
+}
+
+pub struct StreamedInput {
+    pub input: songbird::input::Input,
+    /// Resolves to the full audio once it has been streamed.
+    pub full_audio: coalesce::Pending,
+}
+
+/// Like [`synthesize`], but returns an input which starts playing as soon as the first
+/// audio arrives, for when the audio doesn't need any processing before playback.
+///
+/// Identical requests made while streaming wait for the stream to finish, then play its
+/// full audio instead of sending their own request.
+pub async fn synthesize_stream(
+    data: &Data,
+    request: SynthesisRequest<'_>,
+    guild_id: Option<serenity::GuildId>,
+    is_premium: bool,
+) -> Result<Synthesis<StreamedInput>> {
+    use async_openai::config::Config as _;
+    use songbird::input::core::probe::Hint;
+
+    let publisher = loop {
+        match data.in_flight.join(&request) {
+            Role::Leader(publisher) => break publisher,
+            Role::Follower(pending) => {
+                // If the identical request failed, this is retried as the leader.
+                if let Some(audio) = coalesce::wait(pending.clone()).await {
+                    return Ok(Synthesis::Done(audio.map(|audio| StreamedInput {
+                        input: audio.bytes.clone().into(),
+                        full_audio: pending,
+                    })));
+                }
+            }
         }
-        Err(e) => {
-            tracing::error!("OpenAI TTS error: {:?}", e);
-            // Return None for rate limiting or other recoverable errors
-            Ok(None)
+    };
+
+    // The permit is only held until the backend starts responding.
+    let limiter = &data.synthesis_limiter;
+    let Some(_permit) = limiter.acquire(request.mode, guild_id, is_premium).await else {
+        return Ok(Synthesis::Busy);
+    };
+
+    let format = encode::negotiate(request.mode, request.format);
+    if request.mode == TTSMode::OpenAI {
+        let Some(openai) = &data.openai else {
+            tracing::error!("OpenAI API key not configured for OpenAI TTS mode");
+            publisher.publish(None);
+            return Ok(Synthesis::Done(None));
+        };
+
+        let speaking_rate = request.speaking_rate.parse::<f32>().unwrap_or(1.0);
+        let body = build_openai_request(
+            request.content,
+            request.voice,
+            speaking_rate,
+            request.openai_model,
+            request.instruction,
+            format,
+        )?;
+
+        // async-openai buffers the whole response, so the request is sent manually.
+        let response = data
+            .reqwest
+            .post(openai.config().url("/audio/speech"))
+            .headers(openai.config().headers())
+            .json(&body)
+            .send()
+            .await?;
+
+        if let Err(err) = response.error_for_status_ref() {
+            let kind = OpenAIErrorKind::from_status(response.status());
+            let body = response.text().await.unwrap_or_default();
+            let audio = kind.handle(&format_args!("{err:?}: {body}"))?;
+            publisher.publish(None);
+            return Ok(Synthesis::Done(audio));
         }
+
+        let mut hint = Hint::new();
+        hint.with_extension(format.extension());
+
+        let (tee, full_audio) = Tee::new(publisher, Some(hint.clone()), format);
+        let input = audio::stream::response_input(response, Some(hint), Some(tee));
+        return Ok(Synthesis::Done(Some(StreamedInput { input, full_audio })));
     }
+
+    let url = prepare_url(
+        data.config.tts_service.clone(),
+        request.content,
+        request.voice,
+        request.mode,
+        request.speaking_rate,
+        request.max_length,
+        request.translation_lang,
+        format,
+    );
+
+    let auth_key = data.config.tts_service_auth_key.as_deref();
+    let Some(response) = fetch_audio(&data.reqwest, url, auth_key).await? else {
+        publisher.publish(None);
+        return Ok(Synthesis::Done(None));
+    };
+
+    let hint = content_type_hint(&response)?;
+    let format = response_format(&response, request.mode);
+    let (tee, full_audio) = Tee::new(publisher, hint.clone(), format);
+    let input = audio::stream::response_input(response, hint, Some(tee));
+    Ok(Synthesis::Done(Some(StreamedInput { input, full_audio })))
 }
 
 #[must_use]
@@ -182,6 +568,7 @@ pub fn prepare_url(
     speaking_rate: &str,
     max_length: &str,
     translation_lang: Option<&str>,
+    format: AudioFormat,
 ) -> reqwest::Url {
     {
         let mut params = tts_service.query_pairs_mut();
@@ -189,7 +576,7 @@ pub fn prepare_url(
         params.append_pair("lang", lang);
         params.append_pair("mode", mode.into());
         params.append_pair("max_length", max_length);
-        params.append_pair("preferred_format", "mp3");
+        params.append_pair("preferred_format", format.extension());
         params.append_pair("speaking_rate", speaking_rate);
 
         if let Some(translation_lang) = translation_lang {
@@ -309,6 +696,7 @@ pub fn clean_msg(
     repeated_limit: Option<NonZeroU8>,
     nickname: Option<&str>,
     use_new_formatting: bool,
+    blocked_words: Option<&regex::Regex>,
 
     regex_cache: &RegexCache,
     last_to_xsaid_tracker: &LastToXsaidTracker,
@@ -328,6 +716,12 @@ pub fn clean_msg(
             }
         }
 
+        if let Some(blocked_words) = blocked_words
+            && let Cow::Owned(filtered) = blocked_words.replace_all(&content, "")
+        {
+            content = Cow::Owned(filtered);
+        }
+
         if voice.starts_with("en") {
             content = Cow::Owned(parse_acronyms(&content));
         }
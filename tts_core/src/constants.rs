@@ -1,3 +1,5 @@
+use poise::serenity_prelude::Permissions;
+
 pub const RED: u32 = 0xff0000;
 pub const FREE_NEUTRAL_COLOUR: u32 = 0x3498db;
 pub const PREMIUM_NEUTRAL_COLOUR: u32 = 0xcaa652;
@@ -9,6 +11,17 @@ pub const OPTION_SEPERATORS: [&str; 4] = [
     ":star:",
 ];
 
+/// The permissions the bot needs in a voice channel to read out messages.
+pub const REQUIRED_VC_PERMISSIONS: Permissions = Permissions::VIEW_CHANNEL
+    .union(Permissions::CONNECT)
+    .union(Permissions::SPEAK);
+
+/// The max length of a greeting set with `/set greeting`.
+pub const MAX_GREETING_LENGTH: usize = 100;
+
+/// The max length of `/tts` audio in seconds, outside of a server.
+pub const DEFAULT_TTS_FILE_LENGTH: u16 = 60;
+
 pub const GTTS_DISABLED_ERROR: &str =
     "The `gTTS` voice mode is currently disabled due to maintenance so cannot be used.";
 
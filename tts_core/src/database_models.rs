@@ -5,7 +5,7 @@ use typesize::derive::TypeSize;
 
 use poise::serenity_prelude::{ChannelId, GuildId, RoleId, UserId};
 
-use crate::structs::{IsPremium, OpenAIModel, TTSMode};
+use crate::structs::{AudioEffect, FollowMode, IsPremium, LengthPolicy, OpenAIModel, TTSMode};
 
 const MAX_VOICE_LENGTH: usize = 20;
 
@@ -40,7 +40,26 @@ pub struct GuildRowRaw {
     pub require_voice: bool,
     pub text_in_voice: bool,
     pub audience_ignore: bool,
+    pub normalize_loudness: bool,
     pub msg_length: i16,
+    pub master_volume: i16,
+    pub tts_file_length: i16,
+    pub audiobook_quota: i32,
+    pub length_policy: LengthPolicy,
+    pub daily_quota: Option<i32>,
+    pub monthly_quota: Option<i32>,
+    pub user_daily_quota: Option<i32>,
+    pub captions_channel: Option<i64>,
+    pub transcript_channel: Option<i64>,
+    pub transcript_threads: bool,
+    pub join_on_enter: bool,
+    pub announce_joins: bool,
+    pub announce_leaves: bool,
+    pub greetings: bool,
+    pub transcript_retention: Option<i16>,
+    pub idle_timeout: Option<i16>,
+    pub lonely_leave_delay: Option<i16>,
+    pub follow_mode: FollowMode,
     pub repeated_chars: i16,
     pub prefix: String,
     pub target_lang: Option<String>,
@@ -62,7 +81,26 @@ pub struct GuildRow {
     pub require_voice: bool,
     pub text_in_voice: bool,
     pub audience_ignore: bool,
+    pub normalize_loudness: bool,
     pub msg_length: u16,
+    pub master_volume: u16,
+    pub tts_file_length: u16,
+    pub audiobook_quota: u32,
+    pub length_policy: LengthPolicy,
+    pub daily_quota: Option<u32>,
+    pub monthly_quota: Option<u32>,
+    pub user_daily_quota: Option<u32>,
+    pub captions_channel: Option<ChannelId>,
+    pub transcript_channel: Option<ChannelId>,
+    pub transcript_threads: bool,
+    pub join_on_enter: bool,
+    pub announce_joins: bool,
+    pub announce_leaves: bool,
+    pub greetings: bool,
+    pub transcript_retention: Option<u16>,
+    pub idle_timeout: Option<u16>,
+    pub lonely_leave_delay: Option<u16>,
+    pub follow_mode: FollowMode,
     pub repeated_chars: Option<NonZeroU8>,
     pub prefix: ArrayString<8>,
     pub target_lang: Option<ArrayString<8>>,
@@ -93,6 +131,19 @@ impl Compact for GuildRowRaw {
             premium_user: self.premium_user.map(|id| UserId::new(id as u64)),
             required_role: self.required_role.map(|id| RoleId::new(id as u64)),
             msg_length: self.msg_length as u16,
+            master_volume: self.master_volume as u16,
+            tts_file_length: self.tts_file_length as u16,
+            audiobook_quota: self.audiobook_quota as u32,
+            length_policy: self.length_policy,
+            daily_quota: self.daily_quota.map(|q| q as u32),
+            monthly_quota: self.monthly_quota.map(|q| q as u32),
+            user_daily_quota: self.user_daily_quota.map(|q| q as u32),
+            captions_channel: self.captions_channel.map(|id| ChannelId::new(id as u64)),
+            transcript_channel: self.transcript_channel.map(|id| ChannelId::new(id as u64)),
+            transcript_retention: self.transcript_retention.map(|days| days as u16),
+            idle_timeout: self.idle_timeout.map(|mins| mins as u16),
+            lonely_leave_delay: self.lonely_leave_delay.map(|secs| secs as u16),
+            follow_mode: self.follow_mode,
             repeated_chars: NonZeroU8::new(self.repeated_chars as u8),
             prefix: truncate_convert(self.prefix, "guild.prefix"),
             target_lang: self
@@ -111,6 +162,12 @@ impl Compact for GuildRowRaw {
         .set_require_voice(self.require_voice)
         .set_text_in_voice(self.text_in_voice)
         .set_audience_ignore(self.audience_ignore)
+        .set_normalize_loudness(self.normalize_loudness)
+        .set_transcript_threads(self.transcript_threads)
+        .set_join_on_enter(self.join_on_enter)
+        .set_announce_joins(self.announce_joins)
+        .set_announce_leaves(self.announce_leaves)
+        .set_greetings(self.greetings)
     }
 }
 
@@ -124,8 +181,12 @@ pub struct UserRowRaw {
     pub dm_welcomed: bool,
     pub bot_banned: bool,
     pub use_new_formatting: bool,
+    pub captions_opted_in: bool,
     pub voice_mode: Option<TTSMode>,
     pub premium_voice_mode: Option<TTSMode>,
+    pub volume: i16,
+    pub pitch: i16,
+    pub audio_effect: AudioEffect,
 }
 
 #[bool_to_bitflags::bool_to_bitflags(owning_setters)]
@@ -135,8 +196,12 @@ pub struct UserRow {
     pub dm_welcomed: bool,
     pub bot_banned: bool,
     pub use_new_formatting: bool,
+    pub captions_opted_in: bool,
     pub voice_mode: Option<TTSMode>,
     pub premium_voice_mode: Option<TTSMode>,
+    pub volume: i16,
+    pub pitch: i16,
+    pub audio_effect: AudioEffect,
 }
 
 impl Compact for UserRowRaw {
@@ -145,12 +210,16 @@ impl Compact for UserRowRaw {
         Self::Compacted {
             voice_mode: self.voice_mode,
             premium_voice_mode: self.premium_voice_mode,
+            volume: self.volume,
+            pitch: self.pitch,
+            audio_effect: self.audio_effect,
             __generated_flags: UserRowGeneratedFlags::empty(),
         }
         .set_dm_blocked(self.dm_blocked)
         .set_dm_welcomed(self.dm_welcomed)
         .set_bot_banned(self.bot_banned)
         .set_use_new_formatting(self.use_new_formatting)
+        .set_captions_opted_in(self.captions_opted_in)
     }
 }
 
@@ -225,9 +294,41 @@ impl Compact for UserVoiceRowRaw {
     }
 }
 
+/// A role or channel voice default, keyed by `(guild_id, role_id | channel_id)`.
+#[derive(sqlx::FromRow)]
+pub struct ScopedVoiceRowRaw {
+    pub guild_id: i64,
+    pub mode: TTSMode,
+    pub voice: Option<String>,
+    pub speaking_rate: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, TypeSize)]
+pub struct ScopedVoiceRow {
+    pub guild_id: Option<GuildId>,
+    pub mode: TTSMode,
+    pub voice: Option<ArrayString<MAX_VOICE_LENGTH>>,
+    pub speaking_rate: Option<f32>,
+}
+
+impl Compact for ScopedVoiceRowRaw {
+    type Compacted = ScopedVoiceRow;
+    fn compact(self) -> Self::Compacted {
+        Self::Compacted {
+            guild_id: (self.guild_id != 0).then(|| GuildId::new(self.guild_id as u64)),
+            mode: self.mode,
+            voice: self
+                .voice
+                .map(|v| truncate_convert(v, "scopedvoicerow.voice")),
+            speaking_rate: self.speaking_rate,
+        }
+    }
+}
+
 #[derive(Debug, TypeSize, sqlx::FromRow)]
 pub struct NicknameRow {
     pub name: Option<String>,
+    pub greeting: Option<String>,
 }
 
 pub type NicknameRowRaw = NicknameRow;
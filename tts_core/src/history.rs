@@ -0,0 +1,233 @@
+//! Remembering the messages recently read out in each guild, so they can be replayed
+//! if someone talked over the bot.
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use poise::serenity_prelude as serenity;
+use serenity::{CreateInteractionResponse, GuildId, UserId};
+
+use crate::{
+    coalesce,
+    structs::{Data, Result, TTSMode},
+};
+
+/// How many messages are remembered per guild.
+pub const HISTORY_LEN: usize = 10;
+/// How long a guild's history is kept after the last message was read out.
+const HISTORY_IDLE: Duration = Duration::from_secs(60 * 60);
+const REPLAY_CUSTOM_ID_PREFIX: &str = "history::replay::";
+
+pub struct Item {
+    pub id: u64,
+    pub author: UserId,
+    pub text: String,
+    pub voice: String,
+    pub mode: TTSMode,
+    pub spoken_at: serenity::Timestamp,
+    /// The audio as it was played, set once it has been fully generated.
+    pub audio: OnceLock<Vec<u8>>,
+}
+
+impl Item {
+    #[must_use]
+    pub fn replay_custom_id(&self) -> String {
+        format!("{REPLAY_CUSTOM_ID_PREFIX}{}", self.id)
+    }
+
+    /// Fills in the audio of a streamed message once the stream has finished.
+    pub fn set_audio_when_streamed(self: Arc<Self>, full_audio: coalesce::Pending) {
+        tokio::spawn(async move {
+            if let Some(Some(audio)) = coalesce::wait(full_audio).await {
+                _ = self.audio.set(audio.bytes.clone());
+            }
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ReplayFailure {
+    NotInVoice,
+    AudioUnavailable,
+    Expired,
+}
+
+impl ReplayFailure {
+    #[must_use]
+    pub const fn message(self) -> &'static str {
+        match self {
+            Self::NotInVoice => {
+                "You need to be in the same voice channel as me to replay messages!"
+            }
+            Self::AudioUnavailable => "That message didn't finish playing, so cannot be replayed.",
+            Self::Expired => "That message is too old to be replayed.",
+        }
+    }
+}
+
+type GuildHistory = Arc<Mutex<VecDeque<Arc<Item>>>>;
+
+pub struct History {
+    guilds: mini_moka::sync::Cache<GuildId, GuildHistory>,
+    next_id: AtomicU64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            guilds: mini_moka::sync::Cache::builder()
+                .time_to_idle(HISTORY_IDLE)
+                .build(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl History {
+    /// Remembers a message which has been read out, forgetting the oldest if full.
+    pub fn push(
+        &self,
+        guild_id: GuildId,
+        author: UserId,
+        text: String,
+        voice: String,
+        mode: TTSMode,
+    ) -> Arc<Item> {
+        let item = Arc::new(Item {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            author,
+            text,
+            voice,
+            mode,
+            spoken_at: serenity::Timestamp::now(),
+            audio: OnceLock::new(),
+        });
+
+        let history = self.guilds.get(&guild_id).unwrap_or_else(|| {
+            let history = GuildHistory::default();
+            self.guilds.insert(guild_id, history.clone());
+            history
+        });
+
+        let mut history = history.lock();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+
+        history.push_back(item.clone());
+        item
+    }
+
+    /// The remembered messages of `guild_id`, newest first.
+    #[must_use]
+    pub fn recent(&self, guild_id: GuildId) -> Vec<Arc<Item>> {
+        let Some(history) = self.guilds.get(&guild_id) else {
+            return Vec::new();
+        };
+
+        history.lock().iter().rev().cloned().collect()
+    }
+
+    #[must_use]
+    pub fn latest(&self, guild_id: GuildId) -> Option<Arc<Item>> {
+        let history = self.guilds.get(&guild_id)?;
+        history.lock().back().cloned()
+    }
+
+    #[must_use]
+    pub fn get(&self, guild_id: GuildId, id: u64) -> Option<Arc<Item>> {
+        let history = self.guilds.get(&guild_id)?;
+        history.lock().iter().find(|item| item.id == id).cloned()
+    }
+}
+
+/// Plays `item` again in the voice channel of `guild_id`, if `user_id` is listening.
+pub async fn replay(
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    item: &Item,
+) -> Result<Result<(), ReplayFailure>> {
+    let data = ctx.data_ref::<Data>();
+    let Some(call_lock) = data.songbird.get(guild_id) else {
+        return Ok(Err(ReplayFailure::NotInVoice));
+    };
+
+    let bot_channel = call_lock.lock().await.current_channel();
+    let user_channel = ctx.cache.guild(guild_id).and_then(|guild| {
+        guild
+            .voice_states
+            .get(&user_id)
+            .and_then(|vs| vs.channel_id)
+    });
+
+    let in_same_channel = bot_channel
+        .zip(user_channel)
+        .is_some_and(|(bot, user)| bot.get() == user.get());
+
+    if !in_same_channel {
+        return Ok(Err(ReplayFailure::NotInVoice));
+    }
+
+    let Some(audio) = item.audio.get() else {
+        return Ok(Err(ReplayFailure::AudioUnavailable));
+    };
+
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+    let track = songbird::tracks::Track::new(songbird::input::Input::from(audio.clone()))
+        .volume(f32::from(guild_row.master_volume) / 100.0);
+
+    call_lock.lock().await.enqueue(track).await;
+    data.analytics.log("history_replay".into(), false);
+    Ok(Ok(()))
+}
+
+/// Handles the replay buttons sent by `/history`.
+pub async fn handle_replay_button(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+) -> Result<()> {
+    let Some(id) = interaction
+        .data
+        .custom_id
+        .strip_prefix(REPLAY_CUSTOM_ID_PREFIX)
+    else {
+        return Ok(());
+    };
+
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+
+    let data = ctx.data_ref::<Data>();
+    let item = id
+        .parse()
+        .ok()
+        .and_then(|id| data.history.get(guild_id, id));
+
+    let result = match item {
+        Some(item) => replay(ctx, guild_id, interaction.user.id, &item).await?,
+        None => Err(ReplayFailure::Expired),
+    };
+
+    let content = match result {
+        Ok(()) => "Replaying the message!",
+        Err(failure) => failure.message(),
+    };
+
+    let response = serenity::CreateInteractionResponseMessage::default()
+        .ephemeral(true)
+        .content(content);
+
+    interaction
+        .create_response(&ctx.http, CreateInteractionResponse::Message(response))
+        .await?;
+
+    Ok(())
+}
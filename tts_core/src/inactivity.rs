@@ -0,0 +1,172 @@
+//! Leaving voice channels which are no longer being used, either because nothing has
+//! been read out for a while or because everyone else has left.
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId};
+use tokio::task::JoinHandle;
+
+use crate::{
+    errors, sessions,
+    structs::{Data, Result},
+};
+
+/// How often each call is checked for having gone idle.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy)]
+pub enum LeaveReason {
+    Idle,
+    Lonely,
+}
+
+impl LeaveReason {
+    const fn notice(self) -> &'static str {
+        match self {
+            Self::Idle => "I have left the voice channel as nothing has been read out for a while.",
+            Self::Lonely => "I have left the voice channel as everyone else has left.",
+        }
+    }
+
+    const fn analytics_event(self) -> &'static str {
+        match self {
+            Self::Idle => "idle_leave",
+            Self::Lonely => "lonely_leave",
+        }
+    }
+}
+
+/// The timers deciding when to leave each voice call.
+#[derive(Default)]
+pub struct VoiceTimers {
+    last_activity: DashMap<GuildId, Instant>,
+    idle_timers: DashMap<GuildId, JoinHandle<()>>,
+    lonely_timers: DashMap<GuildId, JoinHandle<()>>,
+}
+
+impl VoiceTimers {
+    /// Marks the call in `guild_id` as in use, resetting the idle timeout.
+    pub fn record_activity(&self, guild_id: GuildId) {
+        self.last_activity.insert(guild_id, Instant::now());
+    }
+
+    /// Starts the idle timer for a newly connected call.
+    pub fn start_idle(&self, ctx: &serenity::Context, guild_id: GuildId) {
+        self.record_activity(guild_id);
+
+        let ctx = ctx.clone();
+        let handle = tokio::spawn(async move {
+            if let Err(err) = idle_timer(&ctx, guild_id).await {
+                report_error(&ctx, "Idle timer", err).await;
+            }
+        });
+
+        if let Some(old_handle) = self.idle_timers.insert(guild_id, handle) {
+            old_handle.abort();
+        }
+    }
+
+    /// Leaves the call in `guild_id` after `delay`, unless someone has rejoined by then.
+    pub fn start_lonely(&self, ctx: &serenity::Context, guild_id: GuildId, delay: Duration) {
+        let ctx = ctx.clone();
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(err) = leave_if_lonely(&ctx, guild_id).await {
+                report_error(&ctx, "Lonely timer", err).await;
+            }
+        });
+
+        // Only the latest time everyone left should count.
+        if let Some(old_handle) = self.lonely_timers.insert(guild_id, handle) {
+            old_handle.abort();
+        }
+    }
+
+    /// Stops all timers for `guild_id`, as the bot has left the call.
+    pub fn stop(&self, guild_id: GuildId) {
+        self.last_activity.remove(&guild_id);
+        for timers in [&self.idle_timers, &self.lonely_timers] {
+            if let Some((_, handle)) = timers.remove(&guild_id) {
+                handle.abort();
+            }
+        }
+    }
+}
+
+async fn report_error(ctx: &serenity::Context, name: &'static str, err: anyhow::Error) {
+    if let Err(err_err) = errors::handle_unexpected_default(ctx, name, err).await {
+        tracing::error!("Error in {name}: {err_err:?}");
+    }
+}
+
+async fn idle_timer(ctx: &serenity::Context, guild_id: GuildId) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    loop {
+        tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+
+        let Some(call) = data.songbird.get(guild_id) else {
+            return Ok(());
+        };
+
+        // Long messages and replays still count as the call being in use.
+        if !call.lock().await.queue().is_empty() {
+            data.voice_timers.record_activity(guild_id);
+            continue;
+        }
+
+        // Read every check, as the timeout may be changed mid-call.
+        let guild_row = data.guilds_db.get(guild_id.into()).await?;
+        let Some(idle_timeout) = guild_row.idle_timeout else {
+            continue;
+        };
+
+        let idle_timeout = Duration::from_secs(u64::from(idle_timeout) * 60);
+        let last_activity = data
+            .voice_timers
+            .last_activity
+            .get(&guild_id)
+            .map(|last_activity| *last_activity);
+
+        if last_activity.is_some_and(|last_activity| last_activity.elapsed() >= idle_timeout) {
+            return leave(ctx, guild_id, LeaveReason::Idle).await;
+        }
+    }
+}
+
+async fn leave_if_lonely(ctx: &serenity::Context, guild_id: GuildId) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    let Some(call) = data.songbird.get(guild_id) else {
+        return Ok(());
+    };
+
+    let Some(channel_id) = call.lock().await.current_channel() else {
+        return Ok(());
+    };
+
+    let channel_id = ChannelId::new(channel_id.get());
+    if sessions::has_listeners(ctx, guild_id, channel_id) {
+        return Ok(());
+    }
+
+    leave(ctx, guild_id, LeaveReason::Lonely).await
+}
+
+/// Leaves the call in `guild_id`, telling the setup channel why.
+pub async fn leave(ctx: &serenity::Context, guild_id: GuildId, reason: LeaveReason) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+
+    // Sent before leaving, as leaving stops this guild's timers.
+    if let Some(channel) = guild_row.channel
+        && let Err(err) = channel.widen().say(&ctx.http, reason.notice()).await
+    {
+        tracing::debug!("Failed to send leave notice to {guild_id}: {err:?}");
+    }
+
+    data.analytics.log(reason.analytics_event().into(), false);
+    data.last_to_xsaid_tracker.remove(&guild_id);
+    data.songbird.remove(guild_id).await?;
+    data.watchdog.forget(guild_id);
+    Ok(())
+}
@@ -1,12 +1,26 @@
 #![allow(async_fn_in_trait)]
 
 pub mod analytics;
+pub mod announcements;
+pub mod audio;
+pub mod audiobook;
+pub mod autojoin;
+pub mod captions;
+pub mod coalesce;
 pub mod common;
 pub mod constants;
 pub mod database;
 pub mod database_models;
 pub mod errors;
+pub mod history;
+pub mod inactivity;
+pub mod limiter;
 pub mod macros;
 pub mod opt_ext;
+pub mod sessions;
 pub mod structs;
 pub mod traits;
+pub mod transcript;
+pub mod usage;
+pub mod watchdog;
+pub mod word_filter;
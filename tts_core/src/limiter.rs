@@ -0,0 +1,298 @@
+//! Limiting how many requests are sent to the paid TTS backends at once, sharing
+//! the capacity fairly between guilds so one busy guild cannot starve the rest.
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use poise::serenity_prelude::GuildId;
+use tokio::sync::oneshot;
+
+use crate::{
+    analytics,
+    structs::{MainConfig, TTSMode},
+};
+
+/// The longest a request may be expected to wait in the queue before being rejected,
+/// if not set in the config.
+const DEFAULT_MAX_QUEUE_WAIT: Duration = Duration::from_secs(10);
+/// How many concurrent requests each backend allows, if not set in the config.
+const DEFAULT_OPENAI_CAPACITY: usize = 16;
+const DEFAULT_GCLOUD_CAPACITY: usize = 32;
+const DEFAULT_POLLY_CAPACITY: usize = 32;
+/// How long to wait between telling a guild that messages are being skipped.
+const NOTICE_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How much of the capacity a premium guild gets, compared to a normal guild.
+const PREMIUM_WEIGHT: u64 = 2;
+/// The virtual cost of a request, which is divided by the guild's weight.
+const REQUEST_COST: u64 = 2520;
+/// The estimated time a request takes, before any requests have finished.
+const INITIAL_SERVICE_TIME: Duration = Duration::from_secs(2);
+
+struct Waiter {
+    guild_id: Option<GuildId>,
+    enqueued_at: Instant,
+    sender: oneshot::Sender<Permit>,
+}
+
+#[derive(Default)]
+struct GuildLane {
+    /// The virtual finish time of the guild's last queued request.
+    last_finish: u64,
+    queued: usize,
+}
+
+struct State {
+    in_flight: usize,
+    /// Waiting requests, ordered by virtual finish time then arrival.
+    queue: BTreeMap<(u64, u64), Waiter>,
+    lanes: HashMap<Option<GuildId>, GuildLane>,
+    /// The virtual finish time of the last request to be started.
+    virtual_time: u64,
+    next_seq: u64,
+    /// A moving average of how long each request holds its permit.
+    service_time: Duration,
+}
+
+impl State {
+    fn remove_queued(&mut self, guild_id: Option<GuildId>) {
+        if let Some(lane) = self.lanes.get_mut(&guild_id) {
+            lane.queued -= 1;
+            if lane.queued == 0 {
+                self.lanes.remove(&guild_id);
+            }
+        }
+    }
+}
+
+/// A weighted fair queue in front of a single backend.
+struct BackendLimiter {
+    mode: TTSMode,
+    capacity: usize,
+    max_queue_wait: Duration,
+    state: Mutex<State>,
+    analytics: Arc<analytics::Handler>,
+}
+
+impl BackendLimiter {
+    fn new(
+        mode: TTSMode,
+        capacity: usize,
+        max_queue_wait: Duration,
+        analytics: Arc<analytics::Handler>,
+    ) -> Self {
+        Self {
+            mode,
+            capacity,
+            max_queue_wait,
+            analytics,
+            state: Mutex::new(State {
+                in_flight: 0,
+                queue: BTreeMap::new(),
+                lanes: HashMap::new(),
+                virtual_time: 0,
+                next_seq: 0,
+                service_time: INITIAL_SERVICE_TIME,
+            }),
+        }
+    }
+
+    fn log(&self, event: &str) {
+        let mode: &'static str = self.mode.into();
+        self.analytics.log(Cow::Owned(format!("{mode}_{event}")), false);
+    }
+
+    fn log_wait(&self, wait: Duration) {
+        self.log(match wait.as_millis() {
+            0..100 => "queue_wait_under_100ms",
+            100..1000 => "queue_wait_under_1s",
+            1000..5000 => "queue_wait_under_5s",
+            _ => "queue_wait_over_5s",
+        });
+    }
+
+    async fn acquire(
+        self: &Arc<Self>,
+        guild_id: Option<GuildId>,
+        is_premium: bool,
+    ) -> Option<Permit> {
+        let (key, receiver) = {
+            let mut state = self.state.lock();
+            if state.in_flight < self.capacity && state.queue.is_empty() {
+                state.in_flight += 1;
+                drop(state);
+
+                self.log_wait(Duration::ZERO);
+                return Some(Permit::new(Some(self.clone())));
+            }
+
+            let weight = if is_premium { PREMIUM_WEIGHT } else { 1 };
+            let last_finish = state.lanes.get(&guild_id).map(|lane| lane.last_finish);
+            let finish =
+                last_finish.unwrap_or_default().max(state.virtual_time) + REQUEST_COST / weight;
+
+            // Only requests which finish before this one in virtual time are started first.
+            let ahead = state.queue.range(..(finish, 0)).count() as u32;
+            let estimated_wait = state.service_time * (ahead + 1) / self.capacity as u32;
+            if estimated_wait > self.max_queue_wait {
+                drop(state);
+                self.log("queue_rejected");
+                return None;
+            }
+
+            let lane = state.lanes.entry(guild_id).or_default();
+            lane.last_finish = finish;
+            lane.queued += 1;
+
+            let seq = state.next_seq;
+            state.next_seq += 1;
+
+            let (sender, receiver) = oneshot::channel();
+            let waiter = Waiter {
+                guild_id,
+                sender,
+                enqueued_at: Instant::now(),
+            };
+
+            state.queue.insert((finish, seq), waiter);
+            ((finish, seq), receiver)
+        };
+
+        // The estimate can be wrong, so a request never waits for much longer than it.
+        match tokio::time::timeout(self.max_queue_wait * 2, receiver).await {
+            Ok(Ok(permit)) => Some(permit),
+            Ok(Err(_)) => None,
+            Err(_) => {
+                let mut state = self.state.lock();
+                if state.queue.remove(&key).is_some() {
+                    state.remove_queued(guild_id);
+                }
+
+                // If the request was started in the meantime, the permit was dropped along
+                // with the receiver, so has already been given to the next request.
+                drop(state);
+                self.log("queue_timed_out");
+                None
+            }
+        }
+    }
+
+    /// Frees up the permit held for `held`, starting the next queued request if any.
+    fn release(self: &Arc<Self>, held: Duration) {
+        let waiter = {
+            let mut state = self.state.lock();
+            state.service_time = (state.service_time * 7 + held) / 8;
+
+            let Some(((finish, _), waiter)) = state.queue.pop_first() else {
+                state.in_flight -= 1;
+                return;
+            };
+
+            state.virtual_time = finish;
+            state.remove_queued(waiter.guild_id);
+            waiter
+        };
+
+        self.log_wait(waiter.enqueued_at.elapsed());
+
+        // If the waiter gave up, the permit is dropped and released again.
+        _ = waiter.sender.send(Permit::new(Some(self.clone())));
+    }
+}
+
+/// Allows a single request to a backend, which must be held until the request is done.
+#[must_use]
+pub struct Permit {
+    /// The limiter to release the permit to, `None` for unlimited backends.
+    limiter: Option<Arc<BackendLimiter>>,
+    acquired_at: Instant,
+}
+
+impl Permit {
+    fn new(limiter: Option<Arc<BackendLimiter>>) -> Self {
+        Self {
+            limiter,
+            acquired_at: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.release(self.acquired_at.elapsed());
+        }
+    }
+}
+
+/// Limits the concurrent requests to each paid backend.
+pub struct SynthesisLimiter {
+    openai: Arc<BackendLimiter>,
+    gcloud: Arc<BackendLimiter>,
+    polly: Arc<BackendLimiter>,
+    notified: mini_moka::sync::Cache<Option<GuildId>, ()>,
+}
+
+impl SynthesisLimiter {
+    #[must_use]
+    pub fn new(analytics: &Arc<analytics::Handler>, config: &MainConfig) -> Self {
+        let max_queue_wait = config
+            .max_queue_wait_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_MAX_QUEUE_WAIT);
+
+        let limiter = |mode, capacity| {
+            let limiter = BackendLimiter::new(mode, capacity, max_queue_wait, analytics.clone());
+            Arc::new(limiter)
+        };
+
+        let openai_capacity = config.openai_capacity.unwrap_or(DEFAULT_OPENAI_CAPACITY);
+        let gcloud_capacity = config.gcloud_capacity.unwrap_or(DEFAULT_GCLOUD_CAPACITY);
+        let polly_capacity = config.polly_capacity.unwrap_or(DEFAULT_POLLY_CAPACITY);
+
+        Self {
+            openai: limiter(TTSMode::OpenAI, openai_capacity),
+            gcloud: limiter(TTSMode::gCloud, gcloud_capacity),
+            polly: limiter(TTSMode::Polly, polly_capacity),
+            notified: mini_moka::sync::Cache::builder()
+                .time_to_live(NOTICE_COOLDOWN)
+                .build(),
+        }
+    }
+
+    /// Waits for a turn to send a request to the backend for `mode`.
+    ///
+    /// Returns `None` if the wait would be too long, and the request should be skipped.
+    /// The free backends are not limited, so always return a permit immediately.
+    pub async fn acquire(
+        &self,
+        mode: TTSMode,
+        guild_id: Option<GuildId>,
+        is_premium: bool,
+    ) -> Option<Permit> {
+        let limiter = match mode {
+            TTSMode::OpenAI => &self.openai,
+            TTSMode::gCloud => &self.gcloud,
+            TTSMode::Polly => &self.polly,
+            TTSMode::gTTS | TTSMode::eSpeak => return Some(Permit::new(None)),
+        };
+
+        limiter.acquire(guild_id, is_premium).await
+    }
+
+    /// Checks if `guild_id` should be told about a skipped request, to avoid spamming
+    /// a notice for every message while the backend is overloaded.
+    #[must_use]
+    pub fn should_notify(&self, guild_id: Option<GuildId>) -> bool {
+        if self.notified.contains_key(&guild_id) {
+            false
+        } else {
+            self.notified.insert(guild_id, ());
+            true
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Remembering which voice channels the bot is in, so they can be rejoined after a restart.
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GenericChannelId, GuildId, UserId};
+
+use crate::{
+    structs::{Data, JoinVCToken, Result},
+    traits::SongbirdManagerExt as _,
+};
+
+/// How long to wait for the guilds of a shard to be cached before restoring sessions.
+const CACHE_WAIT: Duration = Duration::from_secs(60);
+const CACHE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    guild_id: i64,
+    channel_id: i64,
+}
+
+/// Who summoned the bot into a voice channel, and where from.
+#[derive(Clone, Copy)]
+pub struct Summoner {
+    pub user_id: UserId,
+    pub text_channel_id: GenericChannelId,
+}
+
+/// Records that the bot is in `channel_id`, optionally with who summoned it, which is
+/// kept from the previous save if not given.
+pub async fn save(
+    data: &Data,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    summoner: Option<Summoner>,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO voice_sessions(guild_id, channel_id, text_channel_id, summoner_id)
+        VALUES($1, $2, $3, $4)
+
+        ON CONFLICT (guild_id)
+        DO UPDATE SET
+            channel_id = EXCLUDED.channel_id,
+            text_channel_id = COALESCE(EXCLUDED.text_channel_id, voice_sessions.text_channel_id),
+            summoner_id = COALESCE(EXCLUDED.summoner_id, voice_sessions.summoner_id)",
+    )
+    .bind(guild_id.get() as i64)
+    .bind(channel_id.get() as i64)
+    .bind(summoner.map(|s| s.text_channel_id.get() as i64))
+    .bind(summoner.map(|s| s.user_id.get() as i64))
+    .execute(&data.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn remove(data: &Data, guild_id: GuildId) -> Result<()> {
+    sqlx::query("DELETE FROM voice_sessions WHERE guild_id = $1")
+        .bind(guild_id.get() as i64)
+        .execute(&data.pool)
+        .await?;
+
+    Ok(())
+}
+
+/// The text channel the bot was summoned from in `guild_id`, if known.
+pub async fn text_channel(data: &Data, guild_id: GuildId) -> Result<Option<GenericChannelId>> {
+    let text_channel_id: Option<Option<i64>> =
+        sqlx::query_scalar("SELECT text_channel_id FROM voice_sessions WHERE guild_id = $1")
+            .bind(guild_id.get() as i64)
+            .fetch_optional(&data.pool)
+            .await?;
+
+    Ok(text_channel_id
+        .flatten()
+        .map(|id| GenericChannelId::new(id as u64)))
+}
+
+/// The user who summoned the bot in `guild_id`, if known.
+pub async fn summoner(data: &Data, guild_id: GuildId) -> Result<Option<UserId>> {
+    let summoner_id: Option<Option<i64>> =
+        sqlx::query_scalar("SELECT summoner_id FROM voice_sessions WHERE guild_id = $1")
+            .bind(guild_id.get() as i64)
+            .fetch_optional(&data.pool)
+            .await?;
+
+    Ok(summoner_id.flatten().map(|id| UserId::new(id as u64)))
+}
+
+/// Checks if `channel_id` still exists and has someone to read messages out to.
+#[must_use]
+pub fn has_listeners(
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> bool {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return false;
+    };
+
+    guild.channels.contains_key(&channel_id)
+        && guild
+            .voice_states
+            .iter()
+            .filter(|vs| vs.channel_id == Some(channel_id))
+            .filter_map(|vs| guild.members.get(&vs.user_id))
+            .any(|member| !member.user.bot())
+}
+
+/// Rejoins the saved voice sessions in `guild_ids`, once they have been cached.
+///
+/// Sessions whose channel has been deleted or emptied are removed instead.
+pub async fn restore(ctx: &serenity::Context, guild_ids: &[GuildId]) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    let ids: Vec<i64> = guild_ids.iter().map(|id| id.get() as i64).collect();
+    let sessions: Vec<SessionRow> =
+        sqlx::query_as("SELECT guild_id, channel_id FROM voice_sessions WHERE guild_id = ANY($1)")
+            .bind(ids)
+            .fetch_all(&data.pool)
+            .await?;
+
+    if sessions.is_empty() {
+        return Ok(());
+    }
+
+    let wait_start = std::time::Instant::now();
+    while wait_start.elapsed() < CACHE_WAIT
+        && !sessions
+            .iter()
+            .all(|s| ctx.cache.guild(GuildId::new(s.guild_id as u64)).is_some())
+    {
+        tokio::time::sleep(CACHE_POLL_INTERVAL).await;
+    }
+
+    let mut restored = 0_usize;
+    for session in sessions {
+        let guild_id = GuildId::new(session.guild_id as u64);
+        let channel_id = ChannelId::new(session.channel_id as u64);
+
+        // The guild may be unavailable due to an outage, so keep the session for later.
+        if ctx.cache.guild(guild_id).is_none() || data.songbird.get(guild_id).is_some() {
+            continue;
+        }
+
+        if !has_listeners(ctx, guild_id, channel_id) {
+            remove(data, guild_id).await?;
+            continue;
+        }
+
+        let join_vc_token = JoinVCToken::acquire(data, guild_id);
+        match data.songbird.join_vc(join_vc_token, channel_id).await {
+            Ok(_) => {
+                restored += 1;
+                data.analytics.log("session_restored".into(), false);
+            }
+            Err(err) => {
+                tracing::warn!("Failed to restore voice session in {guild_id}: {err:?}");
+                remove(data, guild_id).await?;
+            }
+        }
+    }
+
+    if restored != 0 {
+        tracing::info!("Shard {}: Restored {restored} voice sessions", ctx.shard_id);
+    }
+
+    Ok(())
+}
@@ -23,7 +23,10 @@ use poise::serenity_prelude::{
     ChannelId, GuildId, RoleId, SkuId, UserId,
 };
 
-use crate::{analytics, bool_enum, common::timestamp_in_future, database};
+use crate::{
+    analytics, announcements, bool_enum, coalesce, common::timestamp_in_future, database, history, inactivity,
+    limiter, transcript, usage, watchdog, word_filter,
+};
 
 macro_rules! into_static_display {
     ($struct:ident, max_length($len:literal)) => {
@@ -56,6 +59,8 @@ pub struct Config {
     pub premium: Option<PremiumConfig>,
     #[serde(rename = "Bot-List-Tokens")]
     pub bot_list_tokens: Option<BotListTokens>,
+    #[serde(rename = "STT-Info")]
+    pub stt: Option<SttConfig>,
 }
 
 #[derive(serde::Deserialize)]
@@ -71,6 +76,16 @@ pub struct MainConfig {
     pub main_server: GuildId,
     pub ofs_role: RoleId,
     pub openai_api_key: Option<FixedString>,
+    /// Overrides the OpenAI API base URL, such as for a compatible proxy.
+    pub openai_api_base: Option<reqwest::Url>,
+    /// How many seconds to let queued messages finish playing when shutting down.
+    pub shutdown_grace_secs: Option<u64>,
+    /// How many requests may be sent to each paid backend at once.
+    pub openai_capacity: Option<usize>,
+    pub gcloud_capacity: Option<usize>,
+    pub polly_capacity: Option<usize>,
+    /// How many seconds a message may be expected to wait for a paid backend before being skipped.
+    pub max_queue_wait_secs: Option<u64>,
 
     // Only for situations where gTTS has broken
     #[serde(default)]
@@ -115,6 +130,25 @@ pub struct PremiumConfig {
     pub extra_sku: SkuId,
 }
 
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum SttKind {
+    /// An OpenAI compatible `/v1/audio/transcriptions` endpoint.
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// The `/inference` endpoint of a whisper.cpp server.
+    WhisperServer,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SttConfig {
+    pub kind: SttKind,
+    pub url: reqwest::Url,
+    /// Falls back to `openai_api_key` for the OpenAI backend.
+    pub api_key: Option<FixedString>,
+    pub model: Option<FixedString>,
+}
+
 pub struct WebhookConfig {
     pub logs: Option<serenity::Webhook>,
     pub errors: Option<serenity::Webhook>,
@@ -249,15 +283,32 @@ pub struct Data {
     pub user_voice_db: database::Handler<(i64, TTSMode), database::UserVoiceRowRaw>,
     pub guild_voice_db: database::Handler<(i64, TTSMode), database::GuildVoiceRowRaw>,
     pub user_opt_out_db: database::Handler<[i64; 2], database::UserOptOutRowRaw>,
+    pub role_voice_db: database::Handler<[i64; 2], database::ScopedVoiceRowRaw>,
+    pub channel_voice_db: database::Handler<[i64; 2], database::ScopedVoiceRowRaw>,
 
     pub entitlement_cache: mini_moka::sync::Cache<UserId, CachedEntitlement>,
+    pub voice_preview_cache: mini_moka::sync::Cache<(TTSMode, FixedString<u8>), Arc<[u8]>>,
+    /// Guilds which have recently been told they have run out of quota.
+    pub quota_notices: mini_moka::sync::Cache<GuildId, ()>,
+    pub usage_cache: usage::UsageCache,
+    pub word_filter: word_filter::WordFilter,
+    pub join_on_enter_cooldowns: mini_moka::sync::Cache<GuildId, ()>,
     pub join_vc_tokens: DashMap<GuildId, Arc<tokio::sync::Mutex<()>>>,
+    pub synthesis_limiter: limiter::SynthesisLimiter,
+    pub in_flight: coalesce::InFlight,
+    pub transcripts: transcript::Transcripts,
+    pub history: history::History,
+    pub voice_timers: inactivity::VoiceTimers,
+    pub announcement_limiter: announcements::AnnouncementLimiter,
+    pub watchdog: watchdog::Watchdog,
     pub last_to_xsaid_tracker: LastToXsaidTracker,
     pub startup_message: Option<serenity::MessageId>,
     pub premium_avatar_url: FixedString<u16>,
     pub system_info: Mutex<sysinfo::System>,
     pub start_time: std::time::SystemTime,
     pub reqwest: reqwest::Client,
+    /// The client for OpenAI TTS, if an API key has been configured.
+    pub openai: Option<async_openai::Client<async_openai::config::OpenAIConfig>>,
     pub regex_cache: RegexCache,
     pub webhooks: WebhookConfig,
     pub pool: sqlx::PgPool,
@@ -266,11 +317,14 @@ pub struct Data {
 
     pub config: MainConfig,
     pub premium_config: Option<PremiumConfig>,
+    pub stt_config: Option<SttConfig>,
 
     // Startup information
     pub website_info: Mutex<Option<WebsiteInfo>>,
     pub bot_list_tokens: Mutex<Option<BotListTokens>>,
     pub fully_started: std::sync::atomic::AtomicBool,
+    /// Set once shutdown has begun, to stop new messages from being read out.
+    pub shutting_down: std::sync::atomic::AtomicBool,
     pub update_startup_lock: tokio::sync::Mutex<()>,
 
     pub espeak_voices: FixedArray<FixedString<u8>>,
@@ -288,19 +342,6 @@ impl std::fmt::Debug for Data {
 }
 
 impl Data {
-    pub async fn speaking_rate(&self, user_id: UserId, mode: TTSMode) -> Result<Cow<'static, str>> {
-        let row = self.user_voice_db.get((user_id.into(), mode)).await?;
-
-        Ok(match row.speaking_rate {
-            Some(r) => Cow::Owned(r.to_string()),
-            None => Cow::Borrowed(
-                mode.speaking_rate_info()
-                    .map(|info| info.default)
-                    .unwrap_or("1.0"),
-            ),
-        })
-    }
-
     async fn fetch_patreon_info(&self, user_id: UserId) -> Result<Option<PremiumInfo>> {
         if let Some(config) = &self.premium_config {
             let mut url = config.patreon_service.clone();
@@ -421,42 +462,94 @@ impl Data {
         http: &serenity::Http,
         author_id: UserId,
         guild_id: Option<GuildId>,
-    ) -> Result<(Cow<'static, str>, TTSMode, OpenAIModel, Option<String>)> {
+        scope: VoiceScope<'_>,
+    ) -> Result<VoiceSettings> {
         let info = if let Some(guild_id) = guild_id {
             Some((guild_id, self.is_premium_simple(http, guild_id).await?))
         } else {
             None
         };
 
-        self.parse_user_or_guild_with_premium(author_id, info).await
+        self.parse_user_or_guild_with_premium(author_id, info, scope)
+            .await
     }
 
+    /// Fetches the highest priority role default and the channel default, if set.
+    async fn get_scoped_voice_rows(
+        &self,
+        guild_id: GuildId,
+        scope: VoiceScope<'_>,
+    ) -> Result<[Option<(VoiceSource, Arc<database::ScopedVoiceRow>)>; 2]> {
+        let mut role_row = None;
+        for &role_id in scope.roles {
+            let row = self
+                .role_voice_db
+                .get([guild_id.into(), role_id.get() as i64])
+                .await?;
+
+            if row.guild_id.is_some() {
+                role_row = Some((VoiceSource::Role(role_id), row));
+                break;
+            }
+        }
+
+        let mut channel_row = None;
+        if let Some(channel_id) = scope.channel_id {
+            let row = self
+                .channel_voice_db
+                .get([guild_id.into(), channel_id.get() as i64])
+                .await?;
+
+            if row.guild_id.is_some() {
+                channel_row = Some((VoiceSource::Channel(channel_id), row));
+            }
+        }
+
+        Ok([role_row, channel_row])
+    }
+
+    /// Resolves the voice settings for a user, with the precedence:
+    /// user -> role -> channel -> guild -> mode default.
     pub async fn parse_user_or_guild_with_premium(
         &self,
         author_id: UserId,
         guild_info: Option<(GuildId, bool)>,
-    ) -> Result<(Cow<'static, str>, TTSMode, OpenAIModel, Option<String>)> {
+        scope: VoiceScope<'_>,
+    ) -> Result<VoiceSettings> {
         let user_row = self.userinfo_db.get(author_id.into()).await?;
         let (guild_id, guild_is_premium) = match guild_info {
             Some((id, p)) => (Some(id), p),
             None => (None, false),
         };
 
+        let scoped_rows = match guild_id {
+            Some(guild_id) => self.get_scoped_voice_rows(guild_id, scope).await?,
+            None => [None, None],
+        };
+
         let mut guild_row = None;
-        let mut mode = {
+        let (mut mode, mut mode_source) = {
             let user_mode = if guild_is_premium {
                 user_row.premium_voice_mode
             } else {
                 user_row.voice_mode
             };
 
+            let scoped_mode = scoped_rows
+                .iter()
+                .flatten()
+                .map(|(source, row)| (row.mode, *source))
+                .next();
+
             if let Some(mode) = user_mode {
-                mode
+                (mode, VoiceSource::User)
+            } else if let Some(scoped_mode) = scoped_mode {
+                scoped_mode
             } else if let Some(guild_id) = guild_id {
                 guild_row = Some(self.guilds_db.get(guild_id.into()).await?);
-                guild_row.as_ref().unwrap().voice_mode
+                (guild_row.as_ref().unwrap().voice_mode, VoiceSource::Guild)
             } else {
-                TTSMode::gTTS
+                (TTSMode::gTTS, VoiceSource::Default)
             }
         };
 
@@ -467,7 +560,9 @@ impl Data {
         if mode.is_premium() && !guild_is_premium {
             mode = TTSMode::default();
 
-            if user_row.voice_mode.is_some_and(TTSMode::is_premium) {
+            if mode_source == VoiceSource::User
+                && user_row.voice_mode.is_some_and(TTSMode::is_premium)
+            {
                 warn!(
                     "User ID {author_id}'s normal voice mode is set to a premium mode! Resetting."
                 );
@@ -483,38 +578,116 @@ impl Data {
                     .set_one(guild_id.into(), "voice_mode", mode)
                     .await?;
             } else {
-                warn!("Guild {guild_id:?} - User {author_id} has a mode set to premium without being premium!");
+                warn!("Guild {guild_id:?} - User {author_id} has a mode set to premium without being premium! ({mode_source})");
             }
+
+            mode_source = VoiceSource::Default;
         }
 
         let user_voice_row = self.user_voice_db.get((author_id.into(), mode)).await?;
-        let (voice, openai_model, instruction) =
-            // Get user voice for user mode
-            if user_voice_row.user_id.is_some() {
-                let voice = user_voice_row.voice.map(|v| Cow::Owned(v.as_str().to_owned()));
-                let openai_model = user_voice_row.openai_model.unwrap_or_default();
-                let instruction = user_voice_row.openai_instruction.map(|i| i.as_str().to_owned());
-                (voice, openai_model, instruction)
-            } else if let Some(guild_id) = guild_id {
-                // Get default server voice for user mode
-                let guild_voice_row = self.guild_voice_db.get((guild_id.into(), mode)).await?;
-                if guild_voice_row.guild_id.is_some() {
-                    let voice = Some(Cow::Owned(guild_voice_row.voice.as_str().to_owned()));
-                    let openai_model = guild_voice_row.openai_model.unwrap_or_default();
-                    let instruction = guild_voice_row.openai_instruction.map(|i| i.as_str().to_owned());
-                    (voice, openai_model, instruction)
-                } else {
-                    (None, OpenAIModel::default(), None)
-                }
-            } else {
-                (None, OpenAIModel::default(), None)
-            };
+        let guild_voice_row = match guild_id {
+            Some(guild_id) => Some(self.guild_voice_db.get((guild_id.into(), mode)).await?),
+            None => None,
+        };
+
+        // Role and channel defaults only apply if they were set for the mode in use.
+        let mut scoped_rows = scoped_rows
+            .iter()
+            .flatten()
+            .filter(|(_, row)| row.mode == mode);
+
+        let (voice, voice_source) = if let Some(voice) = user_voice_row.voice {
+            (Cow::Owned(voice.as_str().to_owned()), VoiceSource::User)
+        } else if let Some((source, voice)) = scoped_rows
+            .clone()
+            .find_map(|(source, row)| row.voice.map(|voice| (*source, voice)))
+        {
+            (Cow::Owned(voice.as_str().to_owned()), source)
+        } else if let Some(guild_voice_row) = &guild_voice_row
+            && guild_voice_row.guild_id.is_some()
+        {
+            let voice = guild_voice_row.voice.as_str().to_owned();
+            (Cow::Owned(voice), VoiceSource::Guild)
+        } else {
+            (Cow::Borrowed(mode.default_voice()), VoiceSource::Default)
+        };
+
+        let (openai_model, instruction) = if user_voice_row.user_id.is_some() {
+            let instruction = user_voice_row.openai_instruction.map(|i| i.as_str().to_owned());
+            (user_voice_row.openai_model.unwrap_or_default(), instruction)
+        } else if let Some(guild_voice_row) = guild_voice_row
+            && guild_voice_row.guild_id.is_some()
+        {
+            let instruction = guild_voice_row.openai_instruction.map(|i| i.as_str().to_owned());
+            (guild_voice_row.openai_model.unwrap_or_default(), instruction)
+        } else {
+            (OpenAIModel::default(), None)
+        };
+
+        let speaking_rate = match user_voice_row
+            .speaking_rate
+            .or_else(|| scoped_rows.find_map(|(_, row)| row.speaking_rate))
+        {
+            Some(r) => Cow::Owned(r.to_string()),
+            None => Cow::Borrowed(
+                mode.speaking_rate_info()
+                    .map(|info| info.default)
+                    .unwrap_or("1.0"),
+            ),
+        };
+
+        Ok(VoiceSettings {
+            voice,
+            mode,
+            openai_model,
+            instruction,
+            speaking_rate,
+            voice_source,
+            mode_source,
+        })
+    }
+}
+
+/// Extra context used to look up role and channel voice defaults.
+#[derive(Clone, Copy, Default)]
+pub struct VoiceScope<'a> {
+    /// The roles of the member, ordered from highest to lowest position.
+    pub roles: &'a [RoleId],
+    pub channel_id: Option<ChannelId>,
+}
+
+/// Which level of the precedence chain a voice setting was resolved from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceSource {
+    User,
+    Role(RoleId),
+    Channel(ChannelId),
+    Guild,
+    Default,
+}
 
-        let voice = voice.unwrap_or_else(|| Cow::Borrowed(mode.default_voice()));
-        Ok((voice, mode, openai_model, instruction))
+impl std::fmt::Display for VoiceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User => f.write_str("user"),
+            Self::Role(role_id) => write!(f, "role <@&{role_id}>"),
+            Self::Channel(channel_id) => write!(f, "channel <#{channel_id}>"),
+            Self::Guild => f.write_str("server"),
+            Self::Default => f.write_str("mode default"),
+        }
     }
 }
 
+pub struct VoiceSettings {
+    pub voice: Cow<'static, str>,
+    pub mode: TTSMode,
+    pub openai_model: OpenAIModel,
+    pub instruction: Option<String>,
+    pub speaking_rate: Cow<'static, str>,
+    pub voice_source: VoiceSource,
+    pub mode_source: VoiceSource,
+}
+
 #[derive(Clone, Copy)]
 pub struct SpeakingRateInfo {
     pub min: f32,
@@ -601,6 +774,33 @@ pub enum OpenAIModel {
     Gpt4oMiniTts,
 }
 
+const OPENAI_TTS1_VOICES: &[&str] = &[
+    "alloy", "ash", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer",
+];
+
+const OPENAI_GPT4O_VOICES: &[&str] = &[
+    "alloy", "ash", "ballad", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer",
+    "verse",
+];
+
+/// What an [`OpenAIModel`] accepts in a speech request.
+#[derive(Clone, Copy)]
+pub struct OpenAIModelInfo {
+    pub supports_instructions: bool,
+    /// The most characters of input text accepted in one request.
+    pub max_input_len: usize,
+    pub voices: &'static [&'static str],
+    /// The min and max speed multiplier, `None` if the speed cannot be changed.
+    pub speed_range: Option<(f32, f32)>,
+}
+
+impl OpenAIModelInfo {
+    #[must_use]
+    pub fn supports_voice(&self, voice: &str) -> bool {
+        self.voices.contains(&voice)
+    }
+}
+
 impl OpenAIModel {
     #[must_use]
     pub const fn as_str(self) -> &'static str {
@@ -610,6 +810,24 @@ impl OpenAIModel {
             Self::Gpt4oMiniTts => "gpt-4o-mini-tts",
         }
     }
+
+    #[must_use]
+    pub const fn info(self) -> OpenAIModelInfo {
+        match self {
+            Self::Tts1 | Self::Tts1Hd => OpenAIModelInfo {
+                supports_instructions: false,
+                max_input_len: 4096,
+                voices: OPENAI_TTS1_VOICES,
+                speed_range: Some((0.25, 4.0)),
+            },
+            Self::Gpt4oMiniTts => OpenAIModelInfo {
+                supports_instructions: true,
+                max_input_len: 4096,
+                voices: OPENAI_GPT4O_VOICES,
+                speed_range: None,
+            },
+        }
+    }
 }
 
 #[derive(poise::ChoiceParameter, Clone, Copy)]
@@ -670,6 +888,132 @@ impl From<TTSModeChoice> for TTSMode {
     }
 }
 
+#[derive(IntoStaticStr, sqlx::Type, TypeSize, Debug, Default, Hash, PartialEq, Eq, Copy, Clone)]
+#[sqlx(rename_all = "lowercase")]
+#[sqlx(type_name = "audioeffect")]
+pub enum AudioEffect {
+    #[default]
+    None,
+    Echo,
+    Robot,
+    Telephone,
+    Reverb,
+}
+
+impl AudioEffect {
+    /// Effects which are too expensive to run for free.
+    #[must_use]
+    pub const fn is_premium(self) -> bool {
+        match self {
+            Self::None | Self::Echo | Self::Robot | Self::Telephone => false,
+            Self::Reverb => true,
+        }
+    }
+}
+
+into_static_display!(AudioEffect, max_length(9));
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum AudioEffectChoice {
+    #[name = "None"]
+    #[name = "none"]
+    None,
+    #[name = "Echo"]
+    #[name = "echo"]
+    Echo,
+    #[name = "Robot"]
+    #[name = "robot"]
+    Robot,
+    #[name = "Telephone"]
+    #[name = "telephone"]
+    Telephone,
+    #[name = "⭐ Reverb ⭐"]
+    #[name = "reverb"]
+    Reverb,
+}
+
+impl From<AudioEffectChoice> for AudioEffect {
+    fn from(effect: AudioEffectChoice) -> Self {
+        match effect {
+            AudioEffectChoice::None => Self::None,
+            AudioEffectChoice::Echo => Self::Echo,
+            AudioEffectChoice::Robot => Self::Robot,
+            AudioEffectChoice::Telephone => Self::Telephone,
+            AudioEffectChoice::Reverb => Self::Reverb,
+        }
+    }
+}
+
+/// What to do with TTS audio which is longer than the server's max message length.
+#[derive(IntoStaticStr, sqlx::Type, TypeSize, Debug, Default, Hash, PartialEq, Eq, Copy, Clone)]
+#[sqlx(rename_all = "lowercase")]
+#[sqlx(type_name = "lengthpolicy")]
+pub enum LengthPolicy {
+    /// Cut the audio off at the max length, fading out.
+    #[default]
+    Truncate,
+    /// Skip the message entirely.
+    Reject,
+}
+
+into_static_display!(LengthPolicy, max_length(8));
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum LengthPolicyChoice {
+    #[name = "Cut off long messages"]
+    #[name = "truncate"]
+    Truncate,
+    #[name = "Skip long messages"]
+    #[name = "reject"]
+    Reject,
+}
+
+impl From<LengthPolicyChoice> for LengthPolicy {
+    fn from(policy: LengthPolicyChoice) -> Self {
+        match policy {
+            LengthPolicyChoice::Truncate => Self::Truncate,
+            LengthPolicyChoice::Reject => Self::Reject,
+        }
+    }
+}
+
+/// Who the bot should move with when they change voice channel.
+#[derive(IntoStaticStr, sqlx::Type, TypeSize, Debug, Default, Hash, PartialEq, Eq, Copy, Clone)]
+#[sqlx(rename_all = "snake_case")]
+#[sqlx(type_name = "followmode")]
+pub enum FollowMode {
+    /// Stay in the channel the bot was summoned to.
+    #[default]
+    Off,
+    /// Follow the user who summoned the bot.
+    Summoner,
+    /// Follow the user whose message was most recently read out.
+    LastSpeaker,
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum FollowModeChoice {
+    #[name = "Stay in the same channel"]
+    #[name = "off"]
+    Off,
+    #[name = "Follow whoever summoned me"]
+    #[name = "summoner"]
+    Summoner,
+    #[name = "Follow whoever spoke last"]
+    #[name = "last_speaker"]
+    LastSpeaker,
+}
+
+impl From<FollowModeChoice> for FollowMode {
+    fn from(mode: FollowModeChoice) -> Self {
+        match mode {
+            FollowModeChoice::Off => Self::Off,
+            FollowModeChoice::Summoner => Self::Summoner,
+            FollowModeChoice::LastSpeaker => Self::LastSpeaker,
+        }
+    }
+}
+
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GoogleVoice {
@@ -140,6 +140,14 @@ pub trait SongbirdManagerExt {
         guild_id: JoinVCToken,
         channel_id: serenity::ChannelId,
     ) -> Result<Arc<tokio::sync::Mutex<songbird::Call>>, songbird::error::JoinError>;
+
+    /// Reconnects an existing call, which unlike `join_vc` is left in place on failure
+    /// so the attempt can be retried.
+    async fn reconnect_vc(
+        &self,
+        guild_id: JoinVCToken,
+        channel_id: serenity::ChannelId,
+    ) -> Result<(), songbird::error::JoinError>;
 }
 
 impl SongbirdManagerExt for songbird::Songbird {
@@ -159,4 +167,13 @@ impl SongbirdManagerExt for songbird::Songbird {
             }
         }
     }
+
+    async fn reconnect_vc(
+        &self,
+        JoinVCToken(guild_id, lock): JoinVCToken,
+        channel_id: serenity::ChannelId,
+    ) -> Result<(), songbird::error::JoinError> {
+        let _guard = lock.lock().await;
+        self.join(guild_id, channel_id).await.map(drop)
+    }
 }
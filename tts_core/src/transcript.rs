@@ -0,0 +1,239 @@
+//! Logging what the bot has read out to a transcript channel, so moderators can see
+//! messages after they have been cleaned up and had nicknames applied.
+//!
+//! Entries are buffered and posted in batches by a background task, to avoid hitting
+//! rate limits in busy voice channels.
+use std::fmt::Write as _;
+
+use dashmap::DashMap;
+use poise::serenity_prelude as serenity;
+use serenity::{
+    ChannelType, CreateAllowedMentions, CreateMessage, CreateThread, GenericChannelId, GuildId,
+    UserId,
+};
+
+use crate::{
+    common::safe_truncate,
+    structs::{Data, Result, TTSMode},
+};
+
+/// The longest a single entry can be, so a batch message always has room for it.
+const MAX_ENTRY_LEN: usize = 1800;
+/// The max length of a Discord message.
+const MAX_MESSAGE_LEN: usize = 2000;
+/// How many entries are kept for a guild while they fail to post, dropping the oldest.
+const MAX_PENDING_ENTRIES: usize = 500;
+/// How many expired messages are loaded at once when pruning.
+const PRUNE_BATCH_SIZE: i64 = 100;
+
+pub struct Entry {
+    pub author: UserId,
+    pub text: String,
+    pub voice: String,
+    pub mode: TTSMode,
+}
+
+impl Entry {
+    fn format(&self) -> String {
+        let mut text = self.text.clone();
+        safe_truncate(&mut text, MAX_ENTRY_LEN);
+
+        let Self {
+            author,
+            voice,
+            mode,
+            ..
+        } = self;
+
+        format!("<@{author}> `{mode} | {voice}`: {text}\n")
+    }
+}
+
+/// The transcript entries waiting to be posted, and the threads of active sessions.
+#[derive(Default)]
+pub struct Transcripts {
+    pending: DashMap<GuildId, Vec<Entry>>,
+    /// The thread being used for the current voice session, if threads are enabled.
+    session_threads: DashMap<GuildId, GenericChannelId>,
+}
+
+impl Transcripts {
+    /// Queues `entry` to be posted to the transcript channel of `guild_id`, if it has one.
+    pub async fn log(&self, data: &Data, guild_id: GuildId, entry: Entry) -> Result<()> {
+        let (guild_row, opt_out_row) = tokio::try_join!(
+            data.guilds_db.get(guild_id.into()),
+            data.user_opt_out_db
+                .get([entry.author.into(), guild_id.into()]),
+        )?;
+
+        if guild_row.transcript_channel.is_none() || opt_out_row.opted_out {
+            return Ok(());
+        }
+
+        self.pending.entry(guild_id).or_default().push(entry);
+        Ok(())
+    }
+
+    /// Posts all queued entries, batched into as few messages as possible.
+    pub async fn flush(&self, ctx: &serenity::Context, data: &Data) -> Result<()> {
+        let guild_ids: Vec<GuildId> = self.pending.iter().map(|entry| *entry.key()).collect();
+        for guild_id in guild_ids {
+            let Some((_, mut entries)) = self.pending.remove(&guild_id) else {
+                continue;
+            };
+
+            if let Err(err) = self.post(ctx, data, guild_id, &mut entries).await {
+                tracing::warn!("Failed to post transcript for {guild_id}: {err:?}");
+                self.requeue(guild_id, entries);
+            }
+        }
+
+        // Sessions end once the bot has left, so the next session gets a fresh thread.
+        self.session_threads
+            .retain(|guild_id, _| data.songbird.get(*guild_id).is_some());
+
+        Ok(())
+    }
+
+    /// Puts entries which failed to post back in front of the queue, to retry next flush.
+    fn requeue(&self, guild_id: GuildId, mut entries: Vec<Entry>) {
+        let mut pending = self.pending.entry(guild_id).or_default();
+        entries.append(&mut pending);
+
+        // Entries which can never be posted, such as without permissions, are dropped.
+        let excess = entries.len().saturating_sub(MAX_PENDING_ENTRIES);
+        entries.drain(..excess);
+        *pending = entries;
+    }
+
+    /// Posts `entries`, removing them as they are posted so any left over can be retried.
+    async fn post(
+        &self,
+        ctx: &serenity::Context,
+        data: &Data,
+        guild_id: GuildId,
+        entries: &mut Vec<Entry>,
+    ) -> Result<()> {
+        let guild_row = data.guilds_db.get(guild_id.into()).await?;
+        let Some(channel_id) = guild_row.transcript_channel else {
+            return Ok(());
+        };
+
+        let target = if guild_row.transcript_threads() {
+            self.session_thread(ctx, data, guild_id, channel_id).await?
+        } else {
+            channel_id.widen()
+        };
+
+        // Each batch is a message, along with how many entries are in it.
+        let mut batches: Vec<(String, usize)> = Vec::new();
+        for entry in entries.iter() {
+            let line = entry.format();
+            match batches.last_mut() {
+                Some((batch, count)) if batch.len() + line.len() <= MAX_MESSAGE_LEN => {
+                    batch.push_str(&line);
+                    *count += 1;
+                }
+                _ => batches.push((line, 1)),
+            }
+        }
+
+        for (batch, count) in batches {
+            let builder = CreateMessage::default()
+                .content(batch)
+                .allowed_mentions(CreateAllowedMentions::new());
+
+            let message = target.send_message(&ctx.http, builder).await?;
+            entries.drain(..count);
+
+            sqlx::query(
+                "INSERT INTO transcript_messages(guild_id, channel_id, message_id)
+                VALUES($1, $2, $3)",
+            )
+            .bind(guild_id.get() as i64)
+            .bind(target.get() as i64)
+            .bind(message.id.get() as i64)
+            .execute(&data.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the thread for the current voice session, creating it if needed.
+    async fn session_thread(
+        &self,
+        ctx: &serenity::Context,
+        data: &Data,
+        guild_id: GuildId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<GenericChannelId> {
+        if let Some(thread_id) = self.session_threads.get(&guild_id) {
+            return Ok(*thread_id);
+        }
+
+        let voice_channel = match data.songbird.get(guild_id) {
+            Some(call) => call.lock().await.current_channel(),
+            None => None,
+        };
+
+        let mut name = String::from("TTS transcript");
+        if let Some(voice_channel) = voice_channel
+            && let Some(guild) = ctx.cache.guild(guild_id)
+            && let Some(channel) = guild
+                .channels
+                .get(&serenity::ChannelId::new(voice_channel.get()))
+        {
+            write!(name, " for {}", channel.base.name)?;
+        }
+
+        let builder = CreateThread::new(name).kind(ChannelType::PublicThread);
+        let thread = channel_id.create_thread(&ctx.http, builder).await?;
+
+        let thread_id = thread.id.widen();
+        self.session_threads.insert(guild_id, thread_id);
+        Ok(thread_id)
+    }
+}
+
+/// Deletes transcript messages older than their guild's retention period.
+///
+/// Expired messages are loaded in batches until none are left, as each is always removed
+/// from the database even if deleting the message itself fails.
+pub async fn prune(ctx: &serenity::Context, data: &Data) -> Result<()> {
+    loop {
+        let expired: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT transcript_messages.channel_id, transcript_messages.message_id
+            FROM transcript_messages
+            JOIN guilds ON guilds.guild_id = transcript_messages.guild_id
+            WHERE guilds.transcript_retention IS NOT NULL
+            AND transcript_messages.posted_at < now() - make_interval(days => guilds.transcript_retention)
+            LIMIT $1",
+        )
+        .bind(PRUNE_BATCH_SIZE)
+        .fetch_all(&data.pool)
+        .await?;
+
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        for (channel_id, message_id) in expired {
+            let channel_id = GenericChannelId::new(channel_id as u64);
+            let message_id = serenity::MessageId::new(message_id as u64);
+
+            // The message may have been deleted already, which is fine.
+            if let Err(err) = channel_id.delete_message(&ctx.http, message_id, None).await {
+                tracing::debug!("Failed to delete transcript message {message_id}: {err:?}");
+            }
+
+            sqlx::query(
+                "DELETE FROM transcript_messages WHERE channel_id = $1 AND message_id = $2",
+            )
+            .bind(channel_id.get() as i64)
+            .bind(message_id.get() as i64)
+            .execute(&data.pool)
+            .await?;
+        }
+    }
+}
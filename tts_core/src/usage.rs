@@ -0,0 +1,316 @@
+//! Metering how many characters each guild sends to the paid TTS backends, and
+//! enforcing the daily and monthly quotas on them.
+use std::{borrow::Cow, collections::HashMap, sync::atomic::Ordering};
+
+use chrono::NaiveDate;
+use dashmap::DashMap;
+use poise::serenity_prelude::{GuildId, UserId};
+
+use crate::{
+    database::GuildRow,
+    structs::{Data, Result, TTSMode, VoiceSettings, VoiceSource},
+};
+
+/// How many characters a guild and its members can synthesize with the paid modes.
+#[derive(Clone, Copy)]
+pub struct Quotas {
+    pub daily: u32,
+    pub monthly: u32,
+    pub user_daily: u32,
+}
+
+pub const FREE_QUOTAS: Quotas = Quotas {
+    daily: 20_000,
+    monthly: 300_000,
+    user_daily: 5_000,
+};
+
+pub const PREMIUM_QUOTAS: Quotas = Quotas {
+    daily: 200_000,
+    monthly: 3_000_000,
+    user_daily: 50_000,
+};
+
+impl Quotas {
+    /// The quotas for a guild, which can be lowered but not raised by its settings.
+    #[must_use]
+    pub fn for_guild(guild_row: &GuildRow, is_premium: bool) -> Self {
+        let max = if is_premium { PREMIUM_QUOTAS } else { FREE_QUOTAS };
+        let limit = |setting: Option<u32>, max: u32| setting.map(|s| s.min(max)).unwrap_or(max);
+
+        Self {
+            daily: limit(guild_row.daily_quota, max.daily),
+            monthly: limit(guild_row.monthly_quota, max.monthly),
+            user_daily: limit(guild_row.user_daily_quota, max.user_daily),
+        }
+    }
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum QuotaKind {
+    #[name = "Server daily"]
+    Daily,
+    #[name = "Server monthly"]
+    Monthly,
+    #[name = "Per user daily"]
+    UserDaily,
+}
+
+impl QuotaKind {
+    #[must_use]
+    pub const fn column(self) -> &'static str {
+        match self {
+            Self::Daily => "daily_quota",
+            Self::Monthly => "monthly_quota",
+            Self::UserDaily => "user_daily_quota",
+        }
+    }
+
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Monthly => "monthly",
+            Self::UserDaily => "per user daily",
+        }
+    }
+
+    #[must_use]
+    pub const fn get(self, quotas: Quotas) -> u32 {
+        match self {
+            Self::Daily => quotas.daily,
+            Self::Monthly => quotas.monthly,
+            Self::UserDaily => quotas.user_daily,
+        }
+    }
+}
+
+/// Characters synthesized by the paid backends so far.
+#[derive(Clone, Copy, sqlx::FromRow)]
+pub struct Usage {
+    pub today: i64,
+    pub month: i64,
+    pub user_today: i64,
+}
+
+impl Usage {
+    #[must_use]
+    pub fn exceeds(&self, quotas: Quotas) -> bool {
+        self.today >= quotas.daily.into()
+            || self.month >= quotas.monthly.into()
+            || self.user_today >= quotas.user_daily.into()
+    }
+}
+
+/// Usage loaded from the database, kept up to date by [`record`] so the quotas can be
+/// checked without summing up a guild's usage for every message.
+#[derive(Default)]
+pub struct UsageCache(DashMap<GuildId, GuildUsage>);
+
+struct GuildUsage {
+    /// The day the usage was loaded on, as it has to be reloaded once the day is over.
+    day: NaiveDate,
+    today: i64,
+    month: i64,
+    users_today: HashMap<UserId, i64>,
+}
+
+fn today() -> NaiveDate {
+    chrono::Utc::now().date_naive()
+}
+
+/// Checks if generating audio with `mode` costs money, so should count towards the quotas.
+#[must_use]
+pub const fn is_metered(mode: TTSMode) -> bool {
+    match mode {
+        TTSMode::OpenAI | TTSMode::gCloud | TTSMode::Polly => true,
+        TTSMode::gTTS | TTSMode::eSpeak => false,
+    }
+}
+
+/// The rough price in USD of synthesizing a million characters with `mode`.
+#[must_use]
+pub const fn cost_per_million(mode: TTSMode) -> f64 {
+    match mode {
+        TTSMode::OpenAI => 15.0,
+        TTSMode::gCloud | TTSMode::Polly => 16.0,
+        TTSMode::gTTS | TTSMode::eSpeak => 0.0,
+    }
+}
+
+/// The mode used once a guild has run out of quota.
+#[must_use]
+pub fn free_mode(data: &Data) -> TTSMode {
+    if data.config.gtts_disabled.load(Ordering::Relaxed) {
+        TTSMode::eSpeak
+    } else {
+        TTSMode::gTTS
+    }
+}
+
+/// Switches `settings` over to the free mode, with that mode's default voice.
+pub fn downgrade(data: &Data, settings: &mut VoiceSettings) {
+    let mode = free_mode(data);
+
+    settings.mode = mode;
+    settings.voice = Cow::Borrowed(mode.default_voice());
+    settings.voice_source = VoiceSource::Default;
+    settings.mode_source = VoiceSource::Default;
+    settings.instruction = None;
+    settings.speaking_rate = Cow::Borrowed(
+        mode.speaking_rate_info()
+            .map(|info| info.default)
+            .unwrap_or("1.0"),
+    );
+}
+
+pub async fn fetch_usage(data: &Data, guild_id: GuildId, user_id: UserId) -> Result<Usage> {
+    if let Some(cached) = data.usage_cache.0.get(&guild_id)
+        && cached.day == today()
+        && let Some(&user_today) = cached.users_today.get(&user_id)
+    {
+        return Ok(Usage {
+            today: cached.today,
+            month: cached.month,
+            user_today,
+        });
+    }
+
+    let day = today();
+    let usage: Usage = sqlx::query_as(
+        "SELECT
+            COALESCE(SUM(characters) FILTER (WHERE day = CURRENT_DATE), 0) AS today,
+            COALESCE(SUM(characters), 0) AS month,
+            COALESCE(SUM(characters) FILTER (WHERE day = CURRENT_DATE AND user_id = $2), 0) AS user_today
+        FROM tts_usage
+        WHERE guild_id = $1 AND day >= date_trunc('month', CURRENT_DATE)",
+    )
+    .bind(guild_id.get() as i64)
+    .bind(user_id.get() as i64)
+    .fetch_one(&data.pool)
+    .await?;
+
+    let mut cached = data
+        .usage_cache
+        .0
+        .entry(guild_id)
+        .or_insert_with(|| GuildUsage {
+            day,
+            today: 0,
+            month: 0,
+            users_today: HashMap::new(),
+        });
+
+    if cached.day != day {
+        cached.day = day;
+        cached.users_today.clear();
+    }
+
+    cached.today = usage.today;
+    cached.month = usage.month;
+    cached.users_today.insert(user_id, usage.user_today);
+    Ok(usage)
+}
+
+/// The characters used by each mode in the guild this month.
+pub async fn fetch_monthly_by_mode(
+    data: &Data,
+    guild_id: GuildId,
+) -> Result<Vec<(TTSMode, i64)>> {
+    let usage = sqlx::query_as(
+        "SELECT mode, SUM(characters) FROM tts_usage
+        WHERE guild_id = $1 AND day >= date_trunc('month', CURRENT_DATE)
+        GROUP BY mode
+        ORDER BY mode",
+    )
+    .bind(guild_id.get() as i64)
+    .fetch_all(&data.pool)
+    .await?;
+
+    Ok(usage)
+}
+
+/// Checks if `guild_id`, or `user_id` within it, has run out of quota for the paid modes.
+pub async fn quota_exceeded(
+    data: &Data,
+    guild_id: GuildId,
+    user_id: UserId,
+    is_premium: bool,
+) -> Result<bool> {
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+    let quotas = Quotas::for_guild(&guild_row, is_premium);
+    Ok(fetch_usage(data, guild_id, user_id).await?.exceeds(quotas))
+}
+
+/// Switches `settings` to the free mode if they use a paid mode which `user_id` has
+/// run out of quota for in `guild_id`.
+///
+/// Returns `true` if the settings were downgraded.
+pub async fn enforce_quota(
+    data: &Data,
+    guild_id: GuildId,
+    user_id: UserId,
+    is_premium: bool,
+    settings: &mut VoiceSettings,
+) -> Result<bool> {
+    if !is_metered(settings.mode) || !quota_exceeded(data, guild_id, user_id, is_premium).await? {
+        return Ok(false);
+    }
+
+    downgrade(data, settings);
+    Ok(true)
+}
+
+/// Adds `characters` to the usage of `user_id` in `guild_id`, if `mode` is metered.
+pub async fn record(
+    data: &Data,
+    guild_id: GuildId,
+    user_id: UserId,
+    mode: TTSMode,
+    characters: usize,
+) -> Result<()> {
+    if !is_metered(mode) {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "INSERT INTO tts_usage(guild_id, user_id, mode, day, characters)
+        VALUES($1, $2, $3, CURRENT_DATE, $4)
+
+        ON CONFLICT (guild_id, user_id, mode, day)
+        DO UPDATE SET characters = tts_usage.characters + EXCLUDED.characters",
+    )
+    .bind(guild_id.get() as i64)
+    .bind(user_id.get() as i64)
+    .bind(mode)
+    .bind(i32::try_from(characters).unwrap_or(i32::MAX))
+    .execute(&data.pool)
+    .await?;
+
+    if let Some(mut cached) = data.usage_cache.0.get_mut(&guild_id)
+        && cached.day == today()
+    {
+        let characters = characters as i64;
+        cached.today += characters;
+        cached.month += characters;
+        if let Some(user_today) = cached.users_today.get_mut(&user_id) {
+            *user_today += characters;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes usage from before last month, as only the current month is enforced or shown.
+pub async fn prune(data: &Data) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM tts_usage
+        WHERE day < date_trunc('month', CURRENT_DATE) - INTERVAL '1 month'",
+    )
+    .execute(&data.pool)
+    .await?;
+
+    let today = today();
+    data.usage_cache.0.retain(|_, usage| usage.day == today);
+    Ok(())
+}
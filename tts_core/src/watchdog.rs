@@ -0,0 +1,219 @@
+//! Watching voice connections for unexpected disconnects, and reconnecting them.
+//!
+//! songbird retries some disconnects itself, but others leave the call half-connected,
+//! so the bot sits in the voice channel without being able to play anything.
+use std::time::Duration;
+
+use dashmap::DashMap;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId};
+use songbird::{
+    events::context_data::{DisconnectData, DisconnectReason},
+    model::CloseCode,
+    CoreEvent, EventContext,
+};
+
+use crate::{
+    errors,
+    structs::{Data, JoinVCToken, Result},
+    traits::SongbirdManagerExt as _,
+};
+
+/// How many times to try reconnecting before giving up on the call.
+const MAX_RECONNECT_ATTEMPTS: u8 = 3;
+/// How long to wait before the first reconnect attempt, doubled for each attempt.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(2);
+
+const RECONNECT_FAILED_NOTICE: &str = "I lost connection to the voice channel and could not reconnect, please use `/join` to bring me back.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallHealth {
+    Connected,
+    Reconnecting { attempt: u8 },
+    Failed,
+}
+
+/// The health of each voice call, as seen from its driver events.
+#[derive(Default)]
+pub struct Watchdog {
+    health: DashMap<GuildId, CallHealth>,
+}
+
+impl Watchdog {
+    #[must_use]
+    pub fn health(&self, guild_id: GuildId) -> Option<CallHealth> {
+        self.health.get(&guild_id).map(|health| *health)
+    }
+
+    fn set_health(&self, guild_id: GuildId, health: CallHealth) {
+        self.health.insert(guild_id, health);
+    }
+
+    /// Forgets the health of `guild_id`, as the bot has left the call.
+    pub fn forget(&self, guild_id: GuildId) {
+        self.health.remove(&guild_id);
+    }
+}
+
+/// Starts watching the voice call in `guild_id` for disconnects.
+///
+/// Called once per newly connected call, as reconnects keep the same call and its handlers.
+pub async fn watch(ctx: &serenity::Context, guild_id: GuildId) {
+    let data = ctx.data_ref::<Data>();
+    let Some(call) = data.songbird.get(guild_id) else {
+        return;
+    };
+
+    data.watchdog.set_health(guild_id, CallHealth::Connected);
+
+    let handler = WatchdogHandler {
+        ctx: ctx.clone(),
+        guild_id,
+    };
+
+    let mut call = call.lock().await;
+    call.add_global_event(CoreEvent::DriverReconnect.into(), handler.clone());
+    call.add_global_event(CoreEvent::DriverDisconnect.into(), handler);
+}
+
+/// Checks if a disconnect is worth reconnecting from.
+fn should_reconnect(disconnect: &DisconnectData<'_>) -> bool {
+    match disconnect.reason {
+        // The disconnect was requested, such as by leaving the call.
+        None => false,
+        // The bot was kicked from the channel, or the channel was deleted.
+        Some(DisconnectReason::WsClosed(Some(CloseCode::Disconnected))) => false,
+        Some(_) => true,
+    }
+}
+
+/// Tries to reconnect the call in `guild_id` to `channel_id`, with exponential backoff.
+async fn reconnect(
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Result<()> {
+    let data = ctx.data_ref::<Data>();
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        data.watchdog
+            .set_health(guild_id, CallHealth::Reconnecting { attempt });
+
+        tokio::time::sleep(RECONNECT_BACKOFF * 2_u32.pow(u32::from(attempt - 1))).await;
+
+        // The call was ended while waiting, so there is nothing to reconnect.
+        let Some(call) = data.songbird.get(guild_id) else {
+            data.watchdog.forget(guild_id);
+            return Ok(());
+        };
+
+        // songbird managed to reconnect by itself.
+        if call.lock().await.current_connection().is_some() {
+            data.watchdog.set_health(guild_id, CallHealth::Connected);
+            return Ok(());
+        }
+
+        let join_vc_token = JoinVCToken::acquire(data, guild_id);
+        match data.songbird.reconnect_vc(join_vc_token, channel_id).await {
+            Ok(()) => {
+                tracing::info!("Reconnected voice call in {guild_id} after {attempt} attempts");
+                data.watchdog.set_health(guild_id, CallHealth::Connected);
+                data.analytics.log("voice_reconnected".into(), false);
+                return Ok(());
+            }
+            Err(err) => {
+                tracing::warn!("Reconnect attempt {attempt} failed in {guild_id}: {err:?}");
+            }
+        }
+    }
+
+    tracing::error!(
+        "Giving up on voice call in {guild_id} after {MAX_RECONNECT_ATTEMPTS} reconnect attempts"
+    );
+
+    data.watchdog.set_health(guild_id, CallHealth::Failed);
+    data.analytics.log("voice_reconnect_failed".into(), false);
+
+    // Sent before leaving, as leaving forgets the call's health.
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+    if let Some(channel) = guild_row.channel
+        && let Err(err) = channel
+            .widen()
+            .say(&ctx.http, RECONNECT_FAILED_NOTICE)
+            .await
+    {
+        tracing::debug!("Failed to send reconnect failure notice to {guild_id}: {err:?}");
+    }
+
+    // Leave properly, instead of staying half-connected.
+    data.last_to_xsaid_tracker.remove(&guild_id);
+    data.songbird.remove(guild_id).await?;
+    data.watchdog.forget(guild_id);
+    Ok(())
+}
+
+#[derive(Clone)]
+struct WatchdogHandler {
+    ctx: serenity::Context,
+    guild_id: GuildId,
+}
+
+impl WatchdogHandler {
+    fn handle_disconnect(&self, disconnect: &DisconnectData<'_>) {
+        let data = self.ctx.data_ref::<Data>();
+        if !should_reconnect(disconnect) {
+            return;
+        }
+
+        // Failed attempts from an in-progress reconnect also fire disconnects.
+        if matches!(
+            data.watchdog.health(self.guild_id),
+            Some(CallHealth::Reconnecting { .. })
+        ) {
+            return;
+        }
+
+        let Some(channel_id) = disconnect.channel_id else {
+            return;
+        };
+
+        tracing::warn!(
+            "Voice call in {} disconnected unexpectedly: {:?}",
+            self.guild_id,
+            disconnect.reason
+        );
+
+        data.watchdog
+            .set_health(self.guild_id, CallHealth::Reconnecting { attempt: 0 });
+
+        let ctx = self.ctx.clone();
+        let guild_id = self.guild_id;
+        let channel_id = ChannelId::new(channel_id.get());
+        tokio::spawn(async move {
+            if let Err(err) = reconnect(&ctx, guild_id, channel_id).await {
+                let result = errors::handle_unexpected_default(&ctx, "Voice watchdog", err).await;
+                if let Err(err_err) = result {
+                    tracing::error!("Error in voice watchdog: {err_err:?}");
+                }
+            }
+        });
+    }
+}
+
+#[serenity::async_trait]
+impl songbird::EventHandler for WatchdogHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<songbird::Event> {
+        let data = self.ctx.data_ref::<Data>();
+        match ctx {
+            // songbird recovered the connection by itself, so any running reconnect
+            // will see the connection and stop at its next attempt.
+            EventContext::DriverReconnect(_) => {
+                tracing::debug!("Voice driver reconnected in {}", self.guild_id);
+                data.analytics.log("voice_driver_reconnect".into(), false);
+            }
+            EventContext::DriverDisconnect(disconnect) => self.handle_disconnect(disconnect),
+            _ => {}
+        }
+
+        None
+    }
+}
@@ -0,0 +1,91 @@
+//! Stopping words chosen by a guild's moderators, such as slurs, from being read out.
+use dashmap::DashMap;
+use itertools::Itertools as _;
+use poise::serenity_prelude::GuildId;
+
+use crate::structs::Result;
+
+/// The longest word which can be blocked.
+pub const MAX_WORD_LENGTH: usize = 32;
+/// How many words each guild can block.
+pub const MAX_BLOCKED_WORDS: usize = 100;
+
+/// Each guild's blocked words, compiled into a single regex which matches any of them.
+#[derive(Default)]
+pub struct WordFilter(DashMap<GuildId, Option<regex::Regex>>);
+
+impl WordFilter {
+    /// Gets the filter for `guild_id`, `None` if it has no blocked words.
+    pub async fn get(
+        &self,
+        pool: &sqlx::PgPool,
+        guild_id: GuildId,
+    ) -> Result<Option<regex::Regex>> {
+        if let Some(filter) = self.0.get(&guild_id) {
+            return Ok(filter.clone());
+        }
+
+        let filter = build(&list(pool, guild_id).await?)?;
+        self.0.insert(guild_id, filter.clone());
+        Ok(filter)
+    }
+
+    /// Blocks `word` in `guild_id`, returning `false` if it was already blocked.
+    pub async fn add(&self, pool: &sqlx::PgPool, guild_id: GuildId, word: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO blocked_words(guild_id, word) VALUES($1, $2)
+            ON CONFLICT (guild_id, word) DO NOTHING",
+        )
+        .bind(guild_id.get() as i64)
+        .bind(word.to_lowercase())
+        .execute(pool)
+        .await?;
+
+        self.0.remove(&guild_id);
+        Ok(result.rows_affected() != 0)
+    }
+
+    /// Unblocks `word` in `guild_id`, returning `false` if it wasn't blocked.
+    pub async fn remove(&self, pool: &sqlx::PgPool, guild_id: GuildId, word: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM blocked_words WHERE guild_id = $1 AND word = $2")
+            .bind(guild_id.get() as i64)
+            .bind(word.to_lowercase())
+            .execute(pool)
+            .await?;
+
+        self.0.remove(&guild_id);
+        Ok(result.rows_affected() != 0)
+    }
+}
+
+/// The words blocked in `guild_id`, in alphabetical order.
+pub async fn list(pool: &sqlx::PgPool, guild_id: GuildId) -> Result<Vec<String>> {
+    let words =
+        sqlx::query_scalar("SELECT word FROM blocked_words WHERE guild_id = $1 ORDER BY word")
+            .bind(guild_id.get() as i64)
+            .fetch_all(pool)
+            .await?;
+
+    Ok(words)
+}
+
+/// Checks if `word` can be blocked, as only whole words are matched.
+#[must_use]
+pub fn is_valid_word(word: &str) -> bool {
+    !word.is_empty()
+        && word.chars().count() <= MAX_WORD_LENGTH
+        && word.chars().all(char::is_alphanumeric)
+}
+
+fn build(words: &[String]) -> Result<Option<regex::Regex>> {
+    if words.is_empty() {
+        return Ok(None);
+    }
+
+    let pattern = words.iter().map(|word| regex::escape(word)).join("|");
+    let filter = regex::RegexBuilder::new(&format!(r"\b(?:{pattern})\b"))
+        .case_insensitive(true)
+        .build()?;
+
+    Ok(Some(filter))
+}
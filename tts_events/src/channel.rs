@@ -26,6 +26,7 @@ pub async fn handle_delete(
     if call_channel_id == Some(channel.id) {
         // Ignore errors from leaving the channel, probably already left.
         let _ = data.songbird.remove(guild_id).await;
+        data.watchdog.forget(guild_id);
         data.last_to_xsaid_tracker.remove(&guild_id);
     }
 
@@ -1,15 +1,25 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::atomic::Ordering};
 
 use aformat::ToArrayString as _;
 use poise::serenity_prelude as serenity;
 
 use tts_core::{
-    common::{clean_msg, fetch_audio, fetch_openai_audio, prepare_url},
+    audio::{self, encode::AudioFormat, AudioProcessing},
+    coalesce,
+    common::{
+        clean_msg, member_roles_by_position, synthesize, synthesize_stream, StreamedInput,
+        Synthesis, SynthesisRequest, SynthesizedAudio,
+    },
     database::{GuildRow, UserRow},
     errors,
     opt_ext::OptionTryUnwrap as _,
-    structs::{Data, IsPremium, JoinVCToken, Result, TTSMode},
+    sessions,
+    structs::{
+        AudioEffect, Data, IsPremium, JoinVCToken, LengthPolicy, Result, TTSMode, VoiceScope,
+    },
     traits::SongbirdManagerExt as _,
+    transcript,
+    usage,
 };
 
 /// Parses temporary instructions from message content.
@@ -43,6 +53,29 @@ fn parse_instruction(content: &str) -> (Option<String>, String) {
     (None, content.to_string())
 }
 
+/// The audio which was played for a message, to be remembered for replays.
+enum PlayedAudio {
+    Full(Vec<u8>),
+    Streamed(coalesce::Pending),
+}
+
+/// Tells `message`'s channel that messages are being skipped, unless it was told recently.
+async fn notify_busy(
+    ctx: &serenity::Context,
+    data: &Data,
+    message: &serenity::Message,
+    mode: TTSMode,
+) -> Result<()> {
+    if data.synthesis_limiter.should_notify(message.guild_id) {
+        let msg = format!(
+            "The {mode} voice mode is very busy right now, so some messages are being skipped."
+        );
+        message.channel_id.say(&ctx.http, msg).await?;
+    }
+
+    Ok(())
+}
+
 pub(crate) async fn process_tts_msg(
     ctx: &serenity::Context,
     message: &serenity::Message,
@@ -52,6 +85,10 @@ pub(crate) async fn process_tts_msg(
         return Ok(());
     };
 
+    if data.shutting_down.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
     let (guild_row, user_row) = tokio::try_join!(
         data.guilds_db.get(guild_id.into()),
         data.userinfo_db.get(message.author.id.into()),
@@ -62,7 +99,7 @@ pub(crate) async fn process_tts_msg(
     };
 
     let is_premium = data.is_premium_simple(&ctx.http, guild_id).await?;
-    let (voice, mode, openai_model, persistent_instruction) = {
+    let (voice, mode, openai_model, persistent_instruction, speaking_rate, downgraded) = {
         if let Some(channel_id) = to_autojoin {
             let join_vc_lock = JoinVCToken::acquire(data, guild_id);
             match data.songbird.join_vc(join_vc_lock, channel_id).await {
@@ -70,6 +107,13 @@ pub(crate) async fn process_tts_msg(
                 Err(songbird::error::JoinError::TimedOut) => return Ok(()),
                 Err(err) => return Err(err.into()),
             };
+
+            let summoner = sessions::Summoner {
+                user_id: message.author.id,
+                text_channel_id: message.channel_id,
+            };
+
+            sessions::save(data, guild_id, channel_id, Some(summoner)).await?;
         }
 
         let is_ephemeral = message
@@ -77,24 +121,40 @@ pub(crate) async fn process_tts_msg(
             .is_some_and(|f| f.contains(serenity::model::channel::MessageFlags::EPHEMERAL));
 
         let m;
-        let member_nick = match &message.member {
-            Some(member) => member.nick.as_deref(),
+        let (member_nick, member_roles) = match &message.member {
+            Some(member) => (member.nick.as_deref(), &*member.roles),
             None if message.webhook_id.is_none() && !is_ephemeral => {
                 m = guild_id.member(ctx, message.author.id).await?;
-                m.nick.as_deref()
+                (m.nick.as_deref(), &*m.roles)
             }
-            None => None,
+            None => (None, &[][..]),
         };
 
-        let (voice, mode, openai_model, persistent_instruction) = data
-            .parse_user_or_guild_with_premium(message.author.id, Some((guild_id, is_premium)))
-            .await?;
+        let member_roles = match ctx.cache.guild(guild_id) {
+            Some(guild) => member_roles_by_position(&guild, member_roles),
+            None => Vec::new(),
+        };
+
+        let scope = VoiceScope {
+            roles: &member_roles,
+            channel_id: Some(message.channel_id),
+        };
 
-        let nickname_row = data
-            .nickname_db
-            .get([guild_id.into(), message.author.id.into()])
+        let mut settings = data
+            .parse_user_or_guild_with_premium(message.author.id, Some((guild_id, is_premium)), scope)
             .await?;
 
+        let downgraded =
+            usage::enforce_quota(data, guild_id, message.author.id, is_premium, &mut settings)
+                .await?;
+        let voice = settings.voice;
+
+        let (nickname_row, blocked_words) = tokio::try_join!(
+            data.nickname_db
+                .get([guild_id.into(), message.author.id.into()]),
+            data.word_filter.get(&data.pool, guild_id),
+        )?;
+
         content = clean_msg(
             &content,
             &message.author,
@@ -108,11 +168,19 @@ pub(crate) async fn process_tts_msg(
             guild_row.repeated_chars,
             nickname_row.name.as_deref(),
             user_row.use_new_formatting(),
+            blocked_words.as_ref(),
             &data.regex_cache,
             &data.last_to_xsaid_tracker,
         );
 
-        (voice, mode, openai_model, persistent_instruction)
+        (
+            voice,
+            settings.mode,
+            settings.openai_model,
+            settings.instruction,
+            settings.speaking_rate,
+            downgraded,
+        )
     };
 
     // Final check, make sure we aren't sending an empty message or just symbols.
@@ -122,8 +190,6 @@ pub(crate) async fn process_tts_msg(
         return Ok(());
     }
 
-    let speaking_rate = data.speaking_rate(message.author.id, mode).await?;
-
     let call_lock = if let Some(call) = data.songbird.get(guild_id) {
         call
     } else {
@@ -139,77 +205,128 @@ pub(crate) async fn process_tts_msg(
         };
 
         let join_vc_token = JoinVCToken::acquire(data, guild_id);
-        match data.songbird.join_vc(join_vc_token, voice_channel_id).await {
+        let call = match data.songbird.join_vc(join_vc_token, voice_channel_id).await {
             Ok(call) => call,
             Err(songbird::error::JoinError::TimedOut) => return Ok(()),
             Err(err) => return Err(err.into()),
-        }
+        };
+
+        let summoner = sessions::Summoner {
+            user_id: message.author.id,
+            text_channel_id: message.channel_id,
+        };
+
+        sessions::save(data, guild_id, voice_channel_id, Some(summoner)).await?;
+        call
     };
 
-    // Handle different TTS modes
-    let audio_result = match mode {
-        TTSMode::OpenAI => {
-            // Use OpenAI TTS API
-            let Some(openai_api_key) = &data.config.openai_api_key else {
-                tracing::error!("OpenAI API key not configured for OpenAI TTS mode");
-                return Ok(());
+    if downgraded && data.quota_notices.get(&guild_id).is_none() {
+        data.quota_notices.insert(guild_id, ());
+
+        let msg = format!(
+            "This server has used up its character quota for the paid voice modes, so messages will be read out with the {mode} mode for now. Check `/usage` for more information."
+        );
+        message.channel_id.say(&ctx.http, msg).await?;
+    }
+
+    // Determine instruction with fallback logic: temporary -> persistent -> none
+    let instruction = temp_instruction.as_deref().or(persistent_instruction.as_deref());
+
+    let request = SynthesisRequest {
+        content: &content,
+        voice: &voice,
+        mode,
+        speaking_rate: &speaking_rate,
+        openai_model,
+        instruction,
+        max_length: &guild_row.msg_length.to_arraystring(),
+        translation_lang: guild_row.target_lang(IsPremium::from(is_premium)),
+        // Opus can be sent to Discord without being decoded and re-encoded.
+        format: AudioFormat::OggOpus,
+    };
+
+    let effect = match user_row.audio_effect {
+        effect if effect.is_premium() && !is_premium => AudioEffect::None,
+        effect => effect,
+    };
+
+    // The tts-service cuts audio to `max_length` itself, but OpenAI has no such option.
+    let max_length = (mode == TTSMode::OpenAI)
+        .then(|| std::time::Duration::from_secs(guild_row.msg_length.into()));
+
+    let processing = AudioProcessing {
+        volume: user_row.volume,
+        pitch: user_row.pitch,
+        effect,
+        normalize: guild_row.normalize_loudness(),
+        max_length,
+        length_policy: guild_row.length_policy,
+    };
+
+    // Audio can be streamed if nothing needs the full file, over-length audio can still
+    // be cut off during playback but can only be rejected once fully downloaded.
+    let can_stream = AudioProcessing {
+        max_length: None,
+        ..processing
+    }
+    .is_noop()
+        && (max_length.is_none() || guild_row.length_policy == LengthPolicy::Truncate);
+
+    let (input, played_audio) = if can_stream {
+        let StreamedInput { input, full_audio } =
+            match synthesize_stream(data, request, Some(guild_id), is_premium).await? {
+                Synthesis::Done(Some(streamed)) => streamed,
+                Synthesis::Done(None) => return Ok(()),
+                Synthesis::Busy => return notify_busy(ctx, data, message, mode).await,
             };
 
-            // Determine instruction with fallback logic: temporary -> persistent -> none
-            let instruction = temp_instruction.as_deref().or(persistent_instruction.as_deref());
+        (input, PlayedAudio::Streamed(full_audio))
+    } else {
+        let SynthesizedAudio { bytes, hint, .. } =
+            match synthesize(data, request, Some(guild_id), is_premium).await? {
+                Synthesis::Done(Some(audio)) => audio,
+                Synthesis::Done(None) => return Ok(()),
+                Synthesis::Busy => return notify_busy(ctx, data, message, mode).await,
+            };
 
-            let speaking_rate_f32 = speaking_rate.parse::<f32>().unwrap_or(1.0);
-            match fetch_openai_audio(openai_api_key, &content, &voice, speaking_rate_f32, openai_model, instruction).await? {
-                Some(bytes) => {
-                    // Create input from bytes directly
-                    Some(songbird::input::Input::from(bytes))
-                }
-                None => return Ok(()),
+        let process = move || match audio::process(&bytes, hint.as_ref(), processing) {
+            Ok(processed) => processed,
+            // The length can't be checked without decoding, so it may be too long to play.
+            Err(err) if processing.max_length.is_some() => {
+                tracing::warn!("Failed to process TTS audio with a max length, skipping: {err:?}");
+                None
             }
-        }
-        _ => {
-            // Use traditional TTS service for other modes
-            let url = prepare_url(
-                data.config.tts_service.clone(),
-                &content,
-                &voice,
-                mode,
-                &speaking_rate,
-                &guild_row.msg_length.to_arraystring(),
-                guild_row.target_lang(IsPremium::from(is_premium)),
-            );
-
-            let tts_auth_key = data.config.tts_service_auth_key.as_deref();
-            let Some(audio) = fetch_audio(&data.reqwest, url.clone(), tts_auth_key).await? else {
-                return Ok(());
-            };
+            Err(err) => {
+                tracing::warn!("Failed to process TTS audio, playing unprocessed: {err:?}");
+                Some(bytes)
+            }
+        };
 
-            let _hint = audio
-                .headers()
-                .get(reqwest::header::CONTENT_TYPE)
-                .map(|ct| {
-                    let mut hint = songbird::input::core::probe::Hint::new();
-                    hint.mime_type(ct.to_str()?);
-                    Ok::<_, anyhow::Error>(hint)
-                })
-                .transpose()?;
-
-            // For HTTP responses, get bytes and create input
-            let bytes = audio.bytes().await?.to_vec();
-            Some(songbird::input::Input::from(bytes))
-        }
-    };
+        let Some(processed) = tokio::task::spawn_blocking(process).await? else {
+            tracing::debug!("Skipping TTS message longer than the max length in {guild_id}");
+            return Ok(());
+        };
 
-    let Some(audio_reader) = audio_result else {
-        return Ok(());
+        (
+            songbird::input::Input::from(processed.clone()),
+            PlayedAudio::Full(processed),
+        )
     };
 
+    let track = songbird::tracks::Track::new(input)
+        .volume(f32::from(guild_row.master_volume) / 100.0);
+
     let track_handle = {
         let mut call = call_lock.lock().await;
-        call.enqueue_input(songbird::input::Input::from(audio_reader))
-            .await
+        call.enqueue(track).await
     };
 
+    usage::record(data, guild_id, message.author.id, mode, content.chars().count()).await?;
+
+    if can_stream && let Some(max_length) = max_length {
+        audio::stream::stop_after(&track_handle, max_length)?;
+    }
+
     data.analytics.log(
         Cow::Borrowed(match mode {
             TTSMode::gTTS => "gTTS_tts",
@@ -221,6 +338,31 @@ pub(crate) async fn process_tts_msg(
         false,
     );
 
+    let entry = transcript::Entry {
+        author: message.author.id,
+        text: content.clone(),
+        voice: voice.to_string(),
+        mode,
+    };
+
+    data.transcripts.log(data, guild_id, entry).await?;
+    data.voice_timers.record_activity(guild_id);
+
+    let item = data.history.push(
+        guild_id,
+        message.author.id,
+        content.clone(),
+        voice.to_string(),
+        mode,
+    );
+
+    match played_audio {
+        PlayedAudio::Full(bytes) => {
+            _ = item.audio.set(bytes);
+        }
+        PlayedAudio::Streamed(full_audio) => item.set_audio_when_streamed(full_audio),
+    }
+
     let guild_name = ctx.cache.guild(guild_id).try_unwrap()?.name.to_string();
     let (blank_name, blank_value, blank_inline) = errors::blank_field();
 
@@ -3,7 +3,7 @@ use std::borrow::Cow;
 use poise::serenity_prelude as serenity;
 
 use tts_core::{
-    errors,
+    errors, history,
     structs::{Data, Result},
 };
 
@@ -18,5 +18,9 @@ pub async fn interaction_create(
     ctx: &serenity::Context,
     interaction: &serenity::Interaction,
 ) -> Result<()> {
+    if let serenity::Interaction::Component(interaction) = interaction {
+        history::handle_replay_button(ctx, interaction).await?;
+    }
+
     errors::interaction_create(ctx, interaction).await
 }
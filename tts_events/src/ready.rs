@@ -7,6 +7,7 @@ use poise::serenity_prelude as serenity;
 
 use tts_core::{
     constants::FREE_NEUTRAL_COLOUR,
+    errors, sessions,
     structs::{Data, Result},
 };
 use tts_tasks::Looper;
@@ -110,6 +111,10 @@ fn finalize_startup(ctx: &serenity::Context, data: &Data) {
         tokio::spawn(web_updater.start());
     }
 
+    tokio::spawn(tts_tasks::transcripts::Flusher(ctx.clone()).start());
+    tokio::spawn(tts_tasks::transcripts::Pruner(ctx.clone()).start());
+    tokio::spawn(tts_tasks::usage::Pruner(ctx.clone()).start());
+
     // Tell glibc to let go of the memory it's holding onto.
     // We are very unlikely to reach the peak of memory allocation that was just hit.
     clear_allocator_cache();
@@ -139,5 +144,17 @@ pub async fn handle(ctx: &serenity::Context, data_about_bot: &serenity::Ready) -
         tracing::info!("Shard {} is now ready", ctx.shard_id);
     }
 
+    // The guilds are sent after Ready, so the sessions are restored once they are cached.
+    let guild_ids: Vec<_> = data_about_bot.guilds.iter().map(|guild| guild.id).collect();
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        if let Err(err) = sessions::restore(&ctx, &guild_ids).await {
+            let result = errors::handle_unexpected_default(&ctx, "RestoreSessions", err).await;
+            if let Err(err_err) = result {
+                tracing::error!("Error in restore sessions handler: {err_err:?}");
+            }
+        }
+    });
+
     Ok(())
 }
@@ -1,8 +1,18 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
 use poise::serenity_prelude as serenity;
+use songbird::error::JoinError;
 
 use tts_core::{
+    announcements::{self, Announcement},
+    autojoin, captions,
+    constants::REQUIRED_VC_PERMISSIONS,
+    inactivity::{self, LeaveReason},
     opt_ext::OptionTryUnwrap,
-    structs::{Data, Result},
+    sessions,
+    structs::{Data, FollowMode, JoinVCToken, Result},
+    traits::SongbirdManagerExt as _,
+    watchdog,
 };
 
 pub async fn handle(
@@ -10,34 +20,353 @@ pub async fn handle(
     old: Option<&serenity::VoiceState>,
     new: &serenity::VoiceState,
 ) -> Result<()> {
-    // User left vc
-    let Some(old) = old else { return Ok(()) };
-
     let data = ctx.data_ref::<Data>();
+    let bot_id = ctx.cache.current_user().id;
+    if new.user_id == bot_id {
+        handle_bot_update(ctx, data, old.and_then(|old| old.channel_id), new).await?;
+    } else if let Some(channel_id) = new.channel_id
+        && old.is_none_or(|old| old.channel_id != Some(channel_id))
+    {
+        join_on_enter(ctx, data, bot_id, channel_id, new).await?;
+    }
 
     // Bot is in vc on server
     let guild_id = new.guild_id.try_unwrap()?;
-    if data.songbird.get(guild_id).is_none() {
+    let in_call = data.songbird.get(guild_id).is_some();
+
+    // If the bot has moved with the user, the old channel being empty doesn't matter,
+    // and them leaving it shouldn't be announced as the bot has left it too.
+    if new.user_id != bot_id
+        && in_call
+        && let Some(old) = old
+        && follow_user(ctx, data, bot_id, guild_id, old, new).await?
+    {
         return Ok(());
     }
 
-    // Check if the bot is leaving
-    let bot_id = ctx.cache.current_user().id;
-    let leave_vc = match &new.member {
+    if new.user_id != bot_id {
+        announce_change(ctx, data, old.and_then(|old| old.channel_id), new).await?;
+    }
+
+    // User left vc
+    let Some(old) = old else { return Ok(()) };
+    if !in_call {
+        return Ok(());
+    }
+
+    match &new.member {
         // songbird does not clean up state on VC disconnections, so we have to do it here
-        Some(member) if member.user.id == bot_id => true,
-        Some(_) => check_is_lonely(ctx, bot_id, guild_id, old)?,
-        None => false,
+        Some(member) if member.user.id == bot_id => {
+            if new.channel_id.is_none() {
+                data.last_to_xsaid_tracker.remove(&guild_id);
+                data.songbird.remove(guild_id).await?;
+                data.watchdog.forget(guild_id);
+            }
+        }
+        Some(_) if check_is_lonely(ctx, bot_id, guild_id, old)? => {
+            let guild_row = data.guilds_db.get(guild_id.into()).await?;
+            match guild_row.lonely_leave_delay {
+                Some(delay) if delay != 0 => {
+                    let delay = Duration::from_secs(u64::from(delay));
+                    data.voice_timers.start_lonely(ctx, guild_id, delay);
+                }
+                _ => inactivity::leave(ctx, guild_id, LeaveReason::Lonely).await?,
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Keeps the saved voice session and leave timers in sync with the bot's voice channel.
+async fn handle_bot_update(
+    ctx: &serenity::Context,
+    data: &Data,
+    old_channel_id: Option<serenity::ChannelId>,
+    new: &serenity::VoiceState,
+) -> Result<()> {
+    let guild_id = new.guild_id.try_unwrap()?;
+    let Some(channel_id) = new.channel_id else {
+        data.voice_timers.stop(guild_id);
+
+        // The call is kept while the watchdog is reconnecting it.
+        if data.songbird.get(guild_id).is_none() {
+            data.watchdog.forget(guild_id);
+        }
+
+        // The bot leaves every call when shutting down, but should rejoin them afterwards.
+        if data.shutting_down.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        return sessions::remove(data, guild_id).await;
+    };
+
+    if old_channel_id == Some(channel_id) {
+        return Ok(());
+    }
+
+    sessions::save(data, guild_id, channel_id, None).await?;
+    if new.suppress() {
+        unsuppress_in_stage(ctx, data, guild_id, channel_id).await?;
+    }
+
+    // Moves keep the same call, so only newly connected calls need captions started.
+    if old_channel_id.is_none() {
+        data.voice_timers.start_idle(ctx, guild_id);
+        watchdog::watch(ctx, guild_id).await;
+        captions::start(ctx, guild_id, channel_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Lets the bot be heard after joining a stage, where it starts off in the audience.
+///
+/// Stage moderators can move themselves to the speakers, otherwise the bot requests to
+/// speak, and if it can do neither, the summoning channel is told why it cannot be heard.
+async fn unsuppress_in_stage(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+) -> Result<()> {
+    let bot_perms = {
+        let guild = ctx.cache.guild(guild_id).try_unwrap()?;
+        let Some(channel) = guild.channels.get(&channel_id) else {
+            return Ok(());
+        };
+
+        if channel.base.kind != serenity::ChannelType::Stage {
+            return Ok(());
+        }
+
+        let bot_member = guild
+            .members
+            .get(&ctx.cache.current_user().id)
+            .try_unwrap()?;
+        guild.user_permissions_in(channel, bot_member)
+    };
+
+    let builder = serenity::EditVoiceState::new();
+    if bot_perms.mute_members() {
+        builder
+            .suppress(false)
+            .execute(&ctx.http, (guild_id, channel_id, None))
+            .await?;
+
+        data.analytics.log("stage_unsuppress".into(), false);
+        return Ok(());
+    }
+
+    let notice = if bot_perms.request_to_speak() {
+        builder
+            .request_to_speak(true)
+            .execute(&ctx.http, (guild_id, channel_id, None))
+            .await?;
+
+        data.analytics.log("stage_request_to_speak".into(), false);
+        "I have requested to speak in the stage, a stage moderator needs to accept it before I can be heard."
+    } else {
+        "I cannot be heard in this stage, please make me a stage moderator or give me the Request to Speak permission."
+    };
+
+    // The summoning channel may not be saved yet if the bot has only just joined.
+    let text_channel = match sessions::text_channel(data, guild_id).await? {
+        Some(text_channel) => Some(text_channel),
+        None => {
+            let guild_row = data.guilds_db.get(guild_id.into()).await?;
+            guild_row.channel.map(serenity::ChannelId::widen)
+        }
+    };
+
+    if let Some(text_channel) = text_channel {
+        text_channel.say(&ctx.http, notice).await?;
+    }
+
+    Ok(())
+}
+
+/// Reads out members entering or leaving the bot's voice channel.
+async fn announce_change(
+    ctx: &serenity::Context,
+    data: &Data,
+    old_channel_id: Option<serenity::ChannelId>,
+    new: &serenity::VoiceState,
+) -> Result<()> {
+    let Some(member) = &new.member else {
+        return Ok(());
+    };
+
+    if member.user.bot() || old_channel_id == new.channel_id {
+        return Ok(());
+    }
+
+    let Some(call) = data.songbird.get(member.guild_id) else {
+        return Ok(());
     };
 
-    if leave_vc {
-        data.last_to_xsaid_tracker.remove(&guild_id);
-        data.songbird.remove(guild_id).await?;
+    let Some(bot_channel_id) = call.lock().await.current_channel() else {
+        return Ok(());
+    };
+
+    let bot_channel_id = serenity::ChannelId::new(bot_channel_id.get());
+    let announcement = if new.channel_id == Some(bot_channel_id) {
+        if old_channel_id.is_none() {
+            Announcement::Joined
+        } else {
+            Announcement::MovedIn
+        }
+    } else if old_channel_id == Some(bot_channel_id) {
+        // Nobody is left to hear it, and the bot is about to leave as well.
+        if !sessions::has_listeners(ctx, member.guild_id, bot_channel_id) {
+            return Ok(());
+        }
+
+        Announcement::Left
+    } else {
+        return Ok(());
+    };
+
+    announcements::announce(ctx, member, bot_channel_id, announcement).await
+}
+
+/// Joins `channel_id` when someone enters it, if the guild has enabled joining on enter.
+async fn join_on_enter(
+    ctx: &serenity::Context,
+    data: &Data,
+    bot_id: serenity::UserId,
+    channel_id: serenity::ChannelId,
+    new: &serenity::VoiceState,
+) -> Result<()> {
+    // Calls joined now would not be saved to rejoin after restarting.
+    if data.shutting_down.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let guild_id = new.guild_id.try_unwrap()?;
+    if new.member.as_ref().is_none_or(|member| member.user.bot())
+        || data.songbird.get(guild_id).is_some()
+        || data.join_on_enter_cooldowns.get(&guild_id).is_some()
+    {
+        return Ok(());
     }
 
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+    if !guild_row.join_on_enter() {
+        return Ok(());
+    }
+
+    let (user_row, opt_out_row, can_join) = tokio::try_join!(
+        data.userinfo_db.get(new.user_id.into()),
+        data.user_opt_out_db
+            .get([new.user_id.into(), guild_id.into()]),
+        autojoin::can_join(data, guild_id, channel_id),
+    )?;
+
+    // Only join for members who would actually have their messages read out.
+    if user_row.bot_banned() || opt_out_row.opted_out || !can_join {
+        return Ok(());
+    }
+
+    let channel_perms = {
+        let guild = ctx.cache.guild(guild_id).try_unwrap()?;
+        let bot_member = guild.members.get(&bot_id).try_unwrap()?;
+        let Some(channel) = guild.channels.get(&channel_id) else {
+            return Ok(());
+        };
+
+        guild.user_permissions_in(channel, bot_member)
+    };
+
+    if !channel_perms.contains(REQUIRED_VC_PERMISSIONS) {
+        return Ok(());
+    }
+
+    data.join_on_enter_cooldowns.insert(guild_id, ());
+
+    let join_vc_token = JoinVCToken::acquire(data, guild_id);
+    match data.songbird.join_vc(join_vc_token, channel_id).await {
+        Ok(_) => {}
+        Err(JoinError::TimedOut) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    }
+
+    let summoner = sessions::Summoner {
+        user_id: new.user_id,
+        text_channel_id: guild_row.channel.unwrap_or(channel_id).widen(),
+    };
+
+    sessions::save(data, guild_id, channel_id, Some(summoner)).await?;
+    data.analytics.log("join_on_enter".into(), false);
     Ok(())
 }
 
+/// If the user the bot follows has moved channel, moves the bot with them.
+///
+/// Returns if the bot has moved.
+async fn follow_user(
+    ctx: &serenity::Context,
+    data: &Data,
+    bot_id: serenity::UserId,
+    guild_id: serenity::GuildId,
+    old: &serenity::VoiceState,
+    new: &serenity::VoiceState,
+) -> Result<bool> {
+    let (Some(old_channel_id), Some(new_channel_id)) = (old.channel_id, new.channel_id) else {
+        return Ok(false);
+    };
+
+    if old_channel_id == new_channel_id {
+        return Ok(false);
+    }
+
+    let guild_row = data.guilds_db.get(guild_id.into()).await?;
+    let followed_user = match guild_row.follow_mode {
+        FollowMode::Off => return Ok(false),
+        FollowMode::Summoner => sessions::summoner(data, guild_id).await?,
+        FollowMode::LastSpeaker => data.history.latest(guild_id).map(|item| item.author),
+    };
+
+    if followed_user != Some(new.user_id) {
+        return Ok(false);
+    }
+
+    let Some(call) = data.songbird.get(guild_id) else {
+        return Ok(false);
+    };
+
+    let bot_channel_id = call.lock().await.current_channel();
+    if bot_channel_id.is_none_or(|bot_channel_id| bot_channel_id.get() != old_channel_id.get()) {
+        return Ok(false);
+    }
+
+    let new_channel_perms = {
+        let guild = ctx.cache.guild(guild_id).try_unwrap()?;
+        let bot_member = guild.members.get(&bot_id).try_unwrap()?;
+        let Some(new_channel) = guild.channels.get(&new_channel_id) else {
+            return Ok(false);
+        };
+
+        guild.user_permissions_in(new_channel, bot_member)
+    };
+
+    if !new_channel_perms.contains(REQUIRED_VC_PERMISSIONS) {
+        return Ok(false);
+    }
+
+    let join_vc_token = JoinVCToken::acquire(data, guild_id);
+    match data.songbird.join_vc(join_vc_token, new_channel_id).await {
+        Ok(_) => {}
+        Err(JoinError::TimedOut) => return Ok(false),
+        Err(err) => return Err(err.into()),
+    }
+
+    data.analytics.log("follow_move".into(), false);
+    Ok(true)
+}
+
 /// If (on leave) the bot should also leave as it is alone
 fn check_is_lonely(
     ctx: &serenity::Context,
@@ -188,6 +188,37 @@ async fn run_(config: &mut toml::Table, transaction: &mut Transaction<'_>) -> Re
             WHEN OTHERS THEN null;
         END $$;
 
+        DO $$ BEGIN
+            CREATE type AudioEffect AS ENUM (
+                'none',
+                'echo',
+                'robot',
+                'telephone',
+                'reverb'
+            );
+        EXCEPTION
+            WHEN OTHERS THEN null;
+        END $$;
+
+        DO $$ BEGIN
+            CREATE type LengthPolicy AS ENUM (
+                'truncate',
+                'reject'
+            );
+        EXCEPTION
+            WHEN OTHERS THEN null;
+        END $$;
+
+        DO $$ BEGIN
+            CREATE type FollowMode AS ENUM (
+                'off',
+                'summoner',
+                'last_speaker'
+            );
+        EXCEPTION
+            WHEN OTHERS THEN null;
+        END $$;
+
         DO $$ BEGIN
             CREATE type OpenAIModel AS ENUM (
                 'tts-1',
@@ -226,7 +257,11 @@ async fn run_(config: &mut toml::Table, transaction: &mut Transaction<'_>) -> Re
             ADD COLUMN IF NOT EXISTS voice_mode          TTSMode,
             ADD COLUMN IF NOT EXISTS premium_voice_mode  TTSMode,
             ADD COLUMN IF NOT EXISTS bot_banned          bool     DEFAULT False,
-            ADD COLUMN IF NOT EXISTS use_new_formatting  bool     DEFAULT False;
+            ADD COLUMN IF NOT EXISTS use_new_formatting  bool     DEFAULT False,
+            ADD COLUMN IF NOT EXISTS volume              smallint DEFAULT 100,
+            ADD COLUMN IF NOT EXISTS pitch               smallint DEFAULT 0,
+            ADD COLUMN IF NOT EXISTS audio_effect        AudioEffect DEFAULT 'none',
+            ADD COLUMN IF NOT EXISTS captions_opted_in   bool     DEFAULT False;
         ALTER TABLE guilds
             ADD COLUMN IF NOT EXISTS audience_ignore  bool       DEFAULT True,
             ADD COLUMN IF NOT EXISTS voice_mode       TTSMode    DEFAULT 'gtts',
@@ -237,7 +272,28 @@ async fn run_(config: &mut toml::Table, transaction: &mut Transaction<'_>) -> Re
             ADD COLUMN IF NOT EXISTS required_role    bigint,
             ADD COLUMN IF NOT EXISTS required_prefix  varchar(6),
             ADD COLUMN IF NOT EXISTS text_in_voice    bool       DEFAULT True,
-            ADD COLUMN IF NOT EXISTS skip_emoji       bool       DEFAULT False;
+            ADD COLUMN IF NOT EXISTS skip_emoji       bool       DEFAULT False,
+            ADD COLUMN IF NOT EXISTS normalize_loudness bool     DEFAULT False,
+            ADD COLUMN IF NOT EXISTS master_volume    smallint   DEFAULT 100,
+            ADD COLUMN IF NOT EXISTS tts_file_length  smallint   DEFAULT 60,
+            ADD COLUMN IF NOT EXISTS audiobook_quota  integer    DEFAULT 50000,
+            ADD COLUMN IF NOT EXISTS length_policy    LengthPolicy DEFAULT 'truncate',
+            ADD COLUMN IF NOT EXISTS daily_quota      integer,
+            ADD COLUMN IF NOT EXISTS monthly_quota    integer,
+            ADD COLUMN IF NOT EXISTS user_daily_quota integer,
+            ADD COLUMN IF NOT EXISTS captions_channel bigint,
+            ADD COLUMN IF NOT EXISTS transcript_channel   bigint,
+            ADD COLUMN IF NOT EXISTS transcript_threads   bool     DEFAULT False,
+            ADD COLUMN IF NOT EXISTS transcript_retention smallint,
+            ADD COLUMN IF NOT EXISTS idle_timeout     smallint,
+            ADD COLUMN IF NOT EXISTS lonely_leave_delay smallint,
+            ADD COLUMN IF NOT EXISTS follow_mode      FollowMode DEFAULT 'off',
+            ADD COLUMN IF NOT EXISTS join_on_enter    bool       DEFAULT False,
+            ADD COLUMN IF NOT EXISTS announce_joins   bool       DEFAULT False,
+            ADD COLUMN IF NOT EXISTS announce_leaves  bool       DEFAULT False,
+            ADD COLUMN IF NOT EXISTS greetings        bool       DEFAULT False;
+        ALTER TABLE nicknames
+            ADD COLUMN IF NOT EXISTS greeting varchar(100);
         ALTER TABLE user_voice
             ADD COLUMN IF NOT EXISTS speaking_rate real,
             ADD COLUMN IF NOT EXISTS openai_model OpenAIModel DEFAULT 'tts-1-hd',
@@ -246,6 +302,58 @@ async fn run_(config: &mut toml::Table, transaction: &mut Transaction<'_>) -> Re
             ADD COLUMN IF NOT EXISTS openai_model OpenAIModel DEFAULT 'tts-1-hd',
             ADD COLUMN IF NOT EXISTS openai_instruction varchar(500);
 
+        CREATE TABLE IF NOT EXISTS audiobook_usage (
+            guild_id   bigint,
+            day        date     DEFAULT CURRENT_DATE,
+            characters integer  DEFAULT 0,
+
+            PRIMARY KEY (guild_id, day)
+        );
+
+        CREATE TABLE IF NOT EXISTS tts_usage (
+            guild_id   bigint,
+            user_id    bigint,
+            mode       TTSMode,
+            day        date     DEFAULT CURRENT_DATE,
+            characters integer  DEFAULT 0,
+
+            PRIMARY KEY (guild_id, user_id, mode, day)
+        );
+
+        CREATE TABLE IF NOT EXISTS voice_sessions (
+            guild_id        bigint  PRIMARY KEY,
+            channel_id      bigint  NOT NULL,
+            text_channel_id bigint,
+            started_at      timestamp DEFAULT now()
+        );
+        ALTER TABLE voice_sessions
+            ADD COLUMN IF NOT EXISTS summoner_id bigint;
+
+        CREATE TABLE IF NOT EXISTS join_on_enter_channels (
+            guild_id   bigint,
+            channel_id bigint,
+            allowed    bool    NOT NULL,
+
+            PRIMARY KEY (guild_id, channel_id),
+
+            FOREIGN KEY       (guild_id)
+            REFERENCES guilds (guild_id)
+            ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS transcript_messages (
+            guild_id   bigint,
+            channel_id bigint,
+            message_id bigint,
+            posted_at  timestamp DEFAULT now(),
+
+            PRIMARY KEY (channel_id, message_id),
+
+            FOREIGN KEY       (guild_id)
+            REFERENCES guilds (guild_id)
+            ON DELETE CASCADE
+        );
+
         CREATE TABLE IF NOT EXISTS user_opt_out (
             user_id   bigint,
             guild_id  bigint,
@@ -262,6 +370,45 @@ async fn run_(config: &mut toml::Table, transaction: &mut Transaction<'_>) -> Re
             ON DELETE CASCADE
         );
 
+        CREATE TABLE IF NOT EXISTS blocked_words (
+            guild_id  bigint,
+            word      varchar(32),
+
+            PRIMARY KEY (guild_id, word),
+
+            FOREIGN KEY       (guild_id)
+            REFERENCES guilds (guild_id)
+            ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS role_voice (
+            guild_id      bigint,
+            role_id       bigint,
+            mode          TTSMode  NOT NULL,
+            voice         text,
+            speaking_rate real,
+
+            PRIMARY KEY (guild_id, role_id),
+
+            FOREIGN KEY       (guild_id)
+            REFERENCES guilds (guild_id)
+            ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS channel_voice (
+            guild_id      bigint,
+            channel_id    bigint,
+            mode          TTSMode  NOT NULL,
+            voice         text,
+            speaking_rate real,
+
+            PRIMARY KEY (guild_id, channel_id),
+
+            FOREIGN KEY       (guild_id)
+            REFERENCES guilds (guild_id)
+            ON DELETE CASCADE
+        );
+
         -- The old table had a pkey on traceback, now we hash and pkey on that
         ALTER TABLE errors
             ADD COLUMN IF NOT EXISTS traceback_hash bytea;
@@ -276,6 +423,8 @@ async fn run_(config: &mut toml::Table, transaction: &mut Transaction<'_>) -> Re
         INSERT INTO user_voice  (user_id, mode)         VALUES(0, 'openai')     ON CONFLICT (user_id, mode)  DO NOTHING;
         INSERT INTO guild_voice (guild_id, mode, voice) VALUES(0, 'openai', 'alloy') ON CONFLICT (guild_id, mode) DO NOTHING;
         INSERT INTO user_opt_out (user_id, guild_id, opted_out) VALUES(0, 0, false) ON CONFLICT (user_id, guild_id) DO NOTHING;
+        INSERT INTO role_voice    (guild_id, role_id, mode)    VALUES(0, 0, 'openai') ON CONFLICT (guild_id, role_id)    DO NOTHING;
+        INSERT INTO channel_voice (guild_id, channel_id, mode) VALUES(0, 0, 'openai') ON CONFLICT (guild_id, channel_id) DO NOTHING;
     ").await?;
 
     migrate_single_to_modes(transaction, "userinfo", "user_voice", "voice", "user_id").await?;
@@ -4,6 +4,8 @@
 mod analytics;
 pub mod bot_list_updater;
 pub mod logging;
+pub mod transcripts;
+pub mod usage;
 pub mod web_updater;
 
 pub trait Looper {
@@ -39,7 +39,7 @@ impl WebhookLogger {
         http: Arc<Http>,
         normal_logs: Option<Webhook>,
         error_logs: Option<Webhook>,
-    ) {
+    ) -> Arc<Self> {
         let logger = ArcWrapper(Arc::new(Self {
             http,
             normal_logs,
@@ -53,7 +53,13 @@ impl WebhookLogger {
             .with(logger.clone())
             .init();
 
-        tokio::spawn(logger.0.start());
+        tokio::spawn(logger.0.clone().start());
+        logger.0
+    }
+
+    /// Sends all pending logs immediately, such as before shutting down.
+    pub async fn flush(self: &Arc<Self>) {
+        let Ok(()) = self.loop_func().await;
     }
 }
 
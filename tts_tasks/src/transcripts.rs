@@ -0,0 +1,33 @@
+use serenity::all as serenity;
+
+use tts_core::{
+    structs::{Data, Result},
+    transcript,
+};
+
+/// Posts the queued transcript entries in batches.
+pub struct Flusher(pub serenity::Context);
+
+impl crate::Looper for Flusher {
+    const NAME: &'static str = "Transcript Flusher";
+    const MILLIS: u64 = 5000;
+
+    type Error = anyhow::Error;
+    async fn loop_func(&self) -> Result<()> {
+        let data = self.0.data_ref::<Data>();
+        data.transcripts.flush(&self.0, data).await
+    }
+}
+
+/// Deletes transcript messages past their guild's retention period.
+pub struct Pruner(pub serenity::Context);
+
+impl crate::Looper for Pruner {
+    const NAME: &'static str = "Transcript Pruner";
+    const MILLIS: u64 = 1000 * 60 * 10;
+
+    type Error = anyhow::Error;
+    async fn loop_func(&self) -> Result<()> {
+        transcript::prune(&self.0, self.0.data_ref::<Data>()).await
+    }
+}
@@ -0,0 +1,19 @@
+use serenity::all as serenity;
+
+use tts_core::{
+    structs::{Data, Result},
+    usage,
+};
+
+/// Deletes usage from months which are no longer enforced or shown.
+pub struct Pruner(pub serenity::Context);
+
+impl crate::Looper for Pruner {
+    const NAME: &'static str = "Usage Pruner";
+    const MILLIS: u64 = 1000 * 60 * 60 * 24;
+
+    type Error = anyhow::Error;
+    async fn loop_func(&self) -> Result<()> {
+        usage::prune(self.0.data_ref::<Data>()).await
+    }
+}